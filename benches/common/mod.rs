@@ -0,0 +1,46 @@
+//! Timing shared by the benchmarks. They run with `harness = false` and
+//! no dependencies, so `cargo bench` works offline and on stable.
+
+use std::time::{Duration, Instant};
+
+/// How long each case runs for
+const DURATION: Duration = Duration::from_secs(2);
+
+pub struct Measurement {
+    pub calls: u64,
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /// Rate of something that happened `count` times while measuring
+    pub fn per_second(&self, count: u64) -> f64 {
+        count as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Calls `f` in batches of `batch`, checking the clock in between, until
+/// the case has run for long enough
+pub fn measure<F: FnMut()>(batch: u64, mut f: F) -> Measurement {
+    let start = Instant::now();
+    let mut calls = 0;
+    while start.elapsed() < DURATION {
+        for _ in 0..batch {
+            f();
+        }
+        calls += batch;
+    }
+    Measurement {
+        calls,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// Prints one result line, with the speed relative to the real console
+/// when that means something for the case
+pub fn report(name: &str, rate: f64, unit: &str, real_time: Option<f64>) {
+    let relative = match real_time {
+        Some(ratio) => format!("{:>8.1}x real time", ratio),
+        None => String::new(),
+    };
+    println!("{:<36} {:>10.2} {:<16}{}", name, rate, unit, relative);
+}
@@ -0,0 +1,70 @@
+//! Instructions per second through `CPU::step`
+//!
+//! `cargo bench --bench cpu`
+
+mod common;
+
+use common::{measure, report};
+use nes::cpu::assembler::assemble;
+use nes::cpu::variant::Variant;
+use nes::cpu::CPU;
+use nes::test_rom::CPU_HZ;
+use std::hint::black_box;
+
+/// Instructions between checks of the clock
+const BATCH: u64 = 100_000;
+
+/// About the smallest loop there is, so decoding and dispatch dominate
+const TIGHT_LOOP: &str = "
+        .org $8000
+loop:   DEX
+        BNE loop
+        JMP loop
+";
+
+/// Adds up a page of memory forever, touching most addressing modes
+const ADDRESSING_MIX: &str = "
+        .org $8000
+start:  LDX #$00
+        LDY #$00
+loop:   LDA $0200,X
+        CLC
+        ADC $10
+        STA $10
+        LDA ($20),Y
+        EOR #$FF
+        STA $0300,Y
+        INC $11
+        INY
+        INX
+        BNE loop
+        JMP start
+";
+
+fn bench(name: &str, source: &str, variant: Variant) {
+    let program = assemble(source).expect("the benchmark assembles").bytes();
+    let mut cpu = CPU::new();
+    cpu.variant = variant;
+    cpu.load(program);
+    cpu.power_on();
+    cpu.memory.write_u16(0x20, 0x0200);
+
+    let start_cycles = cpu.cycles;
+    let measurement = measure(BATCH, || {
+        black_box(cpu.step());
+    });
+    let cycles_per_second = measurement.per_second(cpu.cycles - start_cycles);
+    report(
+        &format!("{} ({:?})", name, variant),
+        measurement.per_second(measurement.calls) / 1e6,
+        "M instructions/s",
+        Some(cycles_per_second / CPU_HZ as f64),
+    );
+}
+
+fn main() {
+    bench("tight loop", TIGHT_LOOP, Variant::Ricoh2A03);
+    for variant in [Variant::Ricoh2A03, Variant::Wdc65C02] {
+        bench("addressing modes", ADDRESSING_MIX, variant);
+    }
+}
@@ -0,0 +1,142 @@
+//! The CPU's share of frames of a synthetic NROM game, and save states
+//!
+//! `cargo bench --bench system`
+
+mod common;
+
+use common::{measure, report};
+use nes::cartridge::Rom;
+use nes::cpu::assembler::assemble;
+use nes::cpu::CPU;
+use std::hint::black_box;
+
+/// A game's frame loop: the main loop waits for the NMI and then moves 64
+/// sprites, and the NMI handler starts OAM DMA and sets the scroll. It
+/// turns rendering on, but there is no PPU yet, so the frames only time
+/// the CPU and can't be compared with the real console.
+const GAME: &str = "
+PPUCTRL = $2000
+PPUMASK = $2001
+PPUSCROLL = $2005
+OAMDMA = $4014
+frame = $00
+sprites = $0200
+
+        .org $C000
+reset:  SEI
+        CLD
+        LDX #$FF
+        TXS
+        LDA #%10000000
+        STA PPUCTRL
+        LDA #%00011110
+        STA PPUMASK
+main:   LDA frame
+wait:   CMP frame
+        BEQ wait
+        LDX #0
+update: LDA sprites+3,X
+        CLC
+        ADC #1
+        STA sprites+3,X
+        LDA sprites,X
+        EOR frame
+        AND #$7F
+        STA sprites,X
+        INX
+        INX
+        INX
+        INX
+        BNE update
+        JMP main
+
+nmi:    PHA
+        TXA
+        PHA
+        LDA #>sprites
+        STA OAMDMA
+        LDA #0
+        STA PPUSCROLL
+        STA PPUSCROLL
+        INC frame
+        PLA
+        TAX
+        PLA
+irq:    RTI
+
+        .org $FFFA
+        .word nmi, reset, irq
+";
+
+/// CPU cycles in an NTSC frame, rounded
+const CYCLES_PER_FRAME: u64 = 29_781;
+
+/// The assembled game as an iNES file with one bank each of PRG and CHR
+fn game_rom() -> Rom {
+    let prg = assemble(GAME).expect("the game assembles").bytes();
+    assert_eq!(prg.len(), 0x4000);
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    raw.extend_from_slice(&prg);
+    raw.extend_from_slice(&[0; 0x2000]);
+    Rom::new(&raw).unwrap()
+}
+
+fn booted_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    cpu.load_rom(&game_rom()).unwrap();
+    cpu.power_on();
+    cpu
+}
+
+/// Runs one frame, starting with the vblank NMI
+fn run_frame(cpu: &mut CPU) {
+    let end = cpu.cycles + CYCLES_PER_FRAME;
+    cpu.trigger_nmi();
+    while cpu.cycles < end {
+        black_box(cpu.step());
+    }
+}
+
+fn bench_frames() {
+    let mut cpu = booted_cpu();
+    let measurement = measure(60, || run_frame(&mut cpu));
+    report(
+        "NROM frames (CPU only)",
+        measurement.per_second(measurement.calls),
+        "frames/s",
+        None,
+    );
+}
+
+fn bench_save_states() {
+    let mut cpu = booted_cpu();
+    for _ in 0..10 {
+        run_frame(&mut cpu);
+    }
+    let state = cpu.save_state();
+
+    let saves = measure(100, || {
+        black_box(cpu.save_state());
+    });
+    report(
+        &format!("save state ({} KiB)", state.len() / 1024),
+        saves.per_second(saves.calls),
+        "saves/s",
+        None,
+    );
+
+    let loads = measure(100, || {
+        cpu.load_state(black_box(&state)).unwrap();
+    });
+    report(
+        &format!("load state ({} KiB)", state.len() / 1024),
+        loads.per_second(loads.calls),
+        "loads/s",
+        None,
+    );
+}
+
+fn main() {
+    bench_frames();
+    bench_save_states();
+}
@@ -0,0 +1,117 @@
+use nes::cartridge::Rom;
+use nes::cpu::CPU;
+use nes::debugger::gdb::GdbStub;
+use nes::debugger::monitor::Monitor;
+use nes::symbols::SymbolTable;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+usage: nes-debug [--gdb PORT | --symbols FILE...] <rom.nes>
+       nes-debug [--gdb PORT | --symbols FILE...] --raw <image> [--origin ADDR] [--pc ADDR]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args = args.as_slice();
+    let mut gdb_port = None;
+    let mut symbol_files = Vec::new();
+    loop {
+        match args {
+            [flag, port, rest @ ..] if flag == "--gdb" => {
+                gdb_port = Some(port.parse::<u16>());
+                args = rest;
+            }
+            [flag, path, rest @ ..] if flag == "--symbols" => {
+                symbol_files.push(path);
+                args = rest;
+            }
+            _ => break,
+        }
+    }
+    // GDB takes symbols from the file it debugs, so the stub has no use for them
+    if gdb_port.is_some() && !symbol_files.is_empty() {
+        usage_error("--symbols only applies to the monitor, not --gdb");
+    }
+
+    let (cpu, mut symbols) = match load(args) {
+        Ok(loaded) => loaded,
+        Err(message) => usage_error(&message),
+    };
+    for path in symbol_files {
+        if let Err(error) = symbols.load_file(Path::new(path)) {
+            usage_error(&format!("{}: {}", path, error));
+        }
+    }
+
+    let result = match gdb_port {
+        Some(Ok(port)) => {
+            eprintln!("nes-debug: waiting for GDB on 127.0.0.1:{}", port);
+            GdbStub::new(cpu).listen(port)
+        }
+        Some(Err(error)) => usage_error(&format!("bad port: {}", error)),
+        None => {
+            let stdin = io::stdin();
+            let mut monitor = Monitor::new(cpu);
+            monitor.symbols = symbols;
+            monitor.run(stdin.lock(), io::stdout())
+        }
+    };
+    if let Err(error) = result {
+        eprintln!("nes-debug: {}", error);
+        process::exit(1);
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("nes-debug: {}", message);
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn load(args: &[String]) -> Result<(CPU, SymbolTable), String> {
+    let mut cpu = CPU::new();
+    let mut symbols = SymbolTable::new();
+
+    match args {
+        [path] => {
+            let rom = Rom::new(&read(path)?)?;
+            cpu.load_rom(&rom)?;
+            cpu.power_on();
+            symbols.map_nrom(rom.prg_rom.len());
+        }
+        [flag, path, options @ ..] if flag == "--raw" => {
+            let image = read(path)?;
+            let mut origin = 0x0000;
+            let mut pc = None;
+            for pair in options.chunks(2) {
+                match pair {
+                    [option, value] if option == "--origin" => origin = parse_address(value)?,
+                    [option, value] if option == "--pc" => pc = Some(parse_address(value)?),
+                    _ => return Err(format!("unexpected arguments {:?}", pair)),
+                }
+            }
+            cpu.load_flat(&image, origin, pc.unwrap_or(0));
+            if pc.is_none() {
+                // Without --pc, start where the image's reset vector points
+                cpu.program_counter = cpu.memory.read_u16(CPU::RESET_VECTOR);
+            }
+        }
+        _ => return Err(String::from("expected a ROM file")),
+    }
+    Ok((cpu, symbols))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("{}: {}", path, error))
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("`{}` is not an address", text))
+}
@@ -0,0 +1,184 @@
+use std::ops::RangeInclusive;
+
+/// iNES file signature, "NES" followed by MS-DOS end-of-file
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const PRG_ROM_PAGE_SIZE: usize = 16 * 1024;
+const CHR_ROM_PAGE_SIZE: usize = 8 * 1024;
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+}
+
+/// Contents of an iNES file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub screen_mirroring: Mirroring,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, String> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err("File is not in iNES file format".to_string());
+        }
+
+        let mapper = (raw[7] & 0b1111_0000) | (raw[6] >> 4);
+
+        let ines_ver = (raw[7] >> 2) & 0b11;
+        if ines_ver != 0 {
+            return Err("NES2.0 format is not supported".to_string());
+        }
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        let skip_trainer = raw[6] & 0b100 != 0;
+
+        let prg_rom_start = HEADER_SIZE + if skip_trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err("iNES file is shorter than its header says".to_string());
+        }
+
+        Ok(Rom {
+            prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+            chr_rom: raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec(),
+            mapper,
+            screen_mirroring,
+        })
+    }
+}
+
+/// A CPU address window currently showing PRG ROM from `offset` onwards
+#[derive(Clone, Debug, PartialEq)]
+struct PrgWindow {
+    range: RangeInclusive<u16>,
+    offset: usize,
+}
+
+/// Which PRG ROM offsets the CPU currently reaches through each window of
+/// the address space
+///
+/// Tools that key data by ROM offset rather than CPU address (symbols, code
+/// logging) translate through this, so a mapper only has to update it on a
+/// bank switch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PrgMap {
+    windows: Vec<PrgWindow>,
+}
+
+impl PrgMap {
+    pub fn new() -> Self {
+        PrgMap::default()
+    }
+
+    /// NROM layout: 16 KiB mirrored into both halves of $8000-$FFFF, or
+    /// 32 KiB mapped straight through
+    pub fn nrom(prg_len: usize) -> Self {
+        let mut map = PrgMap::new();
+        map.map(0x8000..=0xBFFF, 0);
+        map.map(0xC000..=0xFFFF, prg_len.saturating_sub(PRG_ROM_PAGE_SIZE));
+        map
+    }
+
+    /// Maps `range` to PRG ROM starting at `offset`, replacing any window
+    /// that starts at the same address
+    pub fn map(&mut self, range: RangeInclusive<u16>, offset: usize) {
+        self.windows
+            .retain(|window| window.range.start() != range.start());
+        self.windows.push(PrgWindow { range, offset });
+    }
+
+    /// ROM offset that `addr` currently reaches, if it is mapped to PRG
+    pub fn offset(&self, addr: u16) -> Option<usize> {
+        self.windows
+            .iter()
+            .find(|window| window.range.contains(&addr))
+            .map(|window| window.offset + (addr - window.range.start()) as usize)
+    }
+
+    /// First CPU address that currently reaches ROM `offset`
+    pub fn address_of(&self, offset: usize) -> Option<u16> {
+        self.windows.iter().find_map(|window| {
+            let len = (window.range.end() - window.range.start()) as usize;
+            let relative = offset.checked_sub(window.offset).filter(|r| *r <= len)?;
+            Some(window.range.start() + relative as u16)
+        })
+    }
+
+    /// Every mapped CPU address with the ROM offset it reaches
+    pub fn iter(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.windows.iter().flat_map(|window| {
+            window
+                .range
+                .clone()
+                .map(move |addr| (addr, window.offset + (addr - window.range.start()) as usize))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ines(flags_6: u8, prg_pages: u8, chr_pages: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_pages, chr_pages, flags_6, 0x00];
+        raw.resize(HEADER_SIZE, 0);
+        raw.resize(
+            raw.len()
+                + prg_pages as usize * PRG_ROM_PAGE_SIZE
+                + chr_pages as usize * CHR_ROM_PAGE_SIZE,
+            0,
+        );
+        raw
+    }
+
+    #[test]
+    fn test_parses_header() {
+        let mut raw = ines(0b0001_0001, 2, 1);
+        raw[HEADER_SIZE] = 0xAA;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(rom.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.prg_rom[0], 0xAA);
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        assert!(Rom::new(&[0x00; 32]).is_err());
+        let mut raw = ines(0, 1, 0);
+        raw.truncate(100);
+        assert!(Rom::new(&raw).is_err());
+    }
+
+    #[test]
+    fn test_nrom_prg_map() {
+        let map = PrgMap::nrom(PRG_ROM_PAGE_SIZE);
+        assert_eq!(map.offset(0x8010), Some(0x10));
+        assert_eq!(map.offset(0xC010), Some(0x10));
+        assert_eq!(map.offset(0x6000), None);
+        assert_eq!(map.address_of(0x10), Some(0x8010));
+
+        let map = PrgMap::nrom(2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(map.offset(0xFFFC), Some(0x7FFC));
+        assert_eq!(map.address_of(0x4000), Some(0xC000));
+    }
+}
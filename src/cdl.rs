@@ -0,0 +1,250 @@
+use crate::cartridge::PrgMap;
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::CPU;
+use crate::debugger::{data_accesses, Access};
+use std::fmt;
+
+/// PRG byte flags, as FCEUX defines them
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+/// Which 8 KiB CPU window ($8000, $A000, $C000, $E000) the byte was last
+/// logged through
+pub const WINDOW_MASK: u8 = 0x0C;
+pub const INDIRECT_CODE: u8 = 0x10;
+pub const INDIRECT_DATA: u8 = 0x20;
+pub const PCM_DATA: u8 = 0x40;
+/// Marks the first byte of an instruction, so opcodes can be told from
+/// operands. FCEUX leaves this bit unused and ignores it when loading.
+pub const OPCODE: u8 = 0x80;
+
+/// CHR byte flags, as FCEUX defines them
+pub const RENDERED: u8 = 0x01;
+pub const CHR_READ: u8 = 0x02;
+
+#[derive(Debug, PartialEq)]
+pub enum CdlError {
+    /// A `.cdl` file must be exactly as long as PRG and CHR ROM together
+    SizeMismatch { expected: usize, found: usize },
+}
+
+impl fmt::Display for CdlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CdlError::SizeMismatch { expected, found } => write!(
+                f,
+                "code/data log is {} bytes, but the ROM needs {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CdlError {}
+
+/// Code/Data Logger: records how every byte of PRG and CHR ROM was used
+///
+/// Bytes are tracked by ROM offset through `prg_map`, so code in a bank
+/// that is switched out keeps its marks. Call `log_instruction` before
+/// each instruction, e.g. from `CPU::run_with_callback`. Until there is a
+/// PPU, CHR usage has to be reported with `log_chr_rendered` and
+/// `log_chr_read`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CodeDataLog {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub prg_map: PrgMap,
+}
+
+impl CodeDataLog {
+    pub fn new(prg_len: usize, chr_len: usize, prg_map: PrgMap) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_len],
+            chr: vec![0; chr_len],
+            prg_map,
+        }
+    }
+
+    /// Loads an FCEUX `.cdl` file: PRG flags followed by CHR flags
+    pub fn from_bytes(
+        data: &[u8],
+        prg_len: usize,
+        chr_len: usize,
+        prg_map: PrgMap,
+    ) -> Result<Self, CdlError> {
+        if data.len() != prg_len + chr_len {
+            return Err(CdlError::SizeMismatch {
+                expected: prg_len + chr_len,
+                found: data.len(),
+            });
+        }
+        Ok(CodeDataLog {
+            prg: data[..prg_len].to_vec(),
+            chr: data[prg_len..].to_vec(),
+            prg_map,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    /// Logs the instruction at the program counter before it runs: its
+    /// bytes as code and whatever ROM it reads as data
+    pub fn log_instruction(&mut self, cpu: &CPU) {
+        let pc = cpu.program_counter;
+        let opcode = cpu.variant.opcodes()[cpu.memory.read(pc) as usize];
+
+        self.mark(pc, CODE | OPCODE);
+        for offset in 1..opcode.len as u16 {
+            self.mark(pc.wrapping_add(offset), CODE);
+        }
+
+        let indirect = matches!(
+            opcode.mode,
+            AddressingMode::Indirect_X
+                | AddressingMode::Indirect_Y
+                | AddressingMode::ZeroPage_Indirect
+        );
+        for (addr, access) in data_accesses(cpu, opcode) {
+            if access == Access::Read {
+                self.mark(addr, if indirect { DATA | INDIRECT_DATA } else { DATA });
+            }
+        }
+
+        if opcode.mode == AddressingMode::Indirect {
+            let pointer = cpu.memory.read_u16(pc.wrapping_add(1));
+            let target =
+                AddressingMode::get_absolute_address(cpu, &opcode.mode, pc.wrapping_add(1));
+            self.mark(pointer, DATA);
+            self.mark(pointer.wrapping_add(1), DATA);
+            self.mark(target, INDIRECT_CODE);
+        }
+    }
+
+    /// Marks a DMC sample byte fetched from `addr`
+    pub fn log_pcm(&mut self, addr: u16) {
+        self.mark(addr, DATA | PCM_DATA);
+    }
+
+    pub fn log_chr_rendered(&mut self, offset: usize) {
+        if let Some(flags) = self.chr.get_mut(offset) {
+            *flags |= RENDERED;
+        }
+    }
+
+    pub fn log_chr_read(&mut self, offset: usize) {
+        if let Some(flags) = self.chr.get_mut(offset) {
+            *flags |= CHR_READ;
+        }
+    }
+
+    fn mark(&mut self, addr: u16, flags: u8) {
+        let offset = match self.prg_map.offset(addr) {
+            Some(offset) => offset,
+            None => return,
+        };
+        if let Some(byte) = self.prg.get_mut(offset) {
+            let window = (((addr >> 13) & 0b11) as u8) << 2;
+            *byte = (*byte & !WINDOW_MASK) | window | flags;
+        }
+    }
+
+    /// Flags of the PRG byte `addr` currently reaches
+    pub fn flags(&self, addr: u16) -> u8 {
+        self.prg_map
+            .offset(addr)
+            .and_then(|offset| self.prg.get(offset))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.flags(addr) & CODE != 0
+    }
+
+    pub fn is_data(&self, addr: u16) -> bool {
+        self.flags(addr) & DATA != 0
+    }
+
+    /// Whether the byte at `addr` was executed as the first byte of an
+    /// instruction, as opposed to an operand
+    pub fn is_opcode(&self, addr: u16) -> bool {
+        self.flags(addr) & OPCODE != 0
+    }
+
+    /// Share of PRG ROM logged as code and as data, for progress reports
+    pub fn coverage(&self) -> (f64, f64) {
+        let share = |mask: u8| {
+            let count = self.prg.iter().filter(|flags| **flags & mask != 0).count();
+            count as f64 / self.prg.len().max(1) as f64
+        };
+        (share(CODE), share(DATA))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cpu_with(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(program.to_vec());
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_logs_opcodes_operands_and_data() {
+        let mut cpu = cpu_with(&[
+            0xAD, 0x10, 0x80, // LDA $8010
+            0xB1, 0x00, // LDA ($00),Y
+            0x8D, 0x00, 0x02, // STA $0200
+            0x00,
+        ]);
+        cpu.memory.write_u16(0x0000, 0x8020);
+        let mut cdl = CodeDataLog::new(0x8000, 0x2000, PrgMap::nrom(0x8000));
+        cpu.run_with_callback(|cpu| cdl.log_instruction(cpu));
+
+        assert_eq!(cdl.prg[0x00], CODE | OPCODE);
+        assert_eq!(cdl.prg[0x01], CODE);
+        assert_eq!(cdl.prg[0x10], DATA);
+        assert_eq!(cdl.prg[0x20], DATA | INDIRECT_DATA);
+        assert!(cdl.is_opcode(0x8008));
+        assert!(!cdl.is_code(0x8009));
+        assert!(!cdl.is_data(0x0200));
+    }
+
+    #[test]
+    fn test_logs_by_rom_offset() {
+        // A mirrored 16 KiB bank seen through $C000 is the same ROM byte
+        let mut cpu = cpu_with(&[]);
+        cpu.memory.write(0xC000, 0xEA);
+        cpu.program_counter = 0xC000;
+        let mut cdl = CodeDataLog::new(0x4000, 0, PrgMap::nrom(0x4000));
+        cdl.log_instruction(&cpu);
+
+        assert_eq!(cdl.prg[0], CODE | OPCODE | 0b1000);
+        assert!(cdl.is_code(0x8000));
+    }
+
+    #[test]
+    fn test_fceux_file_round_trip() {
+        let mut cdl = CodeDataLog::new(0x4000, 0x2000, PrgMap::nrom(0x4000));
+        cdl.prg[5] = CODE;
+        cdl.log_chr_rendered(0x10);
+        cdl.log_chr_read(0x10);
+
+        let bytes = cdl.to_bytes();
+        assert_eq!(bytes.len(), 0x6000);
+        assert_eq!(bytes[0x4010], RENDERED | CHR_READ);
+        let loaded = CodeDataLog::from_bytes(&bytes, 0x4000, 0x2000, PrgMap::nrom(0x4000));
+        assert_eq!(loaded, Ok(cdl));
+        assert_eq!(
+            CodeDataLog::from_bytes(&bytes, 0x8000, 0x2000, PrgMap::new()),
+            Err(CdlError::SizeMismatch {
+                expected: 0xA000,
+                found: 0x6000
+            })
+        );
+    }
+}
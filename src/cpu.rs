@@ -1,15 +1,62 @@
-use crate::cpu::addressing_mode::AddressingMode;
-use crate::cpu::memory::Memory;
-use crate::cpu::opscodes::{OpCode, OPCODES_MAP};
+use crate::cartridge::Rom;
+#[cfg(feature = "memory-hooks")]
+use crate::cpu::hooks::{AccessKind, MemoryHooks};
+use crate::cpu::memory::{Memory, RamInit};
 use crate::cpu::processor_status::ProcessorStatus;
 use crate::cpu::register::Register;
-use std::collections::HashMap;
+use crate::cpu::variant::Variant;
+use crate::debugger::Access;
+use crate::input::InputPorts;
+use crate::savestate::{self, Savestate, SavestateError, StateReader, StateWriter};
+use crate::util::shared::AdjustBy1;
+
+pub mod addressing_mode;
+pub mod assembler;
+pub mod disassembler;
+#[cfg(feature = "memory-hooks")]
+pub mod hooks;
+pub mod memory;
+pub mod opscodes;
+pub mod processor_status;
+pub mod register;
+pub mod standalone;
+pub mod trace;
+pub mod variant;
+
+/// Why `CPU::step` can't carry on to the next instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// BRK ran its software interrupt, leaving the program counter on the
+    /// first instruction of the IRQ handler
+    Brk,
+    /// KIL or STP locked up the CPU until reset
+    Jammed,
+    /// WAI put the CPU to sleep and no interrupt has woken it yet
+    Waiting,
+    /// The opcode is illegal and `reject_illegal_opcodes` is set; nothing ran
+    IllegalOpcode,
+}
+
+/// What `CPU::step` did
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Ran the instruction at the program counter
+    Instruction,
+    /// Took an NMI or IRQ through `vector` in place of an instruction,
+    /// leaving the program counter on the handler
+    Interrupt(u16),
+    /// Didn't get through, or shouldn't carry on; see `Stop`
+    Stopped(Stop),
+}
 
-mod addressing_mode;
-mod memory;
-mod opscodes;
-mod processor_status;
-mod register;
+impl Step {
+    pub fn stop(self) -> Option<Stop> {
+        match self {
+            Step::Stopped(stop) => Some(stop),
+            _ => None,
+        }
+    }
+}
 
 pub struct CPU {
     pub register_a: Register,
@@ -18,7 +65,44 @@ pub struct CPU {
     pub register_y: Register,
     pub status: ProcessorStatus,
     pub program_counter: u16,
+    pub cycles: u64,
     pub memory: Memory,
+    /// Hash of the loaded program, used to match save states to it
+    pub rom_hash: u64,
+    /// PRG ROM of the cartridge from `load_rom`, which `reset` puts back on
+    /// the bus since the flat memory lets stray writes land on it
+    cartridge: Option<Vec<u8>>,
+    pub variant: Variant,
+    /// Set by KIL, or STP on the 65C02. The CPU stops fetching
+    /// instructions until reset.
+    pub jammed: bool,
+    /// Set by the 65C02's WAI. The CPU sleeps until an interrupt, so
+    /// whoever delivers one clears this.
+    pub waiting: bool,
+    /// Treat undocumented opcodes as errors: `step` refuses to run them,
+    /// for checking that homebrew sticks to the documented instruction set
+    pub reject_illegal_opcodes: bool,
+    /// What `power_on` leaves in RAM
+    pub ram_init: RamInit,
+    /// Level of the IRQ input, held by devices for as long as they want
+    /// service. The CPU takes the interrupt while I is clear.
+    pub irq_line: bool,
+    /// Latched by `trigger_nmi` and cleared when the CPU takes the NMI
+    pub nmi_pending: bool,
+    /// Whether an interrupt was wanted at the end of the last cycle, and
+    /// of the one before it. The 6502 polls on every cycle but acts on the
+    /// poll from the one before the last of each instruction.
+    interrupt_wanted: bool,
+    interrupt_wanted_before: bool,
+    /// The next `step` takes an interrupt instead of an instruction
+    interrupt_due: bool,
+    /// Vector of the interrupt taken since the last instruction ran
+    interrupt_taken: Option<u16>,
+    /// Data accesses, dummy reads included, collected for the debugger's
+    /// watchpoints while it sets this to `Some`
+    pub(crate) access_log: Option<Vec<(u16, Access)>>,
+    #[cfg(feature = "memory-hooks")]
+    pub hooks: MemoryHooks,
 }
 
 impl CPU {
@@ -26,7 +110,10 @@ impl CPU {
     /// 16-bit address stored at 0xFFFC (low byte) and 0xFFFD (high byte)
     /// and sets the program_counter to this address.
     /// This is where execution begins.
-    const RESET_VECTOR: u16 = 0xFFFC;
+    pub const RESET_VECTOR: u16 = 0xFFFC;
+    pub const NMI_VECTOR: u16 = 0xFFFA;
+    /// Shared by IRQ and BRK
+    pub const IRQ_VECTOR: u16 = 0xFFFE;
 
     pub fn new() -> Self {
         CPU {
@@ -34,174 +121,530 @@ impl CPU {
             register_s: Register::new(0),
             register_x: Register::new(0),
             register_y: Register::new(0),
-            status: ProcessorStatus::new(0),
+            status: ProcessorStatus::new(ProcessorStatus::POWER_ON),
             program_counter: 0,
+            cycles: 0,
             memory: Memory::new(),
+            rom_hash: savestate::hash(&[]),
+            cartridge: None,
+            variant: Variant::default(),
+            jammed: false,
+            irq_line: false,
+            nmi_pending: false,
+            interrupt_wanted: false,
+            interrupt_wanted_before: false,
+            interrupt_due: false,
+            interrupt_taken: None,
+            waiting: false,
+            reject_illegal_opcodes: false,
+            ram_init: RamInit::default(),
+            access_log: None,
+            #[cfg(feature = "memory-hooks")]
+            hooks: MemoryHooks::new(),
         }
     }
 
     pub fn load_and_run(&mut self, program: Vec<u8>) {
         self.load(program);
-        self.reset();
+        self.power_on();
         self.run();
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
+        self.rom_hash = savestate::hash(&program);
+        self.cartridge = None;
         self.memory.load_program(program);
         self.memory.write_u16(CPU::RESET_VECTOR, 0x8000)
     }
 
-    pub fn reset(&mut self) {
+    /// Maps the PRG ROM of an NROM cartridge to 0x8000, mirroring a single
+    /// 16 KiB bank into 0xC000
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), String> {
+        if rom.mapper != 0 {
+            return Err(format!("Mapper {} is not supported", rom.mapper));
+        }
+        match rom.prg_rom.len() {
+            0x4000 | 0x8000 => {}
+            len => return Err(format!("NROM can't hold {} bytes of PRG ROM", len)),
+        }
+        self.cartridge = Some(rom.prg_rom.clone());
+        self.map_cartridge();
+        self.rom_hash = savestate::hash(&rom.prg_rom);
+        Ok(())
+    }
+
+    fn map_cartridge(&mut self) {
+        if let Some(prg_rom) = &self.cartridge {
+            self.memory.load_at(0x8000, prg_rom);
+            if prg_rom.len() == 0x4000 {
+                self.memory.load_at(0xC000, prg_rom);
+            }
+        }
+    }
+
+    /// Loads a flat binary for running the 6502 on its own, outside an
+    /// NES: memory is cleared, `image` is copied to `origin` and execution
+    /// starts at `pc` rather than through `CPU::RESET_VECTOR`
+    pub fn load_flat(&mut self, image: &[u8], origin: u16, pc: u16) {
+        self.memory = Memory::new();
+        self.cartridge = None;
+        self.power_on();
+        self.memory.load_at(origin, image);
+        self.rom_hash = savestate::hash(image);
+        self.program_counter = pc;
+    }
+
+    /// Turns the console on: RAM is filled as `ram_init` says, the
+    /// registers start cleared and the reset sequence runs, which leaves S
+    /// at $FD
+    pub fn power_on(&mut self) {
+        self.memory.fill_ram(self.ram_init);
         self.register_a = Register::new(0);
-        self.register_s = Register::new(0xFF); // Stack
+        self.register_s = Register::new(0);
         self.register_x = Register::new(0);
         self.register_y = Register::new(0);
-        self.status = ProcessorStatus::new(0);
+        self.status = ProcessorStatus::new(ProcessorStatus::POWER_ON);
+        self.cycles = 0;
+        self.reset();
+    }
+
+    /// Presses the reset button. The 6502 runs its interrupt sequence with
+    /// the writes suppressed, so S drops by 3 and I is set but the other
+    /// registers and RAM are left as they were.
+    pub fn reset(&mut self) {
+        self.register_s.0 = self.register_s.0.wrapping_sub(3);
+        self.status.set_interupt_disable_flag(true);
+        if self.variant.is_cmos() {
+            self.status.set_decimal_flag(false);
+        }
+        self.jammed = false;
+        self.waiting = false;
+        self.nmi_pending = false;
+        self.interrupt_wanted = false;
+        self.interrupt_wanted_before = false;
+        self.interrupt_due = false;
+        self.interrupt_taken = None;
+        self.map_cartridge();
         self.program_counter = self.memory.read_u16(CPU::RESET_VECTOR);
+        self.cycles += 7; // The reset sequence takes 7 cycles
     }
 
-    pub fn run(&mut self) {
-        let ref opcodes: HashMap<u8, &'static OpCode> = *OPCODES_MAP;
+    /// Switches the whole machine on: the CPU with its cartridge, then the
+    /// input ports. There is no PPU or APU yet to be powered on.
+    pub fn power_on_machine(&mut self, input: &mut InputPorts) {
+        self.power_on();
+        input.power_on();
+    }
 
+    /// Presses the reset button, which reaches the CPU, its cartridge and
+    /// the controller strobe. There is no PPU or APU yet to be reset.
+    pub fn reset_machine(&mut self, input: &mut InputPorts) {
+        self.reset();
+        input.reset();
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save_state(self, self.rom_hash)
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SavestateError> {
+        savestate::load_state(self, self.rom_hash, data)
+    }
+
+    /// Saves the whole machine: the input ports, then the CPU and memory
+    pub fn save_machine(&self, input: &InputPorts) -> Vec<u8> {
+        let mut body = StateWriter::new();
+        input.save(&mut body);
+        Savestate::save(self, &mut body);
+        savestate::seal(body, self.rom_hash)
+    }
+
+    /// Restores a state from `save_machine`. The input ports are only
+    /// replaced once the CPU has loaded.
+    pub fn load_machine(
+        &mut self,
+        input: &mut InputPorts,
+        data: &[u8],
+    ) -> Result<(), SavestateError> {
+        let mut body = savestate::open(self.rom_hash, data)?;
+        let mut restored = InputPorts::new(input.mode);
+        restored.load(&mut body)?;
+        Savestate::load(self, &mut body)?;
+        body.finish()?;
+        *input = restored;
+        Ok(())
+    }
+
+    /// One read cycle on behalf of the running program. Each bus access
+    /// is one CPU cycle, so this also advances `cycles`. With the
+    /// `memory-hooks` feature the access is reported to `hooks`.
+    #[inline]
+    pub fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = self.memory.read(addr);
+        #[cfg(feature = "memory-hooks")]
+        self.hooks.notify(addr, value, AccessKind::Read, self.cycles);
+        self.log_access(addr, Access::Read);
+        self.end_cycle();
+        value
+    }
+
+    /// One write cycle, like `mem_read`
+    #[inline]
+    pub fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory.write(addr, data);
+        #[cfg(feature = "memory-hooks")]
+        self.hooks.notify(addr, data, AccessKind::Write, self.cycles);
+        self.log_access(addr, Access::Write);
+        self.end_cycle();
+    }
+
+    /// Reads the opcode or operand byte at the program counter and moves
+    /// past it
+    #[inline]
+    pub(crate) fn fetch(&mut self) -> u8 {
+        let addr = self.program_counter;
+        let value = self.memory.read(addr);
+        #[cfg(feature = "memory-hooks")]
+        self.hooks.notify(addr, value, AccessKind::Execute, self.cycles);
+        self.end_cycle();
+        self.program_counter = addr.wrapping_add(1);
+        value
+    }
+
+    pub(crate) fn fetch_u16(&mut self) -> u16 {
+        let lo = self.fetch();
+        let hi = self.fetch();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// A read cycle whose value the CPU throws away. The 6502 never leaves
+    /// the bus idle, so these still reach I/O registers with read side
+    /// effects.
+    #[inline]
+    pub(crate) fn dummy_read(&mut self, addr: u16) {
+        let _value = self.memory.read(addr);
+        #[cfg(feature = "memory-hooks")]
+        self.hooks.notify(addr, _value, AccessKind::DummyRead, self.cycles);
+        self.log_access(addr, Access::Read);
+        self.end_cycle();
+    }
+
+    /// The NMOS 6502 writes the unmodified value back while it works out
+    /// the result of a read-modify-write instruction; the 65C02 rereads it
+    /// instead. Returns the value for the instruction to modify.
+    pub(crate) fn read_for_modify(&mut self, addr: u16) -> u8 {
+        let value = self.mem_read(addr);
+        if self.variant.is_cmos() {
+            self.dummy_read(addr);
+        } else {
+            self.mem_write(addr, value);
+        }
+        value
+    }
+
+    #[inline]
+    fn log_access(&mut self, addr: u16, access: Access) {
+        if let Some(log) = &mut self.access_log {
+            log.push((addr, access));
+        }
+    }
+
+    /// Samples the interrupt inputs, which the 6502 does at the end of
+    /// every cycle
+    #[inline]
+    fn end_cycle(&mut self) {
+        self.cycles += 1;
+        self.interrupt_wanted_before = self.interrupt_wanted;
+        self.interrupt_wanted =
+            self.nmi_pending || (self.irq_line && self.status.get_interupt_disable_flag() == 0);
+    }
+
+    /// A taken branch that stays on its page doesn't poll on its last
+    /// cycle, so an interrupt that only became pending during the branch
+    /// waits until after the next instruction
+    pub(crate) fn skip_interrupt_poll(&mut self) {
+        if self.interrupt_wanted && !self.interrupt_wanted_before {
+            self.interrupt_wanted = false;
+        }
+    }
+
+    /// Signals an NMI. Like the edge-triggered input, it is remembered
+    /// until the CPU takes it.
+    pub fn trigger_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Takes an interrupt through `vector`, the 7-cycle sequence the 6502
+    /// runs in place of an instruction: two reads of the next opcode that
+    /// are thrown away, then the return address and the status with B
+    /// clear are pushed and the handler address is fetched
+    pub fn interrupt(&mut self, vector: u16) {
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+        self.enter_interrupt_handler(vector, self.status.pushed(false));
+        self.interrupt_taken = Some(vector);
+    }
+
+    /// The vector of the NMI or IRQ taken since the last instruction ran,
+    /// for tools that watch execution from `run_with_callback`
+    pub fn interrupt_taken(&self) -> Option<u16> {
+        self.interrupt_taken
+    }
+
+    /// The last five cycles of an interrupt or BRK
+    pub(crate) fn enter_interrupt_handler(&mut self, vector: u16, pushed_status: u8) {
+        self.stack_push_u16(self.program_counter);
+        self.stack_push(pushed_status);
+        self.status.set_interupt_disable_flag(true);
+        if self.variant.is_cmos() {
+            self.status.set_decimal_flag(false);
+        }
+        let lo = self.mem_read(vector);
+        let hi = self.mem_read(vector.wrapping_add(1));
+        self.program_counter = u16::from_le_bytes([lo, hi]);
+    }
+
+    pub fn stack_push(&mut self, data: u8) {
+        self.mem_write(0x0100 + self.register_s.0 as u16, data);
+        self.register_s.decrement();
+    }
+
+    /// The cycle before a pull, in which the 6502 reads the stack without
+    /// moving the pointer yet
+    pub(crate) fn stack_dummy_read(&mut self) {
+        self.dummy_read(0x0100 + self.register_s.0 as u16);
+    }
+
+    pub fn stack_pull(&mut self) -> u8 {
+        self.register_s.increment();
+        self.mem_read(0x0100 + self.register_s.0 as u16)
+    }
+
+    pub fn stack_push_u16(&mut self, data: u16) {
+        let [lo, hi] = data.to_le_bytes();
+        self.stack_push(hi);
+        self.stack_push(lo);
+    }
+
+    pub fn stack_pull_u16(&mut self) -> u16 {
+        let lo = self.stack_pull();
+        let hi = self.stack_pull();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    pub fn run(&mut self) {
+        self.run_with_callback(|_| {});
+    }
+
+    /// Runs until BRK or KIL, calling `callback` before each instruction. Tracers
+    /// and other tooling hook in here; a no-op closure compiles away.
+    ///
+    /// Interrupts are taken before the callback, so it only ever sees
+    /// instructions that then run.
+    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
         loop {
-            let code = self.memory.read(self.program_counter);
-            let opcode = opcodes
-                .get(&code)
-                .expect(&format!("OpCode {:x} is not recognized", code));
-
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
-
-            match code {
-                0x00 => {
-                    return;
-                }
-                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                    opscodes::arithmetic_logic::adc(self, &opcode.mode);
-                }
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
-                    opscodes::arithmetic_logic::and(self, &opcode.mode);
-                }
-                0x0A | 0x06 | 0x16 | 0x0E | 0x1E => {
-                    opscodes::arithmetic_logic::asl(self, &opcode.mode);
-                }
-                0x90 => {
-                    opscodes::control_flow::bcc(self, &opcode.mode);
-                }
-                0xB0 => {
-                    opscodes::control_flow::bcs(self, &opcode.mode);
-                }
-                0xF0 => {
-                    opscodes::control_flow::beq(self, &opcode.mode);
-                }
-                0x24 | 0x2C => {
-                    opscodes::arithmetic_logic::bit(self, &opcode.mode);
-                }
-                0x18 => {
-                    opscodes::status_register::clc(self);
-                }
-                0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
-                    opscodes::arithmetic_logic::cmp(self, &opcode.mode);
-                }
-                0xE0 | 0xE4 | 0xEC => {
-                    opscodes::registers::cpx(self, &opcode.mode);
-                }
-                0xC0 | 0xC4 | 0xCC => {
-                    opscodes::registers::cpy(self, &opcode.mode);
-                }
-                0xC6 | 0xD6 | 0xCE | 0xDE => {
-                    opscodes::arithmetic_logic::dec(self, &opcode.mode);
-                }
-                0xCA => {
-                    opscodes::registers::dex(self);
-                }
-                0x88 => {
-                    opscodes::registers::dey(self);
-                }
-                0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
-                    opscodes::arithmetic_logic::eor(self, &opcode.mode);
-                }
-                0xE6 | 0xF6 | 0xEE | 0xFE => {
-                    opscodes::registers::inc(self, &opcode.mode);
-                }
-                0xE8 => {
-                    opscodes::registers::inx(self);
-                }
-                0xC8 => {
-                    opscodes::registers::iny(self);
-                }
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    opscodes::registers::lda(self, &opcode.mode);
-                }
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
-                    opscodes::registers::ldx(self, &opcode.mode);
-                }
-                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
-                    opscodes::registers::ldy(self, &opcode.mode);
-                }
-                0x4A | 0x46 | 0x56 | 0x4E | 0x5E => {
-                    opscodes::arithmetic_logic::lsr(self, &opcode.mode);
-                }
-                0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
-                    opscodes::arithmetic_logic::ora(self, &opcode.mode);
-                }
-                0x48 => {
-                    opscodes::registers::pha(self);
-                }
-                0x2A | 0x26 | 0x36 | 0x2E | 0x3E => {
-                    opscodes::arithmetic_logic::rol(self, &opcode.mode);
-                }
-                0x6A | 0x66 | 0x76 | 0x6E | 0x7E => {
-                    opscodes::arithmetic_logic::ror(self, &opcode.mode);
-                }
-                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                    opscodes::arithmetic_logic::sbc(self, &opcode.mode);
-                }
-                0x38 => {
-                    opscodes::status_register::sec(self);
-                }
-                0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-                    opscodes::registers::sta(self, &opcode.mode);
-                }
-                0x86 | 0x96 | 0x8e => {
-                    opscodes::registers::stx(self, &opcode.mode);
-                }
-                0x84 | 0x94 | 0x8c => {
-                    opscodes::registers::sty(self, &opcode.mode);
-                }
-                0xAA => {
-                    opscodes::registers::tax(self);
-                }
-                0xA8 => {
-                    opscodes::registers::tay(self);
-                }
-                0xBA => {
-                    opscodes::registers::tsx(self);
-                }
-                0x8A => {
-                    opscodes::registers::txa(self);
-                }
-                0x9A => {
-                    opscodes::registers::txs(self);
-                }
-                0x98 => {
-                    opscodes::registers::tya(self);
-                }
-                _ => todo!(),
+            if self.take_interrupt().is_some() {
+                continue;
             }
+            callback(self);
 
-            if program_counter_state == self.program_counter
-                || opcode.mode == AddressingMode::Relative
-            {
-                self.program_counter += (opcode.len - 1) as u16;
+            if self.step().stop().is_some() {
+                return;
             }
         }
     }
+
+    /// Takes the interrupt the last instruction polled, if one is due,
+    /// returning its vector. Also wakes the CPU from WAI on an interrupt
+    /// signal. `step` does this first anyway; tooling that looks at each
+    /// instruction before it runs calls it beforehand.
+    pub fn take_interrupt(&mut self) -> Option<u16> {
+        if self.jammed {
+            return None;
+        }
+        if self.waiting {
+            // WAI wakes on IRQ even with I set, carrying on after the WAI
+            if !self.nmi_pending && !self.irq_line {
+                return None;
+            }
+            self.waiting = false;
+            self.interrupt_due = self.nmi_pending || self.status.get_interupt_disable_flag() == 0;
+        }
+        if !self.interrupt_due {
+            return None;
+        }
+        self.interrupt_due = false;
+        let vector = if self.nmi_pending {
+            self.nmi_pending = false;
+            CPU::NMI_VECTOR
+        } else {
+            CPU::IRQ_VECTOR
+        };
+        self.interrupt(vector);
+        Some(vector)
+    }
+
+    /// Executes the instruction at the program counter, or takes an
+    /// interrupt the last one polled. Stops after BRK, KIL or STP, and
+    /// without doing anything while jammed or waiting, or on an illegal
+    /// opcode if those are rejected.
+    ///
+    /// Every bus access the instruction makes, dummy reads included, takes
+    /// its own cycle in the order the 6502 makes them.
+    pub fn step(&mut self) -> Step {
+        if self.jammed {
+            return Step::Stopped(Stop::Jammed);
+        }
+        if let Some(vector) = self.take_interrupt() {
+            return Step::Interrupt(vector);
+        }
+        if self.waiting {
+            return Step::Stopped(Stop::Waiting);
+        }
+
+        let code = self.memory.read(self.program_counter);
+        let opcode = self.variant.opcodes()[code as usize];
+        if opcode.illegal && self.reject_illegal_opcodes {
+            return Step::Stopped(Stop::IllegalOpcode);
+        }
+
+        self.interrupt_taken = None;
+        self.fetch();
+        // Single-byte instructions read the next byte while decoding, apart
+        // from the 65C02's one-cycle NOPs
+        if opcode.len == 1 && opcode.cycles > 1 {
+            self.dummy_read(self.program_counter);
+        }
+        (opcode.execute)(self, &opcode.mode);
+
+        if self.jammed {
+            return Step::Stopped(Stop::Jammed);
+        }
+        // BRK still ends `run`, but only once it has entered the handler
+        if code == 0x00 {
+            return Step::Stopped(Stop::Brk);
+        }
+        // What the 6502 polled on the cycle before the last decides whether
+        // an interrupt comes before the next instruction
+        self.interrupt_due = self.interrupt_wanted_before;
+        Step::Instruction
+    }
+}
+
+impl Savestate for CPU {
+    fn save(&self, state: &mut StateWriter) {
+        // Destructured so that a new field can't be left out of save states
+        let CPU {
+            register_a,
+            register_s,
+            register_x,
+            register_y,
+            status,
+            program_counter,
+            cycles,
+            memory,
+            rom_hash: _,
+            cartridge: _,
+            variant,
+            jammed,
+            waiting,
+            reject_illegal_opcodes: _,
+            ram_init: _,
+            irq_line,
+            nmi_pending,
+            interrupt_wanted,
+            interrupt_wanted_before,
+            interrupt_due,
+            interrupt_taken: _,
+            access_log: _,
+            #[cfg(feature = "memory-hooks")]
+            hooks: _,
+        } = self;
+
+        state.write_u8(*variant as u8);
+        register_a.save(state);
+        register_s.save(state);
+        register_x.save(state);
+        register_y.save(state);
+        status.save(state);
+        state.write_u16(*program_counter);
+        state.write_u64(*cycles);
+        state.write_bool(*jammed);
+        state.write_bool(*waiting);
+        state.write_bool(*irq_line);
+        state.write_bool(*nmi_pending);
+        state.write_bool(*interrupt_wanted);
+        state.write_bool(*interrupt_wanted_before);
+        state.write_bool(*interrupt_due);
+        memory.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError> {
+        let CPU {
+            register_a,
+            register_s,
+            register_x,
+            register_y,
+            status,
+            program_counter,
+            cycles,
+            memory,
+            rom_hash: _,
+            cartridge: _,
+            variant,
+            jammed,
+            waiting,
+            reject_illegal_opcodes: _,
+            ram_init: _,
+            irq_line,
+            nmi_pending,
+            interrupt_wanted,
+            interrupt_wanted_before,
+            interrupt_due,
+            interrupt_taken,
+            access_log: _,
+            #[cfg(feature = "memory-hooks")]
+            hooks: _,
+        } = self;
+
+        let found = match state.read_u8()? {
+            0 => Variant::Ricoh2A03,
+            1 => Variant::Nmos6502,
+            2 => Variant::Wdc65C02,
+            _ => return Err(SavestateError::Corrupted),
+        };
+        if found != *variant {
+            return Err(SavestateError::VariantMismatch {
+                expected: *variant,
+                found,
+            });
+        }
+        register_a.load(state)?;
+        register_s.load(state)?;
+        register_x.load(state)?;
+        register_y.load(state)?;
+        status.load(state)?;
+        *program_counter = state.read_u16()?;
+        *cycles = state.read_u64()?;
+        *jammed = state.read_bool()?;
+        *waiting = state.read_bool()?;
+        *irq_line = state.read_bool()?;
+        *nmi_pending = state.read_bool()?;
+        *interrupt_wanted = state.read_bool()?;
+        *interrupt_wanted_before = state.read_bool()?;
+        *interrupt_due = state.read_bool()?;
+        *interrupt_taken = None;
+        memory.load(state)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::cpu::processor_status::Flag;
     use crate::util::u8_ext::*;
 
     #[test]
@@ -328,7 +771,7 @@ mod test {
         // On the first pass, 0x7F + 0x01 = 0x80 doesn't set the carry flag, so BCC is taken, going back 6 bytes.
         // On the second pass, 0x80 + 0x01 = 0x81 does set the carry flag, so BCC isn't taken, and execution continues to LDA #0xAA.
         assert_eq!(cpu.register_a.0, 0xAA);
-        assert_eq!(cpu.program_counter, 0x8009);
+        assert_eq!(brk_address(&cpu), 0x8008);
     }
 
     #[test]
@@ -345,7 +788,7 @@ mod test {
 
         // 0xFF + 0x01 = 0x00 with the carry flag set. Therefore, BCS is taken and skips to LDA #0xAA.
         assert_eq!(cpu.register_a.0, 0xAA);
-        assert_eq!(cpu.program_counter, 0x800B);
+        assert_eq!(brk_address(&cpu), 0x800A);
     }
 
     #[test]
@@ -361,7 +804,7 @@ mod test {
 
         // 0x7F + 0x01 = 0x80 without setting the carry flag. Therefore, BCS isn't taken and the LDA #0x11 is executed.
         assert_eq!(cpu.register_a.0, 0x11);
-        assert_eq!(cpu.program_counter, 0x8009);
+        assert_eq!(brk_address(&cpu), 0x8008);
     }
 
     #[test]
@@ -378,7 +821,7 @@ mod test {
 
         // The first ADC won't set the carry flag, but the second ADC will. BCS then goes back 4 bytes to the second ADC, which still sets the carry. Execution then moves to LDA #0xAA.
         assert_eq!(cpu.register_a.0, 0xAA);
-        assert_eq!(cpu.program_counter, 0x800B);
+        assert_eq!(brk_address(&cpu), 0x800A);
     }
 
     #[test]
@@ -393,7 +836,7 @@ mod test {
             0x00, // BRK or another ending instruction
         ]);
         assert_eq!(cpu.register_a.0, 0xCC);
-        assert_eq!(cpu.program_counter, 0x800B); // PC should be right after the second LDA
+        assert_eq!(brk_address(&cpu), 0x800A); // BRK should come right after the second LDA
     }
 
     #[test]
@@ -402,12 +845,13 @@ mod test {
         cpu.load_and_run(vec![
             0xA9, 0x01, // LDA #0x01
             0x49, 0x01, // EOR #0x01 (XOR with itself to set zero flag)
-            0xF0, 0xFE, // BEQ -2 (Skip back 2 bytes if zero flag is set)
+            0xF0, 0xFC, // BEQ -4 (Skip back to the EOR if zero flag is set)
             0xA9, 0xCC, // LDA #0xCC (This should be executed)
             0x00, // BRK or another ending instruction
         ]);
-        assert_eq!(cpu.register_a.0, 0xCC); // EOR should have been executed again, resulting in zero
-        assert_eq!(cpu.program_counter, 0x8009); // PC should be right after the BEQ
+        // The second EOR leaves A non-zero, so BEQ falls through to the LDA
+        assert_eq!(cpu.register_a.0, 0xCC);
+        assert_eq!(brk_address(&cpu), 0x8008);
     }
 
     #[test]
@@ -420,8 +864,8 @@ mod test {
             0xA9, 0xCC, // LDA #0xCC (This should be executed)
             0x00, // BRK or another ending instruction
         ]);
-        assert_eq!(cpu.register_a.0, 0x7F);
-        assert_eq!(cpu.program_counter, 0x8007); // PC should be right after the LDA
+        assert_eq!(cpu.register_a.0, 0xCC);
+        assert_eq!(brk_address(&cpu), 0x8006); // BRK should come right after the LDA
     }
 
     #[test]
@@ -829,7 +1273,7 @@ mod test {
     #[test]
     fn test_sbc_basic() {
         let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xA9, 0x10, 0xE9, 0x05, 0x00]); // LDA #0x10, SBC #0x05
+        cpu.load_and_run(vec![0xA9, 0x10, 0x38, 0xE9, 0x05, 0x00]); // LDA #0x10, SEC, SBC #0x05
         assert_eq!(cpu.register_a.0, 0x0B); // Result should be 0x0B (0x10 - 0x05 = 0x0B)
         assert_eq!(cpu.status.bit_1_is_set(), false); // Zero flag should be clear
         assert_eq!(cpu.status.bit_7_is_set(), false); // Negative flag should be clear
@@ -839,7 +1283,7 @@ mod test {
     #[test]
     fn test_sbc_borrow_required() {
         let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xA9, 0x05, 0xE9, 0x10, 0x00]); // LDA #0x05, SBC #0x10
+        cpu.load_and_run(vec![0xA9, 0x05, 0x38, 0xE9, 0x10, 0x00]); // LDA #0x05, SEC, SBC #0x10
         assert_eq!(cpu.register_a.0, 0xF5); // Result will wrap and be 0xF5 (0x05 - 0x10 = -0x0B or 0xF5 in two's complement)
         assert_eq!(cpu.status.bit_1_is_set(), false); // Zero flag should be clear
         assert_eq!(cpu.status.bit_7_is_set(), true); // Negative flag should be set because result is negative in two's complement
@@ -847,15 +1291,46 @@ mod test {
     }
 
     #[test]
-    fn test_sbc_with_initial_carry() {
+    fn test_sbc_with_borrow_in() {
         let mut cpu = CPU::new();
-        cpu.load_and_run(vec![0xA9, 0x10, 0x38, 0xE9, 0x05, 0x00]); // LDA #0x10, SEC (set carry), SBC #0x05
-        assert_eq!(cpu.register_a.0, 0x0A); // Result should be 0x0A (0x10 - 0x05 - 1(carry) = 0x0A)
+        cpu.load_and_run(vec![0xA9, 0x10, 0x18, 0xE9, 0x05, 0x00]); // LDA #0x10, CLC (borrow), SBC #0x05
+        assert_eq!(cpu.register_a.0, 0x0A); // Result should be 0x0A (0x10 - 0x05 - 1(borrow) = 0x0A)
         assert_eq!(cpu.status.bit_1_is_set(), false); // Zero flag should be clear
         assert_eq!(cpu.status.bit_7_is_set(), false); // Negative flag should be clear
         assert_eq!(cpu.status.bit_0_is_set(), true); // Carry flag should be set, as no borrow was required
     }
 
+    #[test]
+    fn test_sbc_overflow() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x80, 0x38, 0xE9, 0x01, 0x00]); // LDA #0x80, SEC, SBC #0x01
+        assert_eq!(cpu.register_a.0, 0x7F); // -128 - 1 doesn't fit in a signed byte
+        assert_eq!(cpu.status.bit_6_is_set(), true); // Overflow flag should be set
+        assert_eq!(cpu.status.bit_0_is_set(), true); // Carry flag should be set, as no borrow was required
+    }
+
+    #[test]
+    fn test_decimal_mode_on_nmos_6502() {
+        let mut cpu = CPU::new();
+        cpu.variant = Variant::Nmos6502;
+        cpu.load_and_run(vec![0xF8, 0x38, 0xA9, 0x58, 0x69, 0x46, 0x00]); // SED, SEC, LDA #0x58, ADC #0x46
+        assert_eq!(cpu.register_a.0, 0x05); // 58 + 46 + 1 = 105 in BCD
+        assert_eq!(cpu.status.bit_0_is_set(), true); // Carry out of the hundreds
+        assert_eq!(cpu.status.bit_1_is_set(), false); // Z follows the binary sum, 0x9F
+
+        cpu.load_and_run(vec![0xF8, 0x18, 0xA9, 0x46, 0xE9, 0x12, 0x00]); // SED, CLC, LDA #0x46, SBC #0x12
+        assert_eq!(cpu.register_a.0, 0x33); // 46 - 12 - 1 = 33 in BCD
+        assert_eq!(cpu.status.bit_0_is_set(), true); // No borrow
+    }
+
+    #[test]
+    fn test_decimal_flag_ignored_on_2a03() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x00]); // SED, CLC, LDA #0x09, ADC #0x01
+        assert_eq!(cpu.register_a.0, 0x0A);
+        assert_eq!(cpu.status.get_decimal_flag(), 1);
+    }
+
     #[test]
     fn test_sta_zero_page() {
         let mut cpu = CPU::new();
@@ -898,8 +1373,8 @@ mod test {
         let mut cpu = CPU::new();
         cpu.load_and_run(vec![0xa9, 0x05, 0x48, 0xba, 0x00]);
 
-        assert_eq!(cpu.register_x.0, 0xFE); // Assuming initial stack pointer was 0xFF
-        assert_eq!(cpu.memory.read(0x0100 + 0xFF), 0x05); // Memory at 0xFE should be 0x05
+        assert_eq!(cpu.register_x.0, 0xFC); // Power-on leaves the stack pointer at 0xFD
+        assert_eq!(cpu.memory.read(0x0100 + 0xFD), 0x05); // PHA wrote to 0x01FD
     }
 
     #[test]
@@ -921,7 +1396,8 @@ mod test {
             0x9A, 0x00,
         ]); // TXS
 
-        assert_eq!(cpu.register_s.0, 0x0A);
+        // Less the 3 bytes BRK pushed
+        assert_eq!(cpu.register_s.0, 0x07);
     }
 
     #[test]
@@ -934,4 +1410,615 @@ mod test {
 
         assert_eq!(cpu.register_a.0, 0x0A);
     }
+
+    #[test]
+    fn test_lax_and_sax() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xA9, 0x85, 0x85, 0x10, // LDA #0x85, STA $10
+            0xA7, 0x10, // LAX $10
+            0xA9, 0x0F, 0x87, 0x20, // LDA #0x0F, SAX $20
+            0x00,
+        ]);
+        assert_eq!(cpu.register_a.0, 0x0F);
+        assert_eq!(cpu.register_x.0, 0x85);
+        assert_eq!(cpu.memory.read(0x20), 0x05);
+    }
+
+    #[test]
+    fn test_dcp_and_isc() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xA9, 0x05, 0x85, 0x10, // LDA #0x05, STA $10
+            0xA9, 0x04, 0xC7, 0x10, // LDA #0x04, DCP $10
+            0x00,
+        ]);
+        assert_eq!(cpu.memory.read(0x10), 0x04);
+        assert!(cpu.status.bit_0_is_set()); // A >= memory
+        assert!(cpu.status.bit_1_is_set()); // A == memory
+
+        cpu.load_and_run(vec![
+            0xA9, 0x04, 0x85, 0x10, 0x38, // LDA #0x04, STA $10, SEC
+            0xE7, 0x10, // ISC $10
+            0x00,
+        ]);
+        assert_eq!(cpu.memory.read(0x10), 0x05);
+        assert_eq!(cpu.register_a.0, 0xFF); // 4 - 5
+        assert!(!cpu.status.bit_0_is_set()); // Borrow
+        assert!(cpu.status.bit_7_is_set());
+    }
+
+    #[test]
+    fn test_slo_and_rra() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0xA9, 0x81, 0x85, 0x10, // LDA #0x81, STA $10
+            0xA9, 0x01, 0x07, 0x10, // LDA #0x01, SLO $10
+            0x00,
+        ]);
+        assert_eq!(cpu.memory.read(0x10), 0x02);
+        assert_eq!(cpu.register_a.0, 0x03);
+        assert!(cpu.status.bit_0_is_set()); // Bit 7 shifted out
+
+        cpu.load_and_run(vec![
+            0xA9, 0x02, 0x85, 0x10, 0x38, // LDA #0x02, STA $10, SEC
+            0xA9, 0x03, 0x67, 0x10, // LDA #0x03, RRA $10
+            0x00,
+        ]);
+        assert_eq!(cpu.memory.read(0x10), 0x81); // Carry rotated in
+        assert_eq!(cpu.register_a.0, 0x84); // 0x03 + 0x81, carry out was 0
+    }
+
+    #[test]
+    fn test_immediate_combinations() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0xFF, 0x0B, 0x80, 0x00]); // LDA #0xFF, ANC #0x80
+        assert_eq!(cpu.register_a.0, 0x80);
+        assert!(cpu.status.bit_0_is_set()); // Copied from N
+
+        cpu.load_and_run(vec![0x38, 0xA9, 0xC0, 0x6B, 0xFF, 0x00]); // SEC, LDA #0xC0, ARR #0xFF
+        assert_eq!(cpu.register_a.0, 0xE0);
+        assert!(cpu.status.bit_0_is_set()); // Bit 6
+        assert!(!cpu.status.bit_6_is_set()); // Bit 6 XOR bit 5
+
+        cpu.load_and_run(vec![0xA9, 0x0F, 0xA2, 0xFC, 0xCB, 0x02, 0x00]); // LDA #0x0F, LDX #0xFC, AXS #0x02
+        assert_eq!(cpu.register_x.0, 0x0A);
+        assert!(cpu.status.bit_0_is_set());
+
+        cpu.load_and_run(vec![0x38, 0xA9, 0x10, 0xEB, 0x01, 0x00]); // SEC, LDA #0x10, SBC #0x01 via $EB
+        assert_eq!(cpu.register_a.0, 0x0F);
+    }
+
+    #[test]
+    fn test_unofficial_nops_skip_their_operands() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![
+            0x1A, // NOP
+            0x80, 0xFF, // NOP #0xFF
+            0x0C, 0x00, 0x20, // NOP $2000
+            0xE8, 0x00, // INX
+        ]);
+        assert_eq!(cpu.register_x.0, 1);
+        assert_eq!(brk_address(&cpu), 0x8007);
+    }
+
+    #[test]
+    fn test_kil_jams_until_reset() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xE8, 0x02, 0xE8, 0x00]); // INX, KIL, INX
+        assert_eq!(cpu.register_x.0, 1);
+        assert!(cpu.jammed);
+        assert_eq!(cpu.program_counter, 0x8001);
+        assert_eq!(cpu.step(), Step::Stopped(Stop::Jammed));
+        assert_eq!(cpu.program_counter, 0x8001);
+
+        cpu.reset();
+        assert!(!cpu.jammed);
+        assert_eq!(cpu.step(), Step::Instruction);
+    }
+
+    #[test]
+    fn test_reject_illegal_opcodes() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xE8, 0xA7, 0x10, 0x00]); // INX, LAX $10
+        cpu.reset();
+        cpu.reject_illegal_opcodes = true;
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 1);
+        assert_eq!(cpu.program_counter, 0x8001);
+        assert_eq!(cpu.step(), Step::Stopped(Stop::IllegalOpcode));
+    }
+
+    /// Where the BRK that ended `run` was, from the return address it
+    /// pushed, which skips the padding byte
+    fn brk_address(cpu: &CPU) -> u16 {
+        let sp = cpu.register_s.0 as u16;
+        cpu.memory.read_u16(0x0102 + sp).wrapping_sub(2)
+    }
+
+    fn cmos_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        cpu.variant = Variant::Wdc65C02;
+        cpu
+    }
+
+    #[test]
+    fn test_65c02_stack_and_store_instructions() {
+        let mut cpu = cmos_cpu();
+        cpu.load_and_run(vec![
+            0xA9, 0xFF, 0x85, 0x10, // LDA #0xFF, STA $10
+            0x64, 0x10, // STZ $10
+            0xA2, 0x42, 0xDA, // LDX #0x42, PHX
+            0x7A, // PLY
+            0x1A, 0x1A, // INC A, INC A
+            0x00,
+        ]);
+        assert_eq!(cpu.memory.read(0x10), 0x00);
+        assert_eq!(cpu.register_y.0, 0x42);
+        assert_eq!(cpu.register_a.0, 0x01);
+    }
+
+    #[test]
+    fn test_65c02_addressing_modes() {
+        let mut cpu = cmos_cpu();
+        cpu.load(vec![
+            0xB2, 0x20, // LDA ($20)
+            0xA2, 0x02, 0x7C, 0x00, 0x03, // LDX #0x02, JMP ($0300,X)
+        ]);
+        cpu.reset();
+        cpu.memory.write_u16(0x20, 0x0400);
+        cpu.memory.write(0x0400, 0x99);
+        cpu.memory.write_u16(0x0302, 0x0500);
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x99);
+        assert_eq!(brk_address(&cpu), 0x0500);
+    }
+
+    #[test]
+    fn test_65c02_branches() {
+        let mut cpu = cmos_cpu();
+        cpu.load_and_run(vec![
+            0x80, 0x01, // BRA +1
+            0xE8, // INX, skipped
+            0x0F, 0x10, 0x01, // BBR0 $10,+1
+            0xE8, // INX, skipped since $10 is 0
+            0x8F, 0x10, 0x01, // BBS0 $10,+1
+            0xC8, // INY
+            0x00,
+        ]);
+        assert_eq!(cpu.register_x.0, 0);
+        assert_eq!(cpu.register_y.0, 1);
+    }
+
+    #[test]
+    fn test_65c02_bit_instructions() {
+        let mut cpu = cmos_cpu();
+        cpu.load_and_run(vec![
+            0xA9, 0x0F, 0x85, 0x10, // LDA #0x0F, STA $10
+            0x87, 0x10, // SMB0 $10, no change
+            0xF7, 0x10, // SMB7 $10
+            0x37, 0x10, // RMB3 $10
+            0xA9, 0x30, 0x04, 0x10, // LDA #0x30, TSB $10
+            0xA9, 0x01, 0x14, 0x10, // LDA #0x01, TRB $10
+            0x00,
+        ]);
+        assert_eq!(cpu.memory.read(0x10), 0b1011_0110);
+        assert!(!cpu.status.bit_1_is_set()); // Bit 0 was set before TRB
+
+        cpu.load_and_run(vec![0xA9, 0x0F, 0x89, 0xF0, 0x00]); // LDA #0x0F, BIT #0xF0
+        assert!(cpu.status.bit_1_is_set());
+        assert!(!cpu.status.bit_7_is_set()); // N and V untouched by BIT #imm
+    }
+
+    #[test]
+    fn test_65c02_jmp_indirect_crosses_page() {
+        for (variant, target) in [(Variant::Nmos6502, 0x1234), (Variant::Wdc65C02, 0x5634)] {
+            let mut cpu = CPU::new();
+            cpu.variant = variant;
+            cpu.load(vec![0x6C, 0xFF, 0x02]); // JMP ($02FF)
+            cpu.reset();
+            cpu.memory.write(0x02FF, 0x34);
+            cpu.memory.write(0x0200, 0x12);
+            cpu.memory.write(0x0300, 0x56);
+            cpu.step();
+            assert_eq!(cpu.program_counter, target);
+        }
+    }
+
+    #[test]
+    fn test_65c02_decimal_flags() {
+        let mut cpu = cmos_cpu();
+        cpu.load_and_run(vec![0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01, 0x00]); // SED, CLC, LDA #0x99, ADC #0x01
+        assert_eq!(cpu.register_a.0, 0x00);
+        assert!(cpu.status.bit_0_is_set());
+        assert!(cpu.status.bit_1_is_set()); // Z from the BCD result
+
+        cpu.variant = Variant::Nmos6502;
+        cpu.load_and_run(vec![0xF8, 0x18, 0xA9, 0x99, 0x69, 0x01, 0x00]);
+        assert!(!cpu.status.bit_1_is_set()); // Z from the binary sum, 0x9A
+    }
+
+    #[test]
+    fn test_65c02_wai_and_stp() {
+        let mut cpu = cmos_cpu();
+        cpu.load_and_run(vec![0xCB, 0xE8, 0x00]); // WAI, INX
+        assert!(cpu.waiting);
+        assert_eq!(cpu.program_counter, 0x8001);
+        assert_eq!(cpu.step(), Step::Stopped(Stop::Waiting));
+        cpu.waiting = false;
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 1);
+
+        cpu.load_and_run(vec![0xDB, 0xE8, 0x00]); // STP, INX
+        assert!(cpu.jammed);
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.register_x.0, 0);
+    }
+
+    #[test]
+    fn test_65c02_undefined_opcodes_are_nops() {
+        let mut cpu = cmos_cpu();
+        cpu.load_and_run(vec![
+            0x03, // NOP
+            0x02, 0xFF, // NOP #0xFF
+            0xDC, 0x00, 0x20, // NOP $2000
+            0xE8, 0x00, // INX
+        ]);
+        assert_eq!(cpu.register_x.0, 1);
+        assert_eq!(brk_address(&cpu), 0x8007);
+    }
+
+    fn step_cycles(cpu: &mut CPU) -> u64 {
+        let before = cpu.cycles;
+        cpu.step();
+        cpu.cycles - before
+    }
+
+    #[test]
+    fn test_cycles_come_from_bus_accesses() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![
+            0xA2, 0x20, // LDX #$20
+            0xBD, 0xF0, 0x80, // LDA $80F0,X
+            0xBD, 0x00, 0x80, // LDA $8000,X
+            0x9D, 0x00, 0x02, // STA $0200,X
+            0xFE, 0x00, 0x02, // INC $0200,X
+            0x20, 0x12, 0x80, // JSR $8012
+            0x00, // BRK
+            0x60, // RTS
+        ]);
+        cpu.reset();
+        let cycles: Vec<u64> = (0..7).map(|_| step_cycles(&mut cpu)).collect();
+        // The first load crosses a page, the store and INC always pay for it
+        assert_eq!(cycles, vec![2, 5, 4, 5, 7, 6, 6]);
+        assert_eq!(cpu.program_counter, 0x8011);
+    }
+
+    #[test]
+    fn test_65c02_cycles() {
+        let program = vec![
+            0xA2, 0x01, // LDX #$01
+            0x1E, 0x00, 0x02, // ASL $0200,X
+            0x7E, 0xFF, 0x02, // ROR $02FF,X
+            0xFE, 0x00, 0x02, // INC $0200,X
+            0xF8, // SED
+            0x69, 0x01, // ADC #$01
+            0xE5, 0x10, // SBC $10
+            0xD8, // CLD
+            0x69, 0x01, // ADC #$01
+            0x00, // BRK
+        ];
+        let mut cpu = cmos_cpu();
+        cpu.load(program.clone());
+        cpu.reset();
+        let cycles: Vec<u64> = (0..9).map(|_| step_cycles(&mut cpu)).collect();
+        // Shifts only pay for a page cross, decimal ADC and SBC take a cycle more
+        assert_eq!(cycles, vec![2, 6, 7, 7, 2, 3, 4, 2, 2]);
+
+        let mut cpu = CPU::new();
+        cpu.variant = Variant::Nmos6502;
+        cpu.load(program);
+        cpu.reset();
+        let cycles: Vec<u64> = (0..9).map(|_| step_cycles(&mut cpu)).collect();
+        assert_eq!(cycles, vec![2, 7, 7, 7, 2, 2, 3, 2, 2]);
+    }
+
+    #[test]
+    fn test_brk_enters_the_irq_handler() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x38, 0x00, 0xEA]); // SEC, BRK
+        cpu.memory.write_u16(CPU::IRQ_VECTOR, 0x9000);
+        cpu.power_on();
+        cpu.step();
+        assert_eq!(step_cycles(&mut cpu), 7);
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.register_s.0, 0xFA);
+        assert_eq!(cpu.memory.read_u16(0x01FC), 0x8003);
+        assert_eq!(cpu.memory.read(0x01FB), 0b0011_0101);
+        assert_eq!(cpu.status.get_interupt_disable_flag(), 1);
+    }
+
+    #[test]
+    fn test_interrupts_are_reported_apart_from_instructions() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xE8, 0xE8, 0x00]); // INX, INX, BRK
+        cpu.memory.load_at(0x9000, &[0xC8, 0x40]); // nmi: INY; RTI
+        cpu.memory.write_u16(CPU::NMI_VECTOR, 0x9000);
+        cpu.power_on();
+        cpu.trigger_nmi();
+        assert_eq!(cpu.step(), Step::Instruction);
+        assert_eq!(cpu.step(), Step::Interrupt(CPU::NMI_VECTOR));
+        assert_eq!(cpu.program_counter, 0x9000);
+
+        // The callback only sees the instructions that run
+        cpu.power_on();
+        cpu.trigger_nmi();
+        let mut seen = Vec::new();
+        cpu.run_with_callback(|cpu| seen.push(cpu.program_counter));
+        assert_eq!(seen, vec![0x8000, 0x9000, 0x9001, 0x8001, 0x8002]);
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xF0, 0x00, 0xD0, 0x7C]); // BEQ +0, BNE +$7C
+        cpu.memory.load_at(0x8080, &[0xD0, 0x7E]); // BNE +$7E
+        cpu.reset();
+        let cycles: Vec<u64> = (0..3).map(|_| step_cycles(&mut cpu)).collect();
+        assert_eq!(cycles, vec![2, 3, 4]);
+        assert_eq!(cpu.program_counter, 0x8100);
+    }
+
+    #[test]
+    fn test_irq_is_polled_before_the_last_cycle() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x58, 0xE8, 0xE8, 0x00]); // CLI, INX, INX
+        cpu.memory.load_at(0x9000, &[0xC8, 0x40]); // INY, RTI
+        cpu.memory.write_u16(CPU::IRQ_VECTOR, 0x9000);
+        cpu.reset();
+        cpu.status.set_interupt_disable_flag(true);
+        cpu.irq_line = true;
+
+        // CLI clears I on its last cycle, after the poll, so the
+        // interrupt comes after the next instruction
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(step_cycles(&mut cpu), 7);
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert_eq!(cpu.memory.read_u16(0x01FC), 0x8002);
+        assert_eq!(cpu.memory.read(0x01FB), 0x20); // B clear
+        assert_eq!(cpu.status.get_interupt_disable_flag(), 1);
+
+        cpu.irq_line = false;
+        cpu.run();
+        assert_eq!((cpu.register_x.0, cpu.register_y.0), (2, 1));
+    }
+
+    #[test]
+    fn test_nmi_ignores_interrupt_disable() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xE8, 0xE8, 0x00]); // INX, INX
+        cpu.memory.load_at(0x9000, &[0xC8, 0x40]); // INY, RTI
+        cpu.memory.write_u16(CPU::NMI_VECTOR, 0x9000);
+        cpu.reset();
+        cpu.status.set_interupt_disable_flag(true);
+        cpu.irq_line = true;
+        cpu.trigger_nmi();
+
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x9000);
+        assert!(!cpu.nmi_pending);
+
+        // Back from the NMI, I is set again and the IRQ stays masked
+        cpu.run();
+        assert_eq!((cpu.register_x.0, cpu.register_y.0), (2, 1));
+    }
+
+    #[test]
+    fn test_power_on_and_reset() {
+        let mut cpu = CPU::new();
+        cpu.ram_init = RamInit::Ones;
+        cpu.load(vec![0xA9, 0x42, 0x58, 0x48, 0x00]); // LDA #$42, CLI, PHA
+        cpu.power_on();
+        assert_eq!(cpu.register_s.0, 0xFD);
+        assert_eq!(cpu.status.0, 0x34);
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.memory.read(0x07FF), 0xFF);
+        assert_eq!(cpu.memory.read(0x8000), 0xA9);
+
+        // A warm reset only moves S and sets I. PHA and BRK pushed 4 bytes.
+        cpu.run();
+        let cycles = cpu.cycles;
+        cpu.reset();
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.register_a.0, 0x42);
+        assert_eq!(cpu.register_s.0, 0xF6);
+        assert_eq!(cpu.status.get_interupt_disable_flag(), 1);
+        assert_eq!(cpu.memory.read(0x01FD), 0x42);
+        assert_eq!(cpu.cycles, cycles + 7);
+
+        cpu.ram_init = RamInit::Random(1);
+        cpu.power_on();
+        let first = cpu.memory.0[..0x0800].to_vec();
+        cpu.power_on();
+        assert_eq!(cpu.memory.0[..0x0800], first[..]);
+        assert!(first.iter().any(|&b| b != first[0]));
+        cpu.ram_init = RamInit::Random(2);
+        cpu.power_on();
+        assert_ne!(cpu.memory.0[..0x0800], first[..]);
+    }
+
+    #[test]
+    fn test_reset_reaches_the_cartridge_and_input_ports() {
+        use crate::cartridge::Mirroring;
+        use crate::input::joypad::JoypadButton;
+        use crate::input::InputMode;
+
+        let mut prg_rom = vec![0xEA; 0x4000];
+        prg_rom[0x3FFC..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80]);
+        let rom = Rom {
+            prg_rom,
+            chr_rom: vec![],
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+        };
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom).unwrap();
+        let mut input = InputPorts::new(InputMode::Standard);
+        cpu.power_on_machine(&mut input);
+        assert_eq!(cpu.program_counter, 0x8000);
+
+        // A stray write lands in the flat memory, but the ROM comes back
+        cpu.memory.write(0xC000, 0x00);
+        input.joypads[0].set_button_pressed(JoypadButton::A, true);
+        input.write(1);
+        cpu.reset_machine(&mut input);
+        assert_eq!(cpu.memory.read(0xC000), 0xEA);
+        // With the strobe cleared the next read shifts past A
+        assert_eq!(input.read(InputPorts::PORT_1), 1);
+        assert_eq!(input.read(InputPorts::PORT_1), 0);
+
+        cpu.power_on_machine(&mut input);
+        assert_eq!(input.read(InputPorts::PORT_1), 0);
+    }
+
+    #[test]
+    fn test_break_and_bit_5_only_exist_on_the_stack() {
+        let mut cpu = CPU::new();
+        // LDA #$00, PHA, PLP, PHP, PLA, LDX #$FF, TXA, PHA, PLP
+        cpu.load(vec![
+            0xA9, 0x00, 0x48, 0x28, 0x08, 0x68, 0xA2, 0xFF, 0x8A, 0x48, 0x28, 0x00,
+        ]);
+        cpu.reset();
+        assert_eq!(cpu.status.0, ProcessorStatus::POWER_ON);
+
+        // PLP of 0 clears every flag but leaves B and bit 5 alone, and PHP
+        // pushes them both set
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.status.0, 0b0011_0000);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.register_a.0, 0b0011_0000);
+
+        cpu.status.set(Flag::Break, false);
+        assert_eq!(cpu.status.0, 0b0011_0000);
+        cpu.run();
+        assert_eq!(cpu.status.0, 0b1111_1111);
+        assert!(cpu.status.is_set(Flag::Decimal));
+        assert_eq!(ProcessorStatus::new(0).0, 0b0011_0000);
+        assert_eq!(cpu.status.pushed(false), 0b1110_1111);
+        assert_eq!(cpu.status.pushed(true), 0b1111_1111);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0xA2, 0x10, 0x85, 0x20, 0x00]); // LDA #0x42, LDX #0x10, STA $20
+        let state = cpu.save_state();
+
+        cpu.register_a.0 = 0;
+        cpu.register_x.0 = 0;
+        cpu.memory.write(0x20, 0);
+        cpu.cycles = 0;
+        cpu.load_state(&state).unwrap();
+
+        assert_eq!(cpu.register_a.0, 0x42);
+        assert_eq!(cpu.register_x.0, 0x10);
+        assert_eq!(cpu.memory.read(0x20), 0x42);
+        assert_eq!(brk_address(&cpu), 0x8006);
+        assert_eq!(cpu.cycles, 7 + 2 + 2 + 3 + 7);
+    }
+
+    #[test]
+    fn test_load_state_rejects_other_rom() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0x00]);
+        let state = cpu.save_state();
+
+        let mut other = CPU::new();
+        other.load_and_run(vec![0xA9, 0x43, 0x00]);
+        let result = other.load_state(&state);
+
+        assert!(matches!(result, Err(SavestateError::RomMismatch { .. })));
+        assert_eq!(other.register_a.0, 0x43);
+    }
+
+    #[test]
+    fn test_load_state_rejects_other_version() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0x00]);
+        let mut state = cpu.save_state();
+        state[4] = 0xFF;
+
+        let result = cpu.load_state(&state);
+        assert_eq!(result, Err(SavestateError::UnsupportedVersion(0x00FF)));
+    }
+
+    #[test]
+    fn test_load_state_rejects_truncated_state() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0x00]);
+        let state = cpu.save_state();
+
+        let result = cpu.load_state(&state[..state.len() - 1]);
+        assert_eq!(result, Err(SavestateError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_load_state_rejects_trailing_data() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0x00]);
+        let mut body = StateWriter::new();
+        Savestate::save(&cpu, &mut body);
+        body.write_u8(0);
+        let state = savestate::seal(body, cpu.rom_hash);
+        assert_eq!(cpu.load_state(&state), Err(SavestateError::TrailingData));
+
+        // A whole-machine state has the input ports in front of the CPU
+        let input = InputPorts::new(crate::input::InputMode::Standard);
+        let state = cpu.save_machine(&input);
+        assert!(cpu.load_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_load_state_rejects_other_variant() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0x00]);
+        let state = cpu.save_state();
+
+        cpu.variant = Variant::Wdc65C02;
+        cpu.register_a.0 = 0;
+        let result = cpu.load_state(&state);
+        assert_eq!(
+            result,
+            Err(SavestateError::VariantMismatch {
+                expected: Variant::Wdc65C02,
+                found: Variant::Ricoh2A03,
+            })
+        );
+        assert_eq!(cpu.register_a.0, 0);
+    }
+
+    #[test]
+    fn test_machine_state_includes_input_ports() {
+        use crate::input::joypad::JoypadButton;
+        use crate::input::InputMode;
+
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x42, 0x00]);
+        let mut input = InputPorts::new(InputMode::FourScore);
+        input.joypads[2].set_button_pressed(JoypadButton::Start, true);
+        let state = cpu.save_machine(&input);
+
+        let mut restored = InputPorts::new(InputMode::Standard);
+        cpu.register_a.0 = 0;
+        cpu.load_machine(&mut restored, &state).unwrap();
+        assert_eq!(cpu.register_a.0, 0x42);
+        assert_eq!(restored.mode, InputMode::FourScore);
+        assert!(restored.joypads[2].is_pressed(JoypadButton::Start));
+    }
 }
@@ -1,6 +1,6 @@
 use crate::cpu::CPU;
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
     Accumulator,
@@ -11,45 +11,135 @@ pub enum AddressingMode {
     Absolute,
     Absolute_X,
     Absolute_Y,
+    Indirect,
     Indirect_X,
     Indirect_Y,
     NoneAddressing,
     Relative,
+    /// `($12)`, 65C02 only
+    ZeroPage_Indirect,
+    /// `($1234,X)`, the 65C02's JMP through a table
+    Absolute_Indirect_X,
+    /// `$12,label`, the zero page byte and branch of BBR and BBS
+    ZeroPage_Relative,
+}
+
+/// What an instruction does at its effective address, which decides the
+/// dummy reads of the indexed modes
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operation {
+    Read,
+    Write,
+    /// Read-modify-write, e.g. INC or ASL on memory
+    Modify,
 }
 
 impl AddressingMode {
+    /// Fetches the operand bytes and any pointer of the running
+    /// instruction, one bus cycle each and with the dummy reads the 6502
+    /// makes on the way, returning the effective address. The program
+    /// counter ends up past the instruction.
+    pub fn fetch_address(cpu: &mut CPU, mode: &AddressingMode, operation: Operation) -> u16 {
+        match mode {
+            AddressingMode::Immediate => {
+                let addr = cpu.program_counter;
+                cpu.program_counter = addr.wrapping_add(1);
+                addr
+            }
+
+            AddressingMode::ZeroPage | AddressingMode::ZeroPage_Relative => cpu.fetch() as u16,
+
+            AddressingMode::Absolute => cpu.fetch_u16(),
+
+            AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+                let base = cpu.fetch();
+                // The base address is read while the index is added
+                cpu.dummy_read(base as u16);
+                let index = match mode {
+                    AddressingMode::ZeroPage_X => cpu.register_x.0,
+                    _ => cpu.register_y.0,
+                };
+                base.wrapping_add(index) as u16
+            }
+
+            AddressingMode::Absolute_X => {
+                let base = cpu.fetch_u16();
+                indexed(cpu, base, cpu.register_x.0, operation)
+            }
+            AddressingMode::Absolute_Y => {
+                let base = cpu.fetch_u16();
+                indexed(cpu, base, cpu.register_y.0, operation)
+            }
+
+            AddressingMode::Indirect_X => {
+                let base = cpu.fetch();
+                cpu.dummy_read(base as u16);
+                read_zero_page_pointer(cpu, base.wrapping_add(cpu.register_x.0))
+            }
+            AddressingMode::Indirect_Y => {
+                let pointer = cpu.fetch();
+                let base = read_zero_page_pointer(cpu, pointer);
+                indexed(cpu, base, cpu.register_y.0, operation)
+            }
+
+            AddressingMode::ZeroPage_Indirect => {
+                let pointer = cpu.fetch();
+                read_zero_page_pointer(cpu, pointer)
+            }
+
+            _ => {
+                panic!("mode {:?} is not supported", mode);
+            }
+        }
+    }
+
+    /// Fetches the operand of a reading instruction, as `fetch_address`
+    /// does, and reads it. Immediate operands are fetched like the rest of
+    /// the instruction.
+    pub fn read_operand(cpu: &mut CPU, mode: &AddressingMode) -> u8 {
+        match mode {
+            AddressingMode::Immediate => cpu.fetch(),
+            _ => {
+                let addr = AddressingMode::fetch_address(cpu, mode, Operation::Read);
+                cpu.mem_read(addr)
+            }
+        }
+    }
+
     pub fn get_operand_address(cpu: &CPU, mode: &AddressingMode) -> u16 {
+        AddressingMode::get_absolute_address(cpu, mode, cpu.program_counter)
+    }
+
+    /// Effective address for an instruction whose operand bytes start at
+    /// `addr`, so tooling can resolve instructions that haven't run yet
+    pub fn get_absolute_address(cpu: &CPU, mode: &AddressingMode, addr: u16) -> u16 {
         match mode {
-            AddressingMode::Immediate => cpu.program_counter,
+            AddressingMode::Immediate => addr,
 
-            AddressingMode::ZeroPage => cpu.memory.read(cpu.program_counter) as u16,
+            AddressingMode::ZeroPage => cpu.memory.read(addr) as u16,
 
-            AddressingMode::Absolute => cpu.memory.read_u16(cpu.program_counter),
+            AddressingMode::Absolute => cpu.memory.read_u16(addr),
 
             AddressingMode::ZeroPage_X => {
-                let pos = cpu.memory.read(cpu.program_counter);
-                let addr = pos.wrapping_add(cpu.register_x.0) as u16;
-                addr
+                let pos = cpu.memory.read(addr);
+                pos.wrapping_add(cpu.register_x.0) as u16
             }
             AddressingMode::ZeroPage_Y => {
-                let pos = cpu.memory.read(cpu.program_counter);
-                let addr = pos.wrapping_add(cpu.register_y.0) as u16;
-                addr
+                let pos = cpu.memory.read(addr);
+                pos.wrapping_add(cpu.register_y.0) as u16
             }
 
             AddressingMode::Absolute_X => {
-                let base = cpu.memory.read_u16(cpu.program_counter);
-                let addr = base.wrapping_add(cpu.register_x.0 as u16);
-                addr
+                let base = cpu.memory.read_u16(addr);
+                base.wrapping_add(cpu.register_x.0 as u16)
             }
             AddressingMode::Absolute_Y => {
-                let base = cpu.memory.read_u16(cpu.program_counter);
-                let addr = base.wrapping_add(cpu.register_y.0 as u16);
-                addr
+                let base = cpu.memory.read_u16(addr);
+                base.wrapping_add(cpu.register_y.0 as u16)
             }
 
             AddressingMode::Indirect_X => {
-                let base = cpu.memory.read(cpu.program_counter);
+                let base = cpu.memory.read(addr);
 
                 let ptr: u8 = (base as u8).wrapping_add(cpu.register_x.0);
                 let lo = cpu.memory.read(ptr as u16);
@@ -57,7 +147,7 @@ impl AddressingMode {
                 (hi as u16) << 8 | (lo as u16)
             }
             AddressingMode::Indirect_Y => {
-                let base = cpu.memory.read(cpu.program_counter);
+                let base = cpu.memory.read(addr);
 
                 let lo = cpu.memory.read(base as u16);
                 let hi = cpu.memory.read((base as u8).wrapping_add(1) as u16);
@@ -65,7 +155,16 @@ impl AddressingMode {
                 let deref = deref_base.wrapping_add(cpu.register_y.0 as u16);
                 deref
             }
-            AddressingMode::Relative => cpu.program_counter,
+            AddressingMode::Relative => addr,
+
+            AddressingMode::ZeroPage_Indirect => {
+                let base = cpu.memory.read(addr);
+
+                let lo = cpu.memory.read(base as u16);
+                let hi = cpu.memory.read(base.wrapping_add(1) as u16);
+                (hi as u16) << 8 | (lo as u16)
+            }
+            AddressingMode::ZeroPage_Relative => cpu.memory.read(addr) as u16,
 
             _ => {
                 panic!("mode {:?} is not supported", mode);
@@ -73,3 +172,23 @@ impl AddressingMode {
         }
     }
 }
+
+/// Adds the index to the low byte first, so the 6502 reads from the
+/// unfixed address while it carries into the high byte. Reads only make
+/// that dummy read when there is a carry; stores and read-modify-writes
+/// always do.
+fn indexed(cpu: &mut CPU, base: u16, index: u8, operation: Operation) -> u16 {
+    let addr = base.wrapping_add(index as u16);
+    let crossed = addr & 0xFF00 != base & 0xFF00;
+    if crossed || operation != Operation::Read {
+        cpu.dummy_read((base & 0xFF00) | (addr & 0x00FF));
+    }
+    addr
+}
+
+/// Pointers in the zero page wrap around instead of crossing into page one
+fn read_zero_page_pointer(cpu: &mut CPU, pointer: u8) -> u16 {
+    let lo = cpu.mem_read(pointer as u16);
+    let hi = cpu.mem_read(pointer.wrapping_add(1) as u16);
+    u16::from_le_bytes([lo, hi])
+}
@@ -0,0 +1,864 @@
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::memory::Memory;
+use crate::cpu::opscodes::OpCode;
+use crate::cpu::variant::Variant;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Where code goes when the source has no `.org`
+pub const DEFAULT_ORIGIN: u16 = 0x8000;
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        AsmError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Bytes assembled contiguously from one `.org`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Assembly {
+    pub segments: Vec<Segment>,
+    pub labels: BTreeMap<String, u16>,
+}
+
+impl Assembly {
+    /// The bytes of every segment laid out from the first origin, with any
+    /// gaps between segments zero-filled
+    pub fn bytes(&self) -> Vec<u8> {
+        let start = match self.segments.first() {
+            Some(segment) => segment.origin as usize,
+            None => return Vec::new(),
+        };
+        let mut bytes = Vec::new();
+        for segment in &self.segments {
+            let offset = (segment.origin as usize).saturating_sub(start);
+            let end = offset + segment.bytes.len();
+            if bytes.len() < end {
+                bytes.resize(end, 0);
+            }
+            bytes[offset..end].copy_from_slice(&segment.bytes);
+        }
+        bytes
+    }
+
+    pub fn load_into(&self, memory: &mut Memory) {
+        for segment in &self.segments {
+            memory.load_at(segment.origin, &segment.bytes);
+        }
+    }
+}
+
+/// Assembles 6502 source with the instructions of the default variant
+///
+/// Supports `label:` definitions, `NAME = expr` constants, `.org`, `.byte`
+/// (numbers and "strings") and `.word`, and `;` comments. Operands are
+/// expressions over numbers (`$FF`, `%1010`, `42`, `'a'`), labels and `*`
+/// (the current address), with `+ - * / & | ^ << >>`, unary `-` and `~`,
+/// `<`/`>` for the low/high byte, and `[ ]` for grouping.
+///
+/// Addresses that are known and fit in one byte use zero page modes. A
+/// forward reference is assumed to need two bytes, since its value isn't
+/// known on the first pass.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    assemble_as(source, Variant::default())
+}
+
+/// Assembles with the instructions of `variant`, so the 65C02 gets BRA,
+/// STZ, `(zp)` operands and the rest
+pub fn assemble_as(source: &str, variant: Variant) -> Result<Assembly, AsmError> {
+    assemble_with(source, &BTreeMap::new(), variant)
+}
+
+/// Assembles `source` at `origin` for `variant`, e.g. to patch memory,
+/// with `labels` predefined so operands can refer to them
+pub fn assemble_at(
+    origin: u16,
+    source: &str,
+    labels: &BTreeMap<String, u16>,
+    variant: Variant,
+) -> Result<Vec<u8>, AsmError> {
+    let source = format!(".org ${:04X}\n{}", origin, source);
+    let assembly = assemble_with(&source, labels, variant)?;
+    Ok(assembly.bytes())
+}
+
+fn assemble_with(
+    source: &str,
+    labels: &BTreeMap<String, u16>,
+    variant: Variant,
+) -> Result<Assembly, AsmError> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(index, text)| parse_line(index + 1, text))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut assembler = Assembler {
+        symbols: labels.clone(),
+        variant,
+        ..Assembler::default()
+    };
+    assembler.pass(&lines, false)?;
+    assembler.pass(&lines, true)?;
+    Ok(Assembly {
+        segments: assembler.segments,
+        labels: assembler.symbols,
+    })
+}
+
+struct Line<'a> {
+    number: usize,
+    label: Option<&'a str>,
+    statement: Statement<'a>,
+}
+
+enum Statement<'a> {
+    Empty,
+    Constant(&'a str),
+    Directive(&'a str, &'a str),
+    Instruction(String, &'a str),
+}
+
+#[derive(Default)]
+struct Assembler {
+    symbols: BTreeMap<String, u16>,
+    /// Addressing mode picked for each instruction on the first pass, so
+    /// sizes don't change once forward references resolve
+    modes: HashMap<usize, AddressingMode>,
+    segments: Vec<Segment>,
+    pc: u16,
+    variant: Variant,
+}
+
+impl Assembler {
+    /// Walks the source once. The first pass only collects symbols and
+    /// sizes; the second emits bytes and requires every symbol to resolve.
+    fn pass(&mut self, lines: &[Line], emit: bool) -> Result<(), AsmError> {
+        self.pc = DEFAULT_ORIGIN;
+        self.segments.clear();
+
+        for (index, line) in lines.iter().enumerate() {
+            let number = line.number;
+            if let Some(label) = line.label {
+                if let Statement::Constant(expr) = line.statement {
+                    // Left undefined until the second pass if it refers
+                    // ahead, so instructions using it are sized as absolute
+                    if let Some(value) = self.evaluate(expr, number, emit)? {
+                        self.define(label, value, number, emit)?;
+                    }
+                    continue;
+                }
+                self.define(label, self.pc, number, emit)?;
+            }
+
+            match line.statement {
+                Statement::Empty | Statement::Constant(_) => {}
+                Statement::Directive(name, args) => self.directive(name, args, number, emit)?,
+                Statement::Instruction(ref mnemonic, operand) => {
+                    self.instruction(index, mnemonic, operand, number, emit)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, label: &str, value: u16, line: usize, emit: bool) -> Result<(), AsmError> {
+        if !emit && self.symbols.contains_key(label) {
+            return Err(AsmError::new(line, format!("`{}` is defined twice", label)));
+        }
+        self.symbols.insert(String::from(label), value);
+        Ok(())
+    }
+
+    fn directive(
+        &mut self,
+        name: &str,
+        args: &str,
+        line: usize,
+        emit: bool,
+    ) -> Result<(), AsmError> {
+        match name.to_ascii_lowercase().as_str() {
+            ".org" => {
+                let origin = self.evaluate(args, line, true)?.unwrap_or(0);
+                self.pc = origin;
+                self.segments.push(Segment {
+                    origin,
+                    bytes: Vec::new(),
+                });
+            }
+            ".byte" | ".db" => {
+                for item in split_list(args) {
+                    if let Some(text) = item.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                        self.emit(text.as_bytes());
+                        continue;
+                    }
+                    let value = self.evaluate_wide(item, line, emit)?.unwrap_or(0);
+                    if emit && !(-0x80..=0xFF).contains(&value) {
+                        return Err(AsmError::new(
+                            line,
+                            format!("`{}` doesn't fit in a byte", item),
+                        ));
+                    }
+                    self.emit(&[value as u8]);
+                }
+            }
+            ".word" | ".dw" => {
+                for item in split_list(args) {
+                    let value = self.evaluate(item, line, emit)?.unwrap_or(0);
+                    self.emit(&value.to_le_bytes());
+                }
+            }
+            _ => return Err(AsmError::new(line, format!("unknown directive `{}`", name))),
+        }
+        Ok(())
+    }
+
+    fn instruction(
+        &mut self,
+        index: usize,
+        mnemonic: &str,
+        operand: &str,
+        line: usize,
+        emit: bool,
+    ) -> Result<(), AsmError> {
+        let candidates: Vec<&'static OpCode> = self
+            .variant
+            .opcodes()
+            .iter()
+            .copied()
+            .filter(|opcode| opcode.mnemonic == mnemonic)
+            .collect();
+        if candidates.is_empty() {
+            return Err(AsmError::new(
+                line,
+                format!("unknown instruction `{}`", mnemonic),
+            ));
+        }
+        let supports = |mode: AddressingMode| candidates.iter().any(|opcode| opcode.mode == mode);
+
+        let (syntax, expr) = parse_operand(mnemonic, operand);
+        let value = match expr {
+            Some(expr) => self.evaluate(expr, line, emit)?,
+            None => None,
+        };
+        let target = match syntax {
+            Syntax::BitBranch(target) => self.evaluate(target, line, emit)?.unwrap_or(0),
+            _ => 0,
+        };
+
+        let mode = match self.modes.get(&index) {
+            Some(mode) => *mode,
+            None => {
+                let zero_page = value.is_some_and(|value| value <= 0xFF);
+                let mode = match syntax {
+                    Syntax::Implied if supports(AddressingMode::NoneAddressing) => {
+                        AddressingMode::NoneAddressing
+                    }
+                    Syntax::Implied | Syntax::Accumulator => AddressingMode::Accumulator,
+                    Syntax::Immediate => AddressingMode::Immediate,
+                    Syntax::Indirect if supports(AddressingMode::Indirect) => {
+                        AddressingMode::Indirect
+                    }
+                    Syntax::Indirect => AddressingMode::ZeroPage_Indirect,
+                    Syntax::IndirectX if supports(AddressingMode::Absolute_Indirect_X) => {
+                        AddressingMode::Absolute_Indirect_X
+                    }
+                    Syntax::IndirectX => AddressingMode::Indirect_X,
+                    Syntax::IndirectY => AddressingMode::Indirect_Y,
+                    Syntax::BitBranch(_) => AddressingMode::ZeroPage_Relative,
+                    Syntax::Direct if supports(AddressingMode::Relative) => {
+                        AddressingMode::Relative
+                    }
+                    Syntax::Direct if zero_page && supports(AddressingMode::ZeroPage) => {
+                        AddressingMode::ZeroPage
+                    }
+                    Syntax::Direct => AddressingMode::Absolute,
+                    Syntax::IndexedX if zero_page && supports(AddressingMode::ZeroPage_X) => {
+                        AddressingMode::ZeroPage_X
+                    }
+                    Syntax::IndexedX => AddressingMode::Absolute_X,
+                    Syntax::IndexedY if zero_page && supports(AddressingMode::ZeroPage_Y) => {
+                        AddressingMode::ZeroPage_Y
+                    }
+                    Syntax::IndexedY => AddressingMode::Absolute_Y,
+                };
+                self.modes.insert(index, mode);
+                mode
+            }
+        };
+
+        // Undocumented opcodes only when there's no documented one
+        let opcode = match candidates
+            .iter()
+            .filter(|opcode| opcode.mode == mode)
+            .min_by_key(|opcode| opcode.illegal)
+        {
+            Some(opcode) => *opcode,
+            None => {
+                return Err(AsmError::new(
+                    line,
+                    format!("{} doesn't support {:?} addressing", mnemonic, mode),
+                ))
+            }
+        };
+
+        let value = value.unwrap_or(0);
+        let mut bytes = vec![opcode.code];
+        match (opcode.len, mode) {
+            (2, AddressingMode::Relative) => {
+                let offset = value as i32 - (self.pc as i32 + 2);
+                if emit && !(-128..=127).contains(&offset) {
+                    return Err(AsmError::new(
+                        line,
+                        format!("branch out of range ({})", offset),
+                    ));
+                }
+                bytes.push(offset as u8);
+            }
+            (3, AddressingMode::ZeroPage_Relative) => {
+                let offset = target as i32 - (self.pc as i32 + 3);
+                if emit && value > 0xFF {
+                    return Err(AsmError::new(
+                        line,
+                        format!("${:X} doesn't fit in a byte", value),
+                    ));
+                }
+                if emit && !(-128..=127).contains(&offset) {
+                    return Err(AsmError::new(
+                        line,
+                        format!("branch out of range ({})", offset),
+                    ));
+                }
+                bytes.extend_from_slice(&[value as u8, offset as u8]);
+            }
+            (2, _) => {
+                if emit && value > 0xFF {
+                    return Err(AsmError::new(
+                        line,
+                        format!("${:X} doesn't fit in a byte", value),
+                    ));
+                }
+                bytes.push(value as u8);
+            }
+            (3, _) => bytes.extend_from_slice(&value.to_le_bytes()),
+            _ => {}
+        }
+        self.emit(&bytes);
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        if self.segments.is_empty() {
+            self.segments.push(Segment {
+                origin: self.pc,
+                bytes: Vec::new(),
+            });
+        }
+        if let Some(segment) = self.segments.last_mut() {
+            segment.bytes.extend_from_slice(bytes);
+        }
+        self.pc = self.pc.wrapping_add(bytes.len() as u16);
+    }
+
+    /// `Ok(None)` when the expression refers to a symbol that isn't defined
+    /// yet and `required` is false. Negative values down to -$8000 wrap
+    /// around; anything else outside 16 bits is an error.
+    fn evaluate(&self, expr: &str, line: usize, required: bool) -> Result<Option<u16>, AsmError> {
+        match self.evaluate_wide(expr, line, required)? {
+            Some(value) if !(-0x8000..=0xFFFF).contains(&value) => Err(AsmError::new(
+                line,
+                format!("`{}` doesn't fit in 16 bits", expr),
+            )),
+            value => Ok(value.map(|value| value as u16)),
+        }
+    }
+
+    /// `evaluate` without the range check
+    fn evaluate_wide(
+        &self,
+        expr: &str,
+        line: usize,
+        required: bool,
+    ) -> Result<Option<i64>, AsmError> {
+        let mut parser = ExprParser {
+            tokens: tokenize(expr).map_err(|message| AsmError::new(line, message))?,
+            position: 0,
+            symbols: &self.symbols,
+            pc: self.pc,
+            unresolved: None,
+        };
+        let value = parser
+            .expression()
+            .map_err(|message| AsmError::new(line, message))?;
+        if parser.position != parser.tokens.len() {
+            return Err(AsmError::new(
+                line,
+                format!("unexpected text in `{}`", expr),
+            ));
+        }
+        match parser.unresolved {
+            Some(name) if required => {
+                Err(AsmError::new(line, format!("`{}` is not defined", name)))
+            }
+            Some(_) => Ok(None),
+            None => Ok(Some(value)),
+        }
+    }
+}
+
+fn parse_line(number: usize, text: &str) -> Result<Line<'_>, AsmError> {
+    let text = match text.find(';') {
+        Some(comment) if !in_string(text, comment) => &text[..comment],
+        _ => text,
+    };
+    let mut rest = text.trim();
+    let mut label = None;
+
+    if let Some((name, tail)) = rest.split_once('=') {
+        let name = name.trim();
+        if is_identifier(name) {
+            return Ok(Line {
+                number,
+                label: Some(name),
+                statement: Statement::Constant(tail.trim()),
+            });
+        }
+    }
+    if let Some((name, tail)) = rest.split_once(':') {
+        if is_identifier(name.trim()) {
+            label = Some(name.trim());
+            rest = tail.trim();
+        }
+    }
+
+    let (head, args) = match rest.split_once(char::is_whitespace) {
+        Some((head, args)) => (head, args.trim()),
+        None => (rest, ""),
+    };
+    let statement = if head.is_empty() {
+        Statement::Empty
+    } else if head.starts_with('.') {
+        Statement::Directive(head, args)
+    } else if is_identifier(head) {
+        Statement::Instruction(head.to_ascii_uppercase(), args)
+    } else {
+        return Err(AsmError::new(number, format!("can't parse `{}`", rest)));
+    };
+    Ok(Line {
+        number,
+        label,
+        statement,
+    })
+}
+
+fn in_string(text: &str, position: usize) -> bool {
+    text[..position].matches(['"', '\'']).count() % 2 == 1
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn split_list(args: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, c) in args.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                items.push(args[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(args[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+enum Syntax<'a> {
+    Implied,
+    Accumulator,
+    Immediate,
+    Direct,
+    IndexedX,
+    IndexedY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    /// `zp,target` of BBR and BBS, holding the target
+    BitBranch(&'a str),
+}
+
+/// Splits an operand into its addressing syntax and the expression inside
+fn parse_operand<'a>(mnemonic: &str, operand: &'a str) -> (Syntax<'a>, Option<&'a str>) {
+    let upper = operand.to_ascii_uppercase().replace(' ', "");
+    if operand.is_empty() {
+        return (Syntax::Implied, None);
+    }
+    if upper == "A" {
+        return (Syntax::Accumulator, None);
+    }
+    if let Some(expr) = operand.strip_prefix('#') {
+        return (Syntax::Immediate, Some(expr.trim()));
+    }
+
+    let inner = |end: usize| operand[1..end].trim();
+    if upper.starts_with('(') {
+        if upper.ends_with(",X)") {
+            let end = operand.rfind(',').unwrap_or(operand.len());
+            return (Syntax::IndirectX, Some(inner(end)));
+        }
+        if upper.ends_with("),Y") {
+            let end = operand.rfind(')').unwrap_or(operand.len());
+            return (Syntax::IndirectY, Some(inner(end)));
+        }
+        if upper.ends_with(')') {
+            return (Syntax::Indirect, Some(inner(operand.len() - 1)));
+        }
+    }
+    if mnemonic.starts_with("BBR") || mnemonic.starts_with("BBS") {
+        if let Some((zero_page, target)) = operand.split_once(',') {
+            return (Syntax::BitBranch(target.trim()), Some(zero_page.trim()));
+        }
+    }
+    if let Some(end) = operand.rfind(',') {
+        match upper.rsplit(',').next() {
+            Some("X") => return (Syntax::IndexedX, Some(operand[..end].trim())),
+            Some("Y") => return (Syntax::IndexedY, Some(operand[..end].trim())),
+            _ => {}
+        }
+    }
+    (Syntax::Direct, Some(operand))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Operator(&'static str),
+}
+
+const OPERATORS: [&str; 14] = [
+    "<<", ">>", "+", "-", "*", "/", "&", "|", "^", "~", "<", ">", "[", "]",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = expr.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..].iter().collect();
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' && chars.get(i + 2) == Some(&'\'') {
+            tokens.push(Token::Number(chars[i + 1] as i64));
+            i += 3;
+        } else if c == '$' || c == '%' || c.is_ascii_digit() {
+            let (radix, start) = match c {
+                '$' => (16, i + 1),
+                '%' => (2, i + 1),
+                _ => (10, i),
+            };
+            let mut end = start;
+            while end < chars.len() && chars[end].is_digit(radix) {
+                end += 1;
+            }
+            let digits: String = chars[start..end].iter().collect();
+            let value = i64::from_str_radix(&digits, radix)
+                .map_err(|_| format!("bad number in `{}`", expr))?;
+            tokens.push(Token::Number(value));
+            i = end;
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '@' {
+            let mut end = i + 1;
+            while end < chars.len() && (chars[end].is_ascii_alphanumeric() || chars[end] == '_') {
+                end += 1;
+            }
+            tokens.push(Token::Symbol(chars[i..end].iter().collect()));
+            i = end;
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(operator));
+            i += operator.len();
+        } else {
+            return Err(format!("unexpected `{}` in `{}`", c, expr));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Precedence climbing over `|`, `^`, `&`, shifts, `+ -`, `* /`, then
+/// unary operators
+struct ExprParser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a BTreeMap<String, u16>,
+    pc: u16,
+    /// First symbol that had no value yet
+    unresolved: Option<String>,
+}
+
+const BINARY_LEVELS: [&[&str]; 6] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/"],
+];
+
+impl ExprParser<'_> {
+    fn expression(&mut self) -> Result<i64, String> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == BINARY_LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(Token::Operator(op)) = self.tokens.get(self.position) {
+            if !BINARY_LEVELS[level].contains(op) {
+                break;
+            }
+            let op = *op;
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            value = match op {
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "<<" => value << (rhs & 0x3F),
+                ">>" => value >> (rhs & 0x3F),
+                "+" => value + rhs,
+                "-" => value - rhs,
+                "*" => value * rhs,
+                _ if rhs == 0 && self.unresolved.is_none() => {
+                    return Err(String::from("division by zero"))
+                }
+                _ => value.checked_div(rhs).unwrap_or(0),
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Operator("-")) => Ok(-self.unary()?),
+            Some(Token::Operator("~")) => Ok(!self.unary()? & 0xFFFF),
+            Some(Token::Operator("<")) => Ok(self.unary()? & 0xFF),
+            Some(Token::Operator(">")) => Ok((self.unary()? >> 8) & 0xFF),
+            Some(Token::Operator("*")) => Ok(self.pc as i64),
+            Some(Token::Operator("[")) => {
+                let value = self.expression()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Operator("]")) => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(String::from("missing `]`")),
+                }
+            }
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Symbol(name)) => match self.symbols.get(&name) {
+                Some(value) => Ok(*value as i64),
+                None => {
+                    self.unresolved.get_or_insert(name);
+                    Ok(0)
+                }
+            },
+            _ => Err(String::from("expected a value")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().bytes()
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let source = "
+            LDA #$30
+            LDA $10
+            LDA $10,X
+            LDX $10,Y
+            LDA $1234
+            LDA $1234,X
+            LDA $1234,Y
+            LDA ($20,X)
+            LDA ($20),Y
+            JMP ($0200)
+            ASL
+            ROL A
+            TAX
+        ";
+        assert_eq!(
+            bytes(source),
+            vec![
+                0xA9, 0x30, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xBD, 0x34, 0x12,
+                0xB9, 0x34, 0x12, 0xA1, 0x20, 0xB1, 0x20, 0x6C, 0x00, 0x02, 0x0A, 0x2A, 0xAA,
+            ]
+        );
+        // STA has no zero page,Y form, so it widens to absolute
+        assert_eq!(bytes("STA $10,Y"), vec![0x99, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn test_65c02_instructions() {
+        let source = "
+            LDA ($10)
+            BRA *
+            STZ $20
+            JMP ($1234,X)
+            BBR0 $12,*
+            NOP
+        ";
+        assert_eq!(
+            assemble_as(source, Variant::Wdc65C02).unwrap().bytes(),
+            vec![0xB2, 0x10, 0x80, 0xFE, 0x64, 0x20, 0x7C, 0x34, 0x12, 0x0F, 0x12, 0xFD, 0xEA]
+        );
+        assert_eq!(
+            assemble("LDA ($10)").unwrap_err().message,
+            "LDA doesn't support ZeroPage_Indirect addressing"
+        );
+    }
+
+    #[test]
+    fn test_labels_and_forward_references() {
+        let source = "
+            start:  LDX #3
+            loop:   DEX
+                    BNE loop
+                    JSR sub
+                    BRK
+            sub:    RTS
+        ";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(
+            assembly.bytes(),
+            vec![0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x20, 0x09, 0x80, 0x00, 0x60]
+        );
+        assert_eq!(assembly.labels["sub"], 0x8009);
+
+        // A forward zero page reference is sized as absolute
+        assert_eq!(bytes("LDA var\nvar: BRK"), vec![0xAD, 0x03, 0x80, 0x00]);
+        // So is one through a constant defined before the label
+        assert_eq!(
+            bytes("X1 = later\n LDA X1\nlater: NOP"),
+            vec![0xAD, 0x03, 0x80, 0xEA]
+        );
+    }
+
+    #[test]
+    fn test_directives_and_expressions() {
+        let source = "
+            SCREEN = $2000
+            .org $C000
+            table:  .byte 1, %10, 'A', \"hi\", <SCREEN, >[SCREEN + $1FF]
+                    .word table, * + 2, -1
+                    LDA #~$0F & $FF
+                    LDA table + 2 * 3
+            .org $FFFC
+                    .word table
+        ";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.segments[0].origin, 0xC000);
+        assert_eq!(
+            assembly.segments[0].bytes,
+            vec![
+                0x01, 0x02, 0x41, 0x68, 0x69, 0x00, 0x21, 0x00, 0xC0, 0x0B, 0xC0, 0xFF, 0xFF, 0xA9,
+                0xF0, 0xAD, 0x06, 0xC0,
+            ]
+        );
+        assert_eq!(
+            assembly.segments[1],
+            Segment {
+                origin: 0xFFFC,
+                bytes: vec![0x00, 0xC0]
+            }
+        );
+        assert_eq!(assembly.bytes().len(), 0x3FFE);
+
+        let mut memory = Memory::new();
+        assembly.load_into(&mut memory);
+        assert_eq!(memory.read_u16(0xFFFC), 0xC000);
+
+        assert_eq!(bytes(".byte -1, -128, 255"), vec![0xFF, 0x80, 0xFF]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(
+            error("FOO #1"),
+            AsmError::new(1, "unknown instruction `FOO`")
+        );
+        assert_eq!(
+            error("\nLDA missing"),
+            AsmError::new(2, "`missing` is not defined")
+        );
+        assert_eq!(error("LDA #$100").message, "$100 doesn't fit in a byte");
+        assert_eq!(
+            error("LDA $12345").message,
+            "`$12345` doesn't fit in 16 bits"
+        );
+        assert_eq!(error(".byte -129").message, "`-129` doesn't fit in a byte");
+        assert_eq!(error("a: NOP\na: NOP").message, "`a` is defined twice");
+        assert_eq!(
+            error("JMP ($10),Y").message,
+            "JMP doesn't support Indirect_Y addressing"
+        );
+        assert!(error("BNE far\n.org $9000\nfar: RTS")
+            .message
+            .starts_with("branch out of range"));
+    }
+
+    #[test]
+    fn test_assemble_at() {
+        let labels = BTreeMap::from([(String::from("reset"), 0xC000)]);
+        assert_eq!(
+            assemble_at(0x8010, "BNE $8010", &labels, Variant::default()),
+            Ok(vec![0xD0, 0xFE])
+        );
+        assert_eq!(
+            assemble_at(0x8010, "JMP reset", &labels, Variant::default()),
+            Ok(vec![0x4C, 0x00, 0xC0])
+        );
+    }
+}
@@ -0,0 +1,535 @@
+use crate::cdl::CodeDataLog;
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::memory::Memory;
+use crate::cpu::opscodes::OpCode;
+use crate::cpu::variant::Variant;
+use crate::cpu::CPU;
+use crate::symbols::SymbolTable;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Anything the disassembler can read bytes from without side effects
+pub trait MemoryReader {
+    fn peek(&self, addr: u16) -> u8;
+
+    fn peek_u16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.peek(addr), self.peek(addr.wrapping_add(1))])
+    }
+}
+
+impl MemoryReader for Memory {
+    fn peek(&self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+}
+
+impl<F: Fn(u16) -> u8> MemoryReader for F {
+    fn peek(&self, addr: u16) -> u8 {
+        self(addr)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub opcode: &'static OpCode,
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.opcode.mnemonic
+    }
+
+    /// Address a branch, JMP or JSR transfers control to, if it is static
+    pub fn target(&self) -> Option<u16> {
+        let opcode = self.opcode;
+        match opcode.mode {
+            AddressingMode::Relative => {
+                let offset = self.bytes[1] as i8 as i16;
+                Some(self.address.wrapping_add(2).wrapping_add(offset as u16))
+            }
+            AddressingMode::ZeroPage_Relative => {
+                let offset = self.bytes[2] as i8 as i16;
+                Some(self.address.wrapping_add(3).wrapping_add(offset as u16))
+            }
+            AddressingMode::Absolute if matches!(opcode.mnemonic, "JMP" | "JSR") => {
+                Some(self.operand_u16())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether execution can continue with the next instruction in memory
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self.opcode.mnemonic,
+            "JMP" | "BRA" | "RTS" | "RTI" | "BRK" | "KIL" | "STP"
+        )
+    }
+
+    pub fn operand(&self) -> String {
+        self.format_operand(|addr, digits| format!("${:0width$X}", addr, width = digits))
+    }
+
+    /// Like `operand`, but with addresses that have a label shown by name
+    pub fn operand_with(&self, symbols: &SymbolTable) -> String {
+        self.format_operand(|addr, digits| symbols.format_address(addr, digits))
+    }
+
+    /// The `Display` line with labelled operands and the comment, if any,
+    /// attached to the instruction's own address
+    pub fn display_with(&self, symbols: &SymbolTable) -> String {
+        let line = self.format_line(&self.operand_with(symbols));
+        match symbols.comment(self.address) {
+            Some(comment) => format!("{:<32}; {}", line, comment.replace('\n', " ")),
+            None => line,
+        }
+    }
+
+    fn format_operand<F: Fn(u16, usize) -> String>(&self, address: F) -> String {
+        let zero_page = || address(self.bytes[1] as u16, 2);
+        let absolute = || address(self.operand_u16(), 4);
+
+        match self.opcode.mode {
+            AddressingMode::Accumulator => String::from("A"),
+            AddressingMode::Immediate => format!("#${:02X}", self.bytes[1]),
+            AddressingMode::ZeroPage => zero_page(),
+            AddressingMode::ZeroPage_X => format!("{},X", zero_page()),
+            AddressingMode::ZeroPage_Y => format!("{},Y", zero_page()),
+            AddressingMode::Absolute => absolute(),
+            AddressingMode::Absolute_X => format!("{},X", absolute()),
+            AddressingMode::Absolute_Y => format!("{},Y", absolute()),
+            AddressingMode::Indirect => format!("({})", absolute()),
+            AddressingMode::Indirect_X => format!("({},X)", zero_page()),
+            AddressingMode::Indirect_Y => format!("({}),Y", zero_page()),
+            AddressingMode::Relative => address(self.target().unwrap(), 4),
+            AddressingMode::NoneAddressing => String::new(),
+            AddressingMode::ZeroPage_Indirect => format!("({})", zero_page()),
+            AddressingMode::Absolute_Indirect_X => format!("({},X)", absolute()),
+            AddressingMode::ZeroPage_Relative => {
+                format!("{},{}", zero_page(), address(self.target().unwrap(), 4))
+            }
+        }
+    }
+
+    fn format_line(&self, operand: &str) -> String {
+        format_line(self.address, &self.bytes, self.mnemonic(), operand)
+    }
+
+    fn operand_u16(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats as `8000  A9 30     LDA #$30`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format_line(&self.operand()))
+    }
+}
+
+/// One line of a `CodeMap` listing: a traced instruction, or a byte that
+/// no traced path reached
+#[derive(Clone, Debug, PartialEq)]
+pub enum Line {
+    Code(Instruction),
+    Data { address: u16, byte: u8 },
+}
+
+impl Line {
+    pub fn address(&self) -> u16 {
+        match self {
+            Line::Code(instruction) => instruction.address,
+            Line::Data { address, .. } => *address,
+        }
+    }
+
+    pub fn len(&self) -> u16 {
+        match self {
+            Line::Code(instruction) => instruction.len(),
+            Line::Data { .. } => 1,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for Line {
+    /// Formats code as `Instruction` does and data as `8006  FF        .byte $FF`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Code(instruction) => write!(f, "{}", instruction),
+            Line::Data { address, byte } => {
+                let operand = format!("${:02X}", byte);
+                write!(f, "{}", format_line(*address, &[*byte], ".byte", &operand))
+            }
+        }
+    }
+}
+
+fn format_line(address: u16, bytes: &[u8], mnemonic: &str, operand: &str) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let text = format!("{} {}", mnemonic, operand);
+    format!(
+        "{:04X}  {:<8}  {}",
+        address,
+        bytes.join(" "),
+        text.trim_end()
+    )
+}
+
+/// Decodes the instruction at `addr`
+pub fn disassemble_one<M: MemoryReader>(memory: &M, addr: u16) -> Instruction {
+    disassemble_one_as(memory, addr, Variant::default())
+}
+
+/// Decodes the instruction at `addr` with the opcodes of `variant`
+pub fn disassemble_one_as<M: MemoryReader>(memory: &M, addr: u16, variant: Variant) -> Instruction {
+    let code = memory.peek(addr);
+    let opcode = variant.opcodes()[code as usize];
+    let len = opcode.len as u16;
+
+    Instruction {
+        address: addr,
+        bytes: (0..len)
+            .map(|i| memory.peek(addr.wrapping_add(i)))
+            .collect(),
+        opcode,
+    }
+}
+
+/// Linear sweep over `start..=end`, decoding every byte as code
+pub fn disassemble<M: MemoryReader>(memory: &M, start: u16, end: u16) -> Vec<Instruction> {
+    disassemble_as(memory, start, end, Variant::default())
+}
+
+/// `disassemble` with the opcodes of `variant`
+pub fn disassemble_as<M: MemoryReader>(
+    memory: &M,
+    start: u16,
+    end: u16,
+    variant: Variant,
+) -> Vec<Instruction> {
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+
+    while addr <= end as u32 {
+        let instruction = disassemble_one_as(memory, addr as u16, variant);
+        addr += instruction.len() as u32;
+        lines.push(instruction);
+    }
+    lines
+}
+
+/// Instructions reachable by following control flow from a set of entry
+/// points, which separates code from data sharing the same address space
+#[derive(Debug, Default)]
+pub struct CodeMap {
+    pub instructions: BTreeMap<u16, Instruction>,
+}
+
+impl CodeMap {
+    /// Recursive descent starting at the NMI, reset and IRQ handlers
+    pub fn from_vectors<M: MemoryReader>(memory: &M, variant: Variant) -> Self {
+        let entry_points = [
+            memory.peek_u16(CPU::NMI_VECTOR),
+            memory.peek_u16(CPU::RESET_VECTOR),
+            memory.peek_u16(CPU::IRQ_VECTOR),
+        ];
+        CodeMap::trace(memory, &entry_points, variant)
+    }
+
+    /// Follows branches, jumps and subroutine calls from `entry_points`.
+    /// Indirect jumps and returns end a path since their targets are only
+    /// known at run time.
+    pub fn trace<M: MemoryReader>(memory: &M, entry_points: &[u16], variant: Variant) -> Self {
+        let mut code_map = CodeMap::default();
+        let mut pending: Vec<u16> = entry_points.to_vec();
+
+        while let Some(addr) = pending.pop() {
+            if code_map.is_code(addr) {
+                continue;
+            }
+            let instruction = disassemble_one_as(memory, addr, variant);
+            if let Some(target) = instruction.target() {
+                pending.push(target);
+            }
+            if instruction.falls_through() {
+                pending.push(addr.wrapping_add(instruction.len()));
+            }
+            code_map.instructions.insert(addr, instruction);
+        }
+        code_map
+    }
+
+    /// Instructions logged as executed in a Code/Data Log. Logs written by
+    /// FCEUX don't mark opcodes, so there each run of code bytes is decoded
+    /// from its first byte. ROM mirrored into several windows is listed
+    /// at its first address only.
+    pub fn from_cdl<M: MemoryReader>(memory: &M, cdl: &CodeDataLog, variant: Variant) -> Self {
+        let mut code_map = CodeMap::default();
+        let mut next = 0;
+
+        for (addr, offset) in cdl.prg_map.iter() {
+            if cdl.prg_map.address_of(offset) != Some(addr) {
+                continue;
+            }
+            let start = cdl.is_opcode(addr) || (cdl.is_code(addr) && addr as u32 >= next);
+            if !start {
+                continue;
+            }
+            let instruction = disassemble_one_as(memory, addr, variant);
+            next = addr as u32 + instruction.len() as u32;
+            code_map.instructions.insert(addr, instruction);
+        }
+        code_map
+    }
+
+    /// Whether `addr` is the first byte or an operand of a traced instruction
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.instructions
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(start, instruction)| addr - start < instruction.len())
+    }
+
+    pub fn entry_points(&self) -> BTreeSet<u16> {
+        self.instructions.keys().copied().collect()
+    }
+
+    /// Lists `start..=end`, showing traced code as instructions and
+    /// everything else as `.byte` data
+    pub fn listing<M: MemoryReader>(&self, memory: &M, start: u16, end: u16) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut addr = start as u32;
+
+        while addr <= end as u32 {
+            let line = match self.instructions.get(&(addr as u16)) {
+                Some(instruction) => Line::Code(instruction.clone()),
+                None => Line::Data {
+                    address: addr as u16,
+                    byte: memory.peek(addr as u16),
+                },
+            };
+            addr += line.len() as u32;
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::PrgMap;
+
+    fn memory_with(origin: u16, bytes: &[u8]) -> Memory {
+        let mut memory = Memory::new();
+        for (offset, byte) in bytes.iter().enumerate() {
+            memory.write(origin + offset as u16, *byte);
+        }
+        memory
+    }
+
+    fn text<T: ToString>(lines: &[T]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_operand_formats() {
+        let memory = memory_with(
+            0x8000,
+            &[
+                0xA9, 0x30, // LDA #$30
+                0x75, 0x10, // ADC $10,X
+                0xB6, 0x20, // LDX $20,Y
+                0x91, 0x40, // STA ($40),Y
+                0xA1, 0x50, // LDA ($50,X)
+                0x3D, 0x34, 0x12, // AND $1234,X
+                0x6C, 0xFF, 0x02, // JMP ($02FF)
+                0x0A, // ASL A
+                0xE8, // INX
+            ],
+        );
+
+        assert_eq!(
+            text(&disassemble(&memory, 0x8000, 0x8011)),
+            vec![
+                "8000  A9 30     LDA #$30",
+                "8002  75 10     ADC $10,X",
+                "8004  B6 20     LDX $20,Y",
+                "8006  91 40     STA ($40),Y",
+                "8008  A1 50     LDA ($50,X)",
+                "800A  3D 34 12  AND $1234,X",
+                "800D  6C FF 02  JMP ($02FF)",
+                "8010  0A        ASL A",
+                "8011  E8        INX",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_relative_branches_resolve_to_targets() {
+        let memory = memory_with(0x8000, &[0xCA, 0xD0, 0xFD, 0xF0, 0x02]); // DEX, BNE -3, BEQ +2
+        assert_eq!(
+            text(&disassemble(&memory, 0x8000, 0x8003)),
+            vec![
+                "8000  CA        DEX",
+                "8001  D0 FD     BNE $8000",
+                "8003  F0 02     BEQ $8007"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operands_resolve_to_labels() {
+        let memory = memory_with(0x8000, &[0x20, 0x10, 0x80, 0xB5, 0x20, 0xD0, 0xF9]);
+        let mut symbols = SymbolTable::new();
+        symbols.define(0x8010, "update", None);
+        symbols.define(0x0020, "scroll", None);
+        symbols.define(0x8000, "main", Some("entry"));
+
+        let lines: Vec<String> = disassemble(&memory, 0x8000, 0x8005)
+            .iter()
+            .map(|instruction| instruction.display_with(&symbols))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "8000  20 10 80  JSR update      ; entry",
+                "8003  B5 20     LDA scroll,X",
+                "8005  D0 F9     BNE main",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_illegal_opcodes() {
+        let memory = memory_with(0x8000, &[0xA7, 0x10, 0x02]);
+        assert_eq!(
+            text(&disassemble(&memory, 0x8000, 0x8002)),
+            vec!["8000  A7 10     LAX $10", "8002  02        KIL"]
+        );
+    }
+
+    #[test]
+    fn test_65c02_opcodes() {
+        let memory = memory_with(
+            0x8000,
+            &[
+                0xB2, 0x20, // LDA ($20)
+                0x7C, 0x00, 0x90, // JMP ($9000,X)
+                0x8F, 0x10, 0xFD, // BBS0 $10,$8005
+                0xA7, 0x10, // SMB2 $10
+            ],
+        );
+        let lines: Vec<String> = [0x8000, 0x8002, 0x8005, 0x8008]
+            .iter()
+            .map(|addr| disassemble_one_as(&memory, *addr, Variant::Wdc65C02).to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "8000  B2 20     LDA ($20)",
+                "8002  7C 00 90  JMP ($9000,X)",
+                "8005  8F 10 FD  BBS0 $10,$8005",
+                "8008  A7 10     SMB2 $10",
+            ]
+        );
+
+        // BRA is an unconditional branch on the 65C02 but a two-byte NOP
+        // that falls through into the data on the NMOS 6502
+        let memory = memory_with(0x9000, &[0x80, 0x01, 0xFF, 0x60]);
+        let code_map = CodeMap::trace(&memory, &[0x9000], Variant::Wdc65C02);
+        assert!(code_map.is_code(0x9003));
+        assert!(!code_map.is_code(0x9002));
+        let code_map = CodeMap::trace(&memory, &[0x9000], Variant::Nmos6502);
+        assert!(code_map.is_code(0x9002));
+    }
+
+    #[test]
+    fn test_reader_from_closure() {
+        let rom = [0xA2, 0x01, 0x60];
+        let reader = |addr: u16| rom[(addr - 0xC000) as usize];
+        assert_eq!(
+            disassemble_one(&reader, 0xC000).to_string(),
+            "C000  A2 01     LDX #$01"
+        );
+    }
+
+    #[test]
+    fn test_recursive_descent_separates_code_from_data() {
+        let mut memory = memory_with(
+            0x8000,
+            &[
+                0x20, 0x09, 0x80, // JSR $8009
+                0x4C, 0x00, 0x80, // JMP $8000
+                0xFF, 0xAA, 0x55, // data
+                0xA9, 0x01, // LDA #$01
+                0xF0, 0x01, // BEQ $800E
+                0x60, // RTS
+                0x40, // RTI
+            ],
+        );
+        memory.write_u16(CPU::RESET_VECTOR, 0x8000);
+        memory.write_u16(CPU::NMI_VECTOR, 0x800E);
+        memory.write_u16(CPU::IRQ_VECTOR, 0x800E);
+
+        let code_map = CodeMap::from_vectors(&memory, Variant::default());
+        assert!(code_map.is_code(0x8000));
+        assert!(code_map.is_code(0x8002));
+        assert!(!code_map.is_code(0x8006));
+        assert!(!code_map.is_code(0x8008));
+        assert!(code_map.is_code(0x800D));
+        assert!(code_map.is_code(0x800E));
+
+        let listing = text(&code_map.listing(&memory, 0x8003, 0x800A));
+        assert_eq!(
+            listing,
+            vec![
+                "8003  4C 00 80  JMP $8000",
+                "8006  FF        .byte $FF",
+                "8007  AA        .byte $AA",
+                "8008  55        .byte $55",
+                "8009  A9 01     LDA #$01",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_map_from_code_data_log() {
+        // LDA $8006; JMP $8000, followed by a data byte
+        let memory = memory_with(0x8000, &[0xAD, 0x06, 0x80, 0x4C, 0x00, 0x80, 0x42]);
+        let mut cdl = CodeDataLog::new(0x4000, 0, PrgMap::nrom(0x4000));
+        let cpu_view = |pc| {
+            let mut cpu = crate::cpu::CPU::new();
+            cpu.memory = memory;
+            cpu.program_counter = pc;
+            cpu
+        };
+        cdl.log_instruction(&cpu_view(0x8000));
+        cdl.log_instruction(&cpu_view(0x8003));
+
+        let code_map = CodeMap::from_cdl(&memory, &cdl, Variant::default());
+        assert_eq!(code_map.entry_points(), BTreeSet::from([0x8000, 0x8003]));
+        assert!(!code_map.is_code(0x8006));
+
+        // Without opcode marks, runs of code are decoded from their start
+        for flags in cdl.prg.iter_mut() {
+            *flags &= !crate::cdl::OPCODE;
+        }
+        let code_map = CodeMap::from_cdl(&memory, &cdl, Variant::default());
+        assert_eq!(code_map.entry_points(), BTreeSet::from([0x8000, 0x8003]));
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// Opcode and operand bytes fetched from the program counter
+    Execute,
+    /// A read the CPU makes and throws away, e.g. the byte after an
+    /// implied instruction or the unfixed address of an indexed store
+    DummyRead,
+}
+
+/// One access the CPU made to memory
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+    /// CPU cycle the access happens on. Every cycle is one access, so
+    /// consecutive accesses are one cycle apart.
+    pub cycle: u64,
+}
+
+/// Returned by `MemoryHooks::add`, to remove the observer again
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+/// Observers are `Send` so a CPU can still be moved to another thread
+type Observer = Box<dyn FnMut(&MemoryAccess) + Send>;
+
+/// Observers of every memory access the CPU makes
+///
+/// Only compiled with the `memory-hooks` feature. Without it the CPU reads
+/// and writes memory directly, so the default build pays nothing for them.
+#[derive(Default)]
+pub struct MemoryHooks {
+    observers: Vec<(ObserverId, Observer)>,
+    next_id: u64,
+}
+
+impl MemoryHooks {
+    pub fn new() -> Self {
+        MemoryHooks::default()
+    }
+
+    /// Calls `observer` for each access from now on, in the order the CPU
+    /// makes them
+    pub fn add<F>(&mut self, observer: F) -> ObserverId
+    where
+        F: FnMut(&MemoryAccess) + Send + 'static,
+    {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.push((id, Box::new(observer)));
+        id
+    }
+
+    /// Removes an observer, returning whether it was registered
+    pub fn remove(&mut self, id: ObserverId) -> bool {
+        let before = self.observers.len();
+        self.observers.retain(|(observer_id, _)| *observer_id != id);
+        self.observers.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.observers.clear();
+    }
+
+    #[inline]
+    pub fn notify(&mut self, addr: u16, value: u8, kind: AccessKind, cycle: u64) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let access = MemoryAccess {
+            addr,
+            value,
+            kind,
+            cycle,
+        };
+        for (_, observer) in self.observers.iter_mut() {
+            observer(&access);
+        }
+    }
+}
+
+impl fmt::Debug for MemoryHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryHooks")
+            .field("observers", &self.observers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::variant::Variant;
+    use crate::cpu::CPU;
+    use std::sync::{Arc, Mutex};
+
+    fn record(cpu: &mut CPU) -> (ObserverId, Arc<Mutex<Vec<MemoryAccess>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let id = cpu
+            .hooks
+            .add(move |access| sink.lock().unwrap().push(*access));
+        (id, log)
+    }
+
+    fn access(addr: u16, value: u8, kind: AccessKind, cycle: u64) -> MemoryAccess {
+        MemoryAccess {
+            addr,
+            value,
+            kind,
+            cycle,
+        }
+    }
+
+    #[test]
+    fn test_reports_accesses_in_order() {
+        let mut cpu = CPU::new();
+        // LDA #$05; STA ($10),Y; INX
+        cpu.load(vec![0xA9, 0x05, 0x91, 0x10, 0xE8, 0x00]);
+        cpu.reset();
+        cpu.memory.write_u16(0x0010, 0x0300);
+        let (_, log) = record(&mut cpu);
+        for _ in 0..3 {
+            cpu.step();
+        }
+
+        use AccessKind::*;
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                access(0x8000, 0xA9, Execute, 7),
+                access(0x8001, 0x05, Execute, 8),
+                access(0x8002, 0x91, Execute, 9),
+                access(0x8003, 0x10, Execute, 10),
+                access(0x0010, 0x00, Read, 11),
+                access(0x0011, 0x03, Read, 12),
+                access(0x0300, 0x00, DummyRead, 13),
+                access(0x0300, 0x05, Write, 14),
+                access(0x8004, 0xE8, Execute, 15),
+                access(0x8005, 0x00, DummyRead, 16),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_modify_write_writes_twice() {
+        use AccessKind::*;
+        for (variant, second) in [
+            (Variant::Ricoh2A03, access(0x0010, 0x41, Write, 10)),
+            (Variant::Wdc65C02, access(0x0010, 0x41, DummyRead, 10)),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.variant = variant;
+            cpu.load(vec![0xE6, 0x10]); // INC $10
+            cpu.reset();
+            cpu.memory.write(0x0010, 0x41);
+            let (_, log) = record(&mut cpu);
+            cpu.step();
+
+            assert_eq!(
+                *log.lock().unwrap(),
+                vec![
+                    access(0x8000, 0xE6, Execute, 7),
+                    access(0x8001, 0x10, Execute, 8),
+                    access(0x0010, 0x41, Read, 9),
+                    second,
+                    access(0x0010, 0x42, Write, 11),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_indexed_reads_from_the_unfixed_address() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xBD, 0xF0, 0x80]); // LDA $80F0,X
+        cpu.reset();
+        cpu.register_x.0 = 0x20;
+        let (_, log) = record(&mut cpu);
+        cpu.step();
+
+        let log = log.lock().unwrap();
+        assert_eq!(log[3], access(0x8010, 0x00, AccessKind::DummyRead, 10));
+        assert_eq!(log[4], access(0x8110, 0x00, AccessKind::Read, 11));
+    }
+
+    #[test]
+    fn test_stack_accesses_and_removal() {
+        let mut cpu = CPU::new();
+        // PHA; PLA
+        cpu.load(vec![0x48, 0x68, 0x00]);
+        cpu.reset();
+        cpu.register_a.0 = 0x42;
+        let (id, log) = record(&mut cpu);
+
+        cpu.step();
+        assert_eq!(
+            log.lock().unwrap()[2],
+            access(0x01FD, 0x42, AccessKind::Write, 9)
+        );
+
+        assert!(cpu.hooks.remove(id));
+        assert!(!cpu.hooks.remove(id));
+        assert!(cpu.hooks.is_empty());
+        cpu.step();
+        assert_eq!(log.lock().unwrap().len(), 3);
+        assert_eq!(cpu.register_a.0, 0x42);
+    }
+}
@@ -1,9 +1,48 @@
+use crate::savestate::{Savestate, SavestateError, StateReader, StateWriter};
+
 #[derive(Copy, Clone, Debug)]
-pub struct Memory(pub [u8; 0xFFFF]);
+pub struct Memory(pub [u8; 0x10000]);
+
+/// The NES's 2 KiB of internal RAM, which holds whatever pattern the chips
+/// power up with
+const RAM: std::ops::Range<usize> = 0x0000..0x0800;
+
+/// What `CPU::power_on` fills RAM with. Real consoles leave a mostly
+/// random pattern, so homebrew that reads RAM before writing it can work on
+/// one emulator and fail on hardware.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RamInit {
+    #[default]
+    Zero,
+    /// Every byte $FF
+    Ones,
+    /// Pseudo-random bytes, the same for the same seed
+    Random(u64),
+}
 
 impl Memory {
     pub fn new() -> Self {
-        Memory([0; 0xFFFF])
+        Memory([0; 0x10000])
+    }
+
+    /// Overwrites internal RAM as it would be found at power-on
+    pub fn fill_ram(&mut self, init: RamInit) {
+        let ram = &mut self.0[RAM];
+        match init {
+            RamInit::Zero => ram.fill(0x00),
+            RamInit::Ones => ram.fill(0xFF),
+            RamInit::Random(seed) => {
+                // SplitMix64, so no dependency is needed for a few bytes of noise
+                let mut state = seed;
+                for byte in ram.iter_mut() {
+                    state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                    let mut z = state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                    *byte = (z ^ (z >> 31)) as u8;
+                }
+            }
+        }
     }
 
     pub fn read(&self, addr: u16) -> u8 {
@@ -38,6 +77,14 @@ impl Memory {
         self.0[reserved_program_addresses].copy_from_slice(program_copy);
     }
 
+    /// Copy a raw image into memory starting at `origin`, wrapping around
+    /// past 0xFFFF
+    pub fn load_at(&mut self, origin: u16, image: &[u8]) {
+        for (offset, byte) in image.iter().enumerate() {
+            self.write(origin.wrapping_add(offset as u16), *byte);
+        }
+    }
+
     pub fn push_to_stack(&mut self, stack_pointer: u8, data: u8) {
         let stack_addr = (0x0100 + stack_pointer as u16) as usize;
         self.0[stack_addr] = data;
@@ -48,3 +95,15 @@ impl Memory {
         self.0[stack_addr]
     }
 }
+
+impl Savestate for Memory {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_bytes(&self.0);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError> {
+        let len = self.0.len();
+        self.0.copy_from_slice(state.read_bytes(len)?);
+        Ok(())
+    }
+}
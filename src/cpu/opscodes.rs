@@ -1,213 +1,585 @@
 use crate::cpu::addressing_mode::AddressingMode;
-use std::collections::HashMap;
+use crate::cpu::CPU;
 
 pub mod arithmetic_logic;
+pub mod cmos;
 pub mod control_flow;
+pub mod illegal;
 pub mod interrupts;
 pub mod registers;
 pub mod stack;
 pub mod status_register;
 
+/// Runs an instruction once `CPU::step` has fetched its opcode, and the
+/// second byte too for single-byte instructions
+pub type Handler = fn(&mut CPU, &AddressingMode);
+
+#[derive(Clone, Debug)]
 pub struct OpCode {
     pub code: u8,
     pub mnemonic: &'static str,
     pub len: u8,
     pub cycles: u8,
     pub mode: AddressingMode,
+    /// Undocumented opcode, a side effect of how the NMOS 6502 decodes
+    /// instructions, or one of the NOPs the 65C02 puts in their place
+    pub illegal: bool,
+    pub execute: Handler,
 }
 
 impl OpCode {
-    fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
+    fn new(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+        execute: Handler,
+    ) -> Self {
         OpCode {
             code,
             mnemonic,
             len,
             cycles,
             mode,
+            illegal: false,
+            execute,
+        }
+    }
+
+    fn illegal(
+        code: u8,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+        execute: Handler,
+    ) -> Self {
+        OpCode {
+            illegal: true,
+            ..OpCode::new(code, mnemonic, len, cycles, mode, execute)
         }
     }
 }
 
+/// Entries are equal when they describe the same instruction. Handlers are
+/// left out, as function pointers don't compare reliably.
+impl PartialEq for OpCode {
+    fn eq(&self, other: &Self) -> bool {
+        self.code == other.code
+            && self.mnemonic == other.mnemonic
+            && self.len == other.len
+            && self.cycles == other.cycles
+            && self.mode == other.mode
+            && self.illegal == other.illegal
+    }
+}
+
+/// Orders the entries of an opcode table by their opcode, so decoding is an
+/// index rather than a search
+fn by_code(table: &'static [OpCode]) -> [&'static OpCode; 256] {
+    let mut entries: [Option<&'static OpCode>; 256] = [None; 256];
+    for cpuop in table {
+        let entry = &mut entries[cpuop.code as usize];
+        assert!(
+            entry.is_none(),
+            "opcode {:02X} is defined twice",
+            cpuop.code
+        );
+        *entry = Some(cpuop);
+    }
+    std::array::from_fn(|code| {
+        entries[code].unwrap_or_else(|| panic!("opcode {:02X} is not defined", code))
+    })
+}
+
 lazy_static! {
         pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
 
-        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7d, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2d, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3d, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x39, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::Accumulator),
-        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::Relative),
-
-        OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::Relative),
-
-        OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::Relative),
-
-        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xDD, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xD9, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xD1, "CMP", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xe0, "CPX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xe4, "CPX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xec, "CPX", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0xc0, "CPY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xc4, "CPY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xcc, "CPY", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xDE, "DEC", 3, 7/*+1 if page crossed*/, AddressingMode::Absolute_X),
-
-        OpCode::new(0xca, "DEX", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x5D, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x59, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x51, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xe8, "INX", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xc8, "INY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xe6, "INC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPage_X),
-        OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xfe, "INC", 3, 7, AddressingMode::Absolute_X),
-
-        OpCode::new(0xa9, "LDA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xa5, "LDA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xb5, "LDA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xad, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbd, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xb9, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xa1, "LDA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xb1, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0xa2, "LDX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xa6, "LDX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xb6, "LDX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0xae, "LDX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbe, "LDX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-
-        OpCode::new(0xa0, "LDY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xa4, "LDY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xb4, "LDY", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0xac, "LDY", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xbc, "LDY", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-
-        OpCode::new(0x4A, "LSR", 2, 2, AddressingMode::Accumulator),
-        OpCode::new(0x46, "LSR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x56, "LSR", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x4E, "LSR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x5E, "LSR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-
-        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x0D, "ORA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x1D, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0x19, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x2A, "ROL", 2, 2, AddressingMode::Accumulator),
-        OpCode::new(0x26, "ROL", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x36, "ROL", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x2E, "ROL", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3E, "ROL", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-
-        OpCode::new(0x6A, "ROR", 2, 2, AddressingMode::Accumulator),
-        OpCode::new(0x66, "ROR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x76, "ROR", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x6E, "ROR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7E, "ROR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-
-        OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xFD, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
-        OpCode::new(0xF9, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
-        OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0xF1, "SBC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8d, "STA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x9d, "STA", 3, 5, AddressingMode::Absolute_X),
-        OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y),
-        OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y),
-
-        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
-        OpCode::new(0x8e, "STX", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
-        OpCode::new(0x8c, "STY", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0xaa, "TAX", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xa8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xba, "TSX", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x8a, "TXA", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate, arithmetic_logic::adc),
+        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage, arithmetic_logic::adc),
+        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPage_X, arithmetic_logic::adc),
+        OpCode::new(0x6d, "ADC", 3, 4, AddressingMode::Absolute, arithmetic_logic::adc),
+        OpCode::new(0x7d, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::adc),
+        OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, arithmetic_logic::adc),
+        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::Indirect_X, arithmetic_logic::adc),
+        OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, arithmetic_logic::adc),
+
+        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate, arithmetic_logic::and),
+        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage, arithmetic_logic::and),
+        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPage_X, arithmetic_logic::and),
+        OpCode::new(0x2d, "AND", 3, 4, AddressingMode::Absolute, arithmetic_logic::and),
+        OpCode::new(0x3d, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::and),
+        OpCode::new(0x39, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, arithmetic_logic::and),
+        OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X, arithmetic_logic::and),
+        OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, arithmetic_logic::and),
+
+        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::Accumulator, arithmetic_logic::asl),
+        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage, arithmetic_logic::asl),
+        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X, arithmetic_logic::asl),
+        OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute, arithmetic_logic::asl),
+        OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::Absolute_X, arithmetic_logic::asl),
+
+        OpCode::new(0x90, "BCC", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::bcc(cpu)),
+
+        OpCode::new(0xB0, "BCS", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::bcs(cpu)),
+
+        OpCode::new(0xF0, "BEQ", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::beq(cpu)),
+
+        OpCode::new(0x30, "BMI", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::bmi(cpu)),
+
+        OpCode::new(0xD0, "BNE", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::bne(cpu)),
+
+        OpCode::new(0x10, "BPL", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::bpl(cpu)),
+
+        OpCode::new(0x50, "BVC", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::bvc(cpu)),
+
+        OpCode::new(0x70, "BVS", 2, 2, AddressingMode::Relative, |cpu, _| control_flow::bvs(cpu)),
+
+        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage, arithmetic_logic::bit),
+        OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute, arithmetic_logic::bit),
+
+        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing, |cpu, _| interrupts::brk(cpu)),
+
+        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing, |cpu, _| status_register::clc(cpu)),
+
+        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing, |cpu, _| status_register::cld(cpu)),
+
+        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing, |cpu, _| status_register::cli(cpu)),
+
+        OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing, |cpu, _| status_register::clv(cpu)),
+
+        OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate, arithmetic_logic::cmp),
+        OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage, arithmetic_logic::cmp),
+        OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPage_X, arithmetic_logic::cmp),
+        OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute, arithmetic_logic::cmp),
+        OpCode::new(0xDD, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::cmp),
+        OpCode::new(0xD9, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, arithmetic_logic::cmp),
+        OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::Indirect_X, arithmetic_logic::cmp),
+        OpCode::new(0xD1, "CMP", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, arithmetic_logic::cmp),
+
+        OpCode::new(0xe0, "CPX", 2, 2, AddressingMode::Immediate, registers::cpx),
+        OpCode::new(0xe4, "CPX", 2, 3, AddressingMode::ZeroPage, registers::cpx),
+        OpCode::new(0xec, "CPX", 3, 4, AddressingMode::Absolute, registers::cpx),
+
+        OpCode::new(0xc0, "CPY", 2, 2, AddressingMode::Immediate, registers::cpy),
+        OpCode::new(0xc4, "CPY", 2, 3, AddressingMode::ZeroPage, registers::cpy),
+        OpCode::new(0xcc, "CPY", 3, 4, AddressingMode::Absolute, registers::cpy),
+
+        OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage, arithmetic_logic::dec),
+        OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPage_X, arithmetic_logic::dec),
+        OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute, arithmetic_logic::dec),
+        OpCode::new(0xDE, "DEC", 3, 7/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::dec),
+
+        OpCode::new(0xca, "DEX", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::dex(cpu)),
+
+        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::dey(cpu)),
+
+        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate, arithmetic_logic::eor),
+        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage, arithmetic_logic::eor),
+        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X, arithmetic_logic::eor),
+        OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute, arithmetic_logic::eor),
+        OpCode::new(0x5D, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::eor),
+        OpCode::new(0x59, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, arithmetic_logic::eor),
+        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X, arithmetic_logic::eor),
+        OpCode::new(0x51, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, arithmetic_logic::eor),
+
+        OpCode::new(0xe8, "INX", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::inx(cpu)),
+
+        OpCode::new(0xc8, "INY", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::iny(cpu)),
+
+        OpCode::new(0xe6, "INC", 2, 5, AddressingMode::ZeroPage, registers::inc),
+        OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPage_X, registers::inc),
+        OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute, registers::inc),
+        OpCode::new(0xfe, "INC", 3, 7, AddressingMode::Absolute_X, registers::inc),
+
+        OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute, control_flow::jmp),
+        OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::Indirect, control_flow::jmp),
+
+        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute, |cpu, _| control_flow::jsr(cpu)),
+
+        OpCode::new(0xa9, "LDA", 2, 2, AddressingMode::Immediate, registers::lda),
+        OpCode::new(0xa5, "LDA", 2, 3, AddressingMode::ZeroPage, registers::lda),
+        OpCode::new(0xb5, "LDA", 2, 4, AddressingMode::ZeroPage_X, registers::lda),
+        OpCode::new(0xad, "LDA", 3, 4, AddressingMode::Absolute, registers::lda),
+        OpCode::new(0xbd, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, registers::lda),
+        OpCode::new(0xb9, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, registers::lda),
+        OpCode::new(0xa1, "LDA", 2, 6, AddressingMode::Indirect_X, registers::lda),
+        OpCode::new(0xb1, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, registers::lda),
+
+        OpCode::new(0xa2, "LDX", 2, 2, AddressingMode::Immediate, registers::ldx),
+        OpCode::new(0xa6, "LDX", 2, 3, AddressingMode::ZeroPage, registers::ldx),
+        OpCode::new(0xb6, "LDX", 2, 4, AddressingMode::ZeroPage_Y, registers::ldx),
+        OpCode::new(0xae, "LDX", 3, 4, AddressingMode::Absolute, registers::ldx),
+        OpCode::new(0xbe, "LDX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, registers::ldx),
+
+        OpCode::new(0xa0, "LDY", 2, 2, AddressingMode::Immediate, registers::ldy),
+        OpCode::new(0xa4, "LDY", 2, 3, AddressingMode::ZeroPage, registers::ldy),
+        OpCode::new(0xb4, "LDY", 2, 4, AddressingMode::ZeroPage_X, registers::ldy),
+        OpCode::new(0xac, "LDY", 3, 4, AddressingMode::Absolute, registers::ldy),
+        OpCode::new(0xbc, "LDY", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, registers::ldy),
+
+        OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::Accumulator, arithmetic_logic::lsr),
+        OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage, arithmetic_logic::lsr),
+        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPage_X, arithmetic_logic::lsr),
+        OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute, arithmetic_logic::lsr),
+        OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::Absolute_X, arithmetic_logic::lsr),
+
+        OpCode::new(0xEA, "NOP", 1, 2, AddressingMode::NoneAddressing, illegal::nop),
+
+        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate, arithmetic_logic::ora),
+        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage, arithmetic_logic::ora),
+        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X, arithmetic_logic::ora),
+        OpCode::new(0x0D, "ORA", 3, 4, AddressingMode::Absolute, arithmetic_logic::ora),
+        OpCode::new(0x1D, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::ora),
+        OpCode::new(0x19, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, arithmetic_logic::ora),
+        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X, arithmetic_logic::ora),
+        OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, arithmetic_logic::ora),
+
+        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing, |cpu, _| registers::pha(cpu)),
+
+        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing, |cpu, _| stack::php(cpu)),
+
+        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing, |cpu, _| stack::pla(cpu)),
+
+        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing, |cpu, _| stack::plp(cpu)),
+
+        OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::Accumulator, arithmetic_logic::rol),
+        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage, arithmetic_logic::rol),
+        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X, arithmetic_logic::rol),
+        OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute, arithmetic_logic::rol),
+        OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::Absolute_X, arithmetic_logic::rol),
+
+        OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::Accumulator, arithmetic_logic::ror),
+        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage, arithmetic_logic::ror),
+        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X, arithmetic_logic::ror),
+        OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute, arithmetic_logic::ror),
+        OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::Absolute_X, arithmetic_logic::ror),
+
+        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing, |cpu, _| interrupts::rti(cpu)),
+
+        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing, |cpu, _| control_flow::rts(cpu)),
+
+        OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate, arithmetic_logic::sbc),
+        OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage, arithmetic_logic::sbc),
+        OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPage_X, arithmetic_logic::sbc),
+        OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute, arithmetic_logic::sbc),
+        OpCode::new(0xFD, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::sbc),
+        OpCode::new(0xF9, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, arithmetic_logic::sbc),
+        OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::Indirect_X, arithmetic_logic::sbc),
+        OpCode::new(0xF1, "SBC", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, arithmetic_logic::sbc),
+
+        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing, |cpu, _| status_register::sec(cpu)),
+
+        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing, |cpu, _| status_register::sed(cpu)),
+
+        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing, |cpu, _| status_register::sei(cpu)),
+
+        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage, registers::sta),
+        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPage_X, registers::sta),
+        OpCode::new(0x8d, "STA", 3, 4, AddressingMode::Absolute, registers::sta),
+        OpCode::new(0x9d, "STA", 3, 5, AddressingMode::Absolute_X, registers::sta),
+        OpCode::new(0x99, "STA", 3, 5, AddressingMode::Absolute_Y, registers::sta),
+        OpCode::new(0x81, "STA", 2, 6, AddressingMode::Indirect_X, registers::sta),
+        OpCode::new(0x91, "STA", 2, 6, AddressingMode::Indirect_Y, registers::sta),
+
+        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage, registers::stx),
+        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y, registers::stx),
+        OpCode::new(0x8e, "STX", 3, 4, AddressingMode::Absolute, registers::stx),
+
+        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage, registers::sty),
+        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X, registers::sty),
+        OpCode::new(0x8c, "STY", 3, 4, AddressingMode::Absolute, registers::sty),
+
+        OpCode::new(0xaa, "TAX", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::tax(cpu)),
+
+        OpCode::new(0xa8, "TAY", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::tay(cpu)),
+
+        OpCode::new(0xba, "TSX", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::tsx(cpu)),
+
+        OpCode::new(0x8a, "TXA", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::txa(cpu)),
+
+        OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::txs(cpu)),
+
+        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing, |cpu, _| registers::tya(cpu)),
+
+        // Undocumented opcodes. The unstable ones (XAA, LXA, AHX, TAS, SHX,
+        // SHY) are implemented the way most NMOS parts behave.
+
+        OpCode::illegal(0x07, "SLO", 2, 5, AddressingMode::ZeroPage, illegal::slo),
+        OpCode::illegal(0x17, "SLO", 2, 6, AddressingMode::ZeroPage_X, illegal::slo),
+        OpCode::illegal(0x0f, "SLO", 3, 6, AddressingMode::Absolute, illegal::slo),
+        OpCode::illegal(0x1f, "SLO", 3, 7, AddressingMode::Absolute_X, illegal::slo),
+        OpCode::illegal(0x1b, "SLO", 3, 7, AddressingMode::Absolute_Y, illegal::slo),
+        OpCode::illegal(0x03, "SLO", 2, 8, AddressingMode::Indirect_X, illegal::slo),
+        OpCode::illegal(0x13, "SLO", 2, 8, AddressingMode::Indirect_Y, illegal::slo),
+
+        OpCode::illegal(0x27, "RLA", 2, 5, AddressingMode::ZeroPage, illegal::rla),
+        OpCode::illegal(0x37, "RLA", 2, 6, AddressingMode::ZeroPage_X, illegal::rla),
+        OpCode::illegal(0x2f, "RLA", 3, 6, AddressingMode::Absolute, illegal::rla),
+        OpCode::illegal(0x3f, "RLA", 3, 7, AddressingMode::Absolute_X, illegal::rla),
+        OpCode::illegal(0x3b, "RLA", 3, 7, AddressingMode::Absolute_Y, illegal::rla),
+        OpCode::illegal(0x23, "RLA", 2, 8, AddressingMode::Indirect_X, illegal::rla),
+        OpCode::illegal(0x33, "RLA", 2, 8, AddressingMode::Indirect_Y, illegal::rla),
+
+        OpCode::illegal(0x47, "SRE", 2, 5, AddressingMode::ZeroPage, illegal::sre),
+        OpCode::illegal(0x57, "SRE", 2, 6, AddressingMode::ZeroPage_X, illegal::sre),
+        OpCode::illegal(0x4f, "SRE", 3, 6, AddressingMode::Absolute, illegal::sre),
+        OpCode::illegal(0x5f, "SRE", 3, 7, AddressingMode::Absolute_X, illegal::sre),
+        OpCode::illegal(0x5b, "SRE", 3, 7, AddressingMode::Absolute_Y, illegal::sre),
+        OpCode::illegal(0x43, "SRE", 2, 8, AddressingMode::Indirect_X, illegal::sre),
+        OpCode::illegal(0x53, "SRE", 2, 8, AddressingMode::Indirect_Y, illegal::sre),
+
+        OpCode::illegal(0x67, "RRA", 2, 5, AddressingMode::ZeroPage, illegal::rra),
+        OpCode::illegal(0x77, "RRA", 2, 6, AddressingMode::ZeroPage_X, illegal::rra),
+        OpCode::illegal(0x6f, "RRA", 3, 6, AddressingMode::Absolute, illegal::rra),
+        OpCode::illegal(0x7f, "RRA", 3, 7, AddressingMode::Absolute_X, illegal::rra),
+        OpCode::illegal(0x7b, "RRA", 3, 7, AddressingMode::Absolute_Y, illegal::rra),
+        OpCode::illegal(0x63, "RRA", 2, 8, AddressingMode::Indirect_X, illegal::rra),
+        OpCode::illegal(0x73, "RRA", 2, 8, AddressingMode::Indirect_Y, illegal::rra),
+
+        OpCode::illegal(0xc7, "DCP", 2, 5, AddressingMode::ZeroPage, illegal::dcp),
+        OpCode::illegal(0xd7, "DCP", 2, 6, AddressingMode::ZeroPage_X, illegal::dcp),
+        OpCode::illegal(0xcf, "DCP", 3, 6, AddressingMode::Absolute, illegal::dcp),
+        OpCode::illegal(0xdf, "DCP", 3, 7, AddressingMode::Absolute_X, illegal::dcp),
+        OpCode::illegal(0xdb, "DCP", 3, 7, AddressingMode::Absolute_Y, illegal::dcp),
+        OpCode::illegal(0xc3, "DCP", 2, 8, AddressingMode::Indirect_X, illegal::dcp),
+        OpCode::illegal(0xd3, "DCP", 2, 8, AddressingMode::Indirect_Y, illegal::dcp),
+
+        OpCode::illegal(0xe7, "ISC", 2, 5, AddressingMode::ZeroPage, illegal::isc),
+        OpCode::illegal(0xf7, "ISC", 2, 6, AddressingMode::ZeroPage_X, illegal::isc),
+        OpCode::illegal(0xef, "ISC", 3, 6, AddressingMode::Absolute, illegal::isc),
+        OpCode::illegal(0xff, "ISC", 3, 7, AddressingMode::Absolute_X, illegal::isc),
+        OpCode::illegal(0xfb, "ISC", 3, 7, AddressingMode::Absolute_Y, illegal::isc),
+        OpCode::illegal(0xe3, "ISC", 2, 8, AddressingMode::Indirect_X, illegal::isc),
+        OpCode::illegal(0xf3, "ISC", 2, 8, AddressingMode::Indirect_Y, illegal::isc),
+
+        OpCode::illegal(0xa7, "LAX", 2, 3, AddressingMode::ZeroPage, illegal::lax),
+        OpCode::illegal(0xb7, "LAX", 2, 4, AddressingMode::ZeroPage_Y, illegal::lax),
+        OpCode::illegal(0xaf, "LAX", 3, 4, AddressingMode::Absolute, illegal::lax),
+        OpCode::illegal(0xbf, "LAX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, illegal::lax),
+        OpCode::illegal(0xa3, "LAX", 2, 6, AddressingMode::Indirect_X, illegal::lax),
+        OpCode::illegal(0xb3, "LAX", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y, illegal::lax),
+
+        OpCode::illegal(0x87, "SAX", 2, 3, AddressingMode::ZeroPage, illegal::sax),
+        OpCode::illegal(0x97, "SAX", 2, 4, AddressingMode::ZeroPage_Y, illegal::sax),
+        OpCode::illegal(0x8f, "SAX", 3, 4, AddressingMode::Absolute, illegal::sax),
+        OpCode::illegal(0x83, "SAX", 2, 6, AddressingMode::Indirect_X, illegal::sax),
+
+        OpCode::illegal(0x0b, "ANC", 2, 2, AddressingMode::Immediate, illegal::anc),
+        OpCode::illegal(0x2b, "ANC", 2, 2, AddressingMode::Immediate, illegal::anc),
+        OpCode::illegal(0x4b, "ALR", 2, 2, AddressingMode::Immediate, illegal::alr),
+        OpCode::illegal(0x6b, "ARR", 2, 2, AddressingMode::Immediate, illegal::arr),
+        OpCode::illegal(0xcb, "AXS", 2, 2, AddressingMode::Immediate, illegal::axs),
+        OpCode::illegal(0xeb, "SBC", 2, 2, AddressingMode::Immediate, arithmetic_logic::sbc),
+        OpCode::illegal(0x8b, "XAA", 2, 2, AddressingMode::Immediate, illegal::xaa),
+        OpCode::illegal(0xab, "LXA", 2, 2, AddressingMode::Immediate, illegal::lxa),
+
+        OpCode::illegal(0x9f, "AHX", 3, 5, AddressingMode::Absolute_Y, illegal::ahx),
+        OpCode::illegal(0x93, "AHX", 2, 6, AddressingMode::Indirect_Y, illegal::ahx),
+        OpCode::illegal(0x9b, "TAS", 3, 5, AddressingMode::Absolute_Y, illegal::tas),
+        OpCode::illegal(0x9c, "SHY", 3, 5, AddressingMode::Absolute_X, illegal::shy),
+        OpCode::illegal(0x9e, "SHX", 3, 5, AddressingMode::Absolute_Y, illegal::shx),
+        OpCode::illegal(0xbb, "LAS", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y, illegal::las),
+
+        OpCode::illegal(0x1a, "NOP", 1, 2, AddressingMode::NoneAddressing, illegal::nop),
+        OpCode::illegal(0x3a, "NOP", 1, 2, AddressingMode::NoneAddressing, illegal::nop),
+        OpCode::illegal(0x5a, "NOP", 1, 2, AddressingMode::NoneAddressing, illegal::nop),
+        OpCode::illegal(0x7a, "NOP", 1, 2, AddressingMode::NoneAddressing, illegal::nop),
+        OpCode::illegal(0xda, "NOP", 1, 2, AddressingMode::NoneAddressing, illegal::nop),
+        OpCode::illegal(0xfa, "NOP", 1, 2, AddressingMode::NoneAddressing, illegal::nop),
+        OpCode::illegal(0x80, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+        OpCode::illegal(0x82, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+        OpCode::illegal(0x89, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+        OpCode::illegal(0xc2, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+        OpCode::illegal(0xe2, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+        OpCode::illegal(0x04, "NOP", 2, 3, AddressingMode::ZeroPage, illegal::nop),
+        OpCode::illegal(0x44, "NOP", 2, 3, AddressingMode::ZeroPage, illegal::nop),
+        OpCode::illegal(0x64, "NOP", 2, 3, AddressingMode::ZeroPage, illegal::nop),
+        OpCode::illegal(0x14, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+        OpCode::illegal(0x34, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+        OpCode::illegal(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+        OpCode::illegal(0x74, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+        OpCode::illegal(0xd4, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+        OpCode::illegal(0xf4, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+        OpCode::illegal(0x0c, "NOP", 3, 4, AddressingMode::Absolute, illegal::nop),
+        OpCode::illegal(0x1c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, illegal::nop),
+        OpCode::illegal(0x3c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, illegal::nop),
+        OpCode::illegal(0x5c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, illegal::nop),
+        OpCode::illegal(0x7c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, illegal::nop),
+        OpCode::illegal(0xdc, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, illegal::nop),
+        OpCode::illegal(0xfc, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, illegal::nop),
+
+        OpCode::illegal(0x02, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x12, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x22, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x32, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x42, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x52, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x62, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x72, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0x92, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0xb2, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0xd2, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
+        OpCode::illegal(0xf2, "KIL", 1, 2, AddressingMode::NoneAddressing, |cpu, _| illegal::kil(cpu)),
     ];
 
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
-        for cpuop in &*CPU_OPS_CODES {
-            map.insert(cpuop.code, cpuop);
-        }
-        map
-    };
+    /// Every NMOS opcode indexed by its value, which is what `CPU::step`
+    /// decodes with
+    pub static ref OPCODES: [&'static OpCode; 256] = by_code(&CPU_OPS_CODES);
+
+    /// The 65C02 keeps the documented NMOS instructions and fills every
+    /// other opcode. Those that behave or take time differently are listed
+    /// again.
+    pub static ref CMOS_OPS_CODES: Vec<OpCode> = CPU_OPS_CODES
+        .iter()
+        .filter(|cpuop| !cpuop.illegal && !matches!(cpuop.mnemonic, "ADC" | "SBC"))
+        .filter(|cpuop| !matches!(cpuop.code, 0x6c | 0x1e | 0x5e | 0x3e | 0x7e))
+        .cloned()
+        .chain(vec![
+            OpCode::new(0x69, "ADC", 2, 2/*+1 in decimal mode*/, AddressingMode::Immediate, cmos::adc),
+            OpCode::new(0x65, "ADC", 2, 3/*+1 in decimal mode*/, AddressingMode::ZeroPage, cmos::adc),
+            OpCode::new(0x75, "ADC", 2, 4/*+1 in decimal mode*/, AddressingMode::ZeroPage_X, cmos::adc),
+            OpCode::new(0x6d, "ADC", 3, 4/*+1 in decimal mode*/, AddressingMode::Absolute, cmos::adc),
+            OpCode::new(0x7d, "ADC", 3, 4/*+1 if page crossed, +1 in decimal mode*/, AddressingMode::Absolute_X, cmos::adc),
+            OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed, +1 in decimal mode*/, AddressingMode::Absolute_Y, cmos::adc),
+            OpCode::new(0x61, "ADC", 2, 6/*+1 in decimal mode*/, AddressingMode::Indirect_X, cmos::adc),
+            OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed, +1 in decimal mode*/, AddressingMode::Indirect_Y, cmos::adc),
+            OpCode::new(0x72, "ADC", 2, 5/*+1 in decimal mode*/, AddressingMode::ZeroPage_Indirect, cmos::adc),
+
+            OpCode::new(0xe9, "SBC", 2, 2/*+1 in decimal mode*/, AddressingMode::Immediate, cmos::sbc),
+            OpCode::new(0xe5, "SBC", 2, 3/*+1 in decimal mode*/, AddressingMode::ZeroPage, cmos::sbc),
+            OpCode::new(0xf5, "SBC", 2, 4/*+1 in decimal mode*/, AddressingMode::ZeroPage_X, cmos::sbc),
+            OpCode::new(0xed, "SBC", 3, 4/*+1 in decimal mode*/, AddressingMode::Absolute, cmos::sbc),
+            OpCode::new(0xfd, "SBC", 3, 4/*+1 if page crossed, +1 in decimal mode*/, AddressingMode::Absolute_X, cmos::sbc),
+            OpCode::new(0xf9, "SBC", 3, 4/*+1 if page crossed, +1 in decimal mode*/, AddressingMode::Absolute_Y, cmos::sbc),
+            OpCode::new(0xe1, "SBC", 2, 6/*+1 in decimal mode*/, AddressingMode::Indirect_X, cmos::sbc),
+            OpCode::new(0xf1, "SBC", 2, 5/*+1 if page crossed, +1 in decimal mode*/, AddressingMode::Indirect_Y, cmos::sbc),
+            OpCode::new(0xf2, "SBC", 2, 5/*+1 in decimal mode*/, AddressingMode::ZeroPage_Indirect, cmos::sbc),
+
+            OpCode::new(0x1e, "ASL", 3, 6/*+1 if page crossed*/, AddressingMode::Absolute_X, cmos::asl),
+            OpCode::new(0x5e, "LSR", 3, 6/*+1 if page crossed*/, AddressingMode::Absolute_X, cmos::lsr),
+            OpCode::new(0x3e, "ROL", 3, 6/*+1 if page crossed*/, AddressingMode::Absolute_X, cmos::rol),
+            OpCode::new(0x7e, "ROR", 3, 6/*+1 if page crossed*/, AddressingMode::Absolute_X, cmos::ror),
+
+            OpCode::new(0x6c, "JMP", 3, 6, AddressingMode::Indirect, control_flow::jmp),
+            OpCode::new(0x7c, "JMP", 3, 6, AddressingMode::Absolute_Indirect_X, control_flow::jmp),
+
+            OpCode::new(0x80, "BRA", 2, 3/*+1 if page crossed*/, AddressingMode::Relative, |cpu, _| control_flow::bra(cpu)),
+
+            OpCode::new(0xda, "PHX", 1, 3, AddressingMode::NoneAddressing, |cpu, _| cmos::phx(cpu)),
+            OpCode::new(0x5a, "PHY", 1, 3, AddressingMode::NoneAddressing, |cpu, _| cmos::phy(cpu)),
+            OpCode::new(0xfa, "PLX", 1, 4, AddressingMode::NoneAddressing, |cpu, _| cmos::plx(cpu)),
+            OpCode::new(0x7a, "PLY", 1, 4, AddressingMode::NoneAddressing, |cpu, _| cmos::ply(cpu)),
+
+            OpCode::new(0x64, "STZ", 2, 3, AddressingMode::ZeroPage, cmos::stz),
+            OpCode::new(0x74, "STZ", 2, 4, AddressingMode::ZeroPage_X, cmos::stz),
+            OpCode::new(0x9c, "STZ", 3, 4, AddressingMode::Absolute, cmos::stz),
+            OpCode::new(0x9e, "STZ", 3, 5, AddressingMode::Absolute_X, cmos::stz),
+
+            OpCode::new(0x14, "TRB", 2, 5, AddressingMode::ZeroPage, cmos::trb),
+            OpCode::new(0x1c, "TRB", 3, 6, AddressingMode::Absolute, cmos::trb),
+            OpCode::new(0x04, "TSB", 2, 5, AddressingMode::ZeroPage, cmos::tsb),
+            OpCode::new(0x0c, "TSB", 3, 6, AddressingMode::Absolute, cmos::tsb),
+
+            OpCode::new(0x12, "ORA", 2, 5, AddressingMode::ZeroPage_Indirect, arithmetic_logic::ora),
+            OpCode::new(0x32, "AND", 2, 5, AddressingMode::ZeroPage_Indirect, arithmetic_logic::and),
+            OpCode::new(0x52, "EOR", 2, 5, AddressingMode::ZeroPage_Indirect, arithmetic_logic::eor),
+            OpCode::new(0x92, "STA", 2, 5, AddressingMode::ZeroPage_Indirect, registers::sta),
+            OpCode::new(0xb2, "LDA", 2, 5, AddressingMode::ZeroPage_Indirect, registers::lda),
+            OpCode::new(0xd2, "CMP", 2, 5, AddressingMode::ZeroPage_Indirect, arithmetic_logic::cmp),
+
+            OpCode::new(0x89, "BIT", 2, 2, AddressingMode::Immediate, arithmetic_logic::bit),
+            OpCode::new(0x34, "BIT", 2, 4, AddressingMode::ZeroPage_X, arithmetic_logic::bit),
+            OpCode::new(0x3c, "BIT", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X, arithmetic_logic::bit),
+
+            OpCode::new(0x1a, "INC", 1, 2, AddressingMode::Accumulator, registers::inc),
+            OpCode::new(0x3a, "DEC", 1, 2, AddressingMode::Accumulator, arithmetic_logic::dec),
+
+            OpCode::new(0x07, "RMB0", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 0)),
+            OpCode::new(0x17, "RMB1", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 1)),
+            OpCode::new(0x27, "RMB2", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 2)),
+            OpCode::new(0x37, "RMB3", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 3)),
+            OpCode::new(0x47, "RMB4", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 4)),
+            OpCode::new(0x57, "RMB5", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 5)),
+            OpCode::new(0x67, "RMB6", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 6)),
+            OpCode::new(0x77, "RMB7", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::rmb(cpu, mode, 7)),
+            OpCode::new(0x87, "SMB0", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 0)),
+            OpCode::new(0x97, "SMB1", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 1)),
+            OpCode::new(0xa7, "SMB2", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 2)),
+            OpCode::new(0xb7, "SMB3", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 3)),
+            OpCode::new(0xc7, "SMB4", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 4)),
+            OpCode::new(0xd7, "SMB5", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 5)),
+            OpCode::new(0xe7, "SMB6", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 6)),
+            OpCode::new(0xf7, "SMB7", 2, 5, AddressingMode::ZeroPage, |cpu, mode| cmos::smb(cpu, mode, 7)),
+            OpCode::new(0x0f, "BBR0", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 0)),
+            OpCode::new(0x1f, "BBR1", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 1)),
+            OpCode::new(0x2f, "BBR2", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 2)),
+            OpCode::new(0x3f, "BBR3", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 3)),
+            OpCode::new(0x4f, "BBR4", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 4)),
+            OpCode::new(0x5f, "BBR5", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 5)),
+            OpCode::new(0x6f, "BBR6", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 6)),
+            OpCode::new(0x7f, "BBR7", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbr(cpu, mode, 7)),
+            OpCode::new(0x8f, "BBS0", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 0)),
+            OpCode::new(0x9f, "BBS1", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 1)),
+            OpCode::new(0xaf, "BBS2", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 2)),
+            OpCode::new(0xbf, "BBS3", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 3)),
+            OpCode::new(0xcf, "BBS4", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 4)),
+            OpCode::new(0xdf, "BBS5", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 5)),
+            OpCode::new(0xef, "BBS6", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 6)),
+            OpCode::new(0xff, "BBS7", 3, 5, AddressingMode::ZeroPage_Relative, |cpu, mode| cmos::bbs(cpu, mode, 7)),
+
+            OpCode::new(0xcb, "WAI", 1, 3, AddressingMode::NoneAddressing, |cpu, _| cmos::wai(cpu)),
+            OpCode::new(0xdb, "STP", 1, 3, AddressingMode::NoneAddressing, |cpu, _| cmos::stp(cpu)),
+
+            // The undefined opcodes are NOPs of various lengths
+            OpCode::illegal(0x02, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+            OpCode::illegal(0x22, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+            OpCode::illegal(0x42, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+            OpCode::illegal(0x62, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+            OpCode::illegal(0x82, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+            OpCode::illegal(0xc2, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+            OpCode::illegal(0xe2, "NOP", 2, 2, AddressingMode::Immediate, illegal::nop),
+            OpCode::illegal(0x44, "NOP", 2, 3, AddressingMode::ZeroPage, illegal::nop),
+            OpCode::illegal(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+            OpCode::illegal(0xd4, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+            OpCode::illegal(0xf4, "NOP", 2, 4, AddressingMode::ZeroPage_X, illegal::nop),
+            OpCode::illegal(0x5c, "NOP", 3, 8, AddressingMode::Absolute, cmos::long_nop),
+            OpCode::illegal(0xdc, "NOP", 3, 4, AddressingMode::Absolute, illegal::nop),
+            OpCode::illegal(0xfc, "NOP", 3, 4, AddressingMode::Absolute, illegal::nop),
+            OpCode::illegal(0x03, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x13, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x23, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x33, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x43, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x53, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x63, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x73, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x83, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x93, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xa3, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xb3, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xc3, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xd3, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xe3, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xf3, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x0b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x1b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x2b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x3b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x4b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x5b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x6b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x7b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x8b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0x9b, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xab, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xbb, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xeb, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+            OpCode::illegal(0xfb, "NOP", 1, 1, AddressingMode::NoneAddressing, illegal::nop),
+        ])
+        .collect();
+
+    pub static ref CMOS_OPCODES: [&'static OpCode; 256] = by_code(&CMOS_OPS_CODES);
 
 }
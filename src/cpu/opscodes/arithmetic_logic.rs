@@ -1,4 +1,4 @@
-use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::addressing_mode::{AddressingMode, Operation};
 use crate::cpu::Register;
 use crate::cpu::CPU;
 use crate::util::shared::Comparison;
@@ -20,9 +20,17 @@ fn update_carry_zero_and_negative_flags(cpu: &mut CPU, comparator: u8, register:
 }
 
 pub fn adc(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
+    let param = AddressingMode::read_operand(cpu, mode);
+    add_with_carry(cpu, param);
+}
+
+/// Adds `param` and the carry to the accumulator, as ADC and RRA do
+pub(crate) fn add_with_carry(cpu: &mut CPU, param: u8) {
     let carry_value = cpu.status.get_carry_flag();
-    let param = cpu.memory.read(addr);
+    if decimal_mode(cpu) {
+        decimal_adc(cpu, param, carry_value);
+        return;
+    }
 
     let temp = (cpu.register_a.0 as u16)
         .wrapping_add(param as u16)
@@ -42,15 +50,79 @@ pub fn adc(cpu: &mut CPU, mode: &AddressingMode) -> () {
     cpu.register_a.0 = result;
 }
 
+/// Whether ADC and SBC work in BCD, which the 2A03 doesn't support
+fn decimal_mode(cpu: &CPU) -> bool {
+    cpu.variant.has_decimal_mode() && cpu.status.get_decimal_flag() != 0
+}
+
+/// BCD addition. On NMOS parts only C is valid for BCD: Z reflects the
+/// binary sum, and N and V the sum before the high digit is corrected. The
+/// 65C02 takes N and Z from the result.
+fn decimal_adc(cpu: &mut CPU, param: u8, carry: u8) {
+    let a = cpu.register_a.0;
+
+    let mut low = (a & 0x0F) + (param & 0x0F) + carry;
+    if low >= 0x0A {
+        low = ((low + 0x06) & 0x0F) + 0x10;
+    }
+    let sum = (a & 0xF0) as u16 + (param & 0xF0) as u16 + low as u16;
+    let signed_sum = (a & 0xF0) as i8 as i16 + (param & 0xF0) as i8 as i16 + low as i16;
+    let result = if sum >= 0xA0 { sum + 0x60 } else { sum };
+
+    cpu.status.set_carry_flag(result > 0xFF);
+    cpu.status
+        .set_zero_flag(a.wrapping_add(param).wrapping_add(carry) == 0);
+    cpu.status
+        .set_overflow_flag(!(-128..=127).contains(&signed_sum));
+    cpu.status.set_negative_flag(sum & 0x80 != 0);
+
+    cpu.register_a.0 = result as u8;
+    if cpu.variant.is_cmos() {
+        update_zero_and_negative_flags(cpu, cpu.register_a.0);
+    }
+}
+
+/// NMOS BCD subtraction. The flags are those of the binary subtraction, so
+/// only the accumulator differs.
+fn decimal_sbc(a: u8, param: u8, carry: u8) -> u8 {
+    let mut low = (a & 0x0F) as i16 - (param & 0x0F) as i16 + carry as i16 - 1;
+    if low < 0 {
+        low = ((low - 0x06) & 0x0F) - 0x10;
+    }
+    let mut result = (a & 0xF0) as i16 - (param & 0xF0) as i16 + low;
+    if result < 0 {
+        result -= 0x60;
+    }
+    result as u8
+}
+
+/// 65C02 BCD subtraction, which corrects the whole difference before the
+/// low digit. Only invalid BCD operands give a different result from NMOS.
+fn cmos_decimal_sbc(a: u8, param: u8, carry: u8) -> u8 {
+    let low = (a & 0x0F) as i16 - (param & 0x0F) as i16 + carry as i16 - 1;
+    let mut result = a as i16 - param as i16 + carry as i16 - 1;
+    if result < 0 {
+        result -= 0x60;
+    }
+    if low < 0 {
+        result -= 0x06;
+    }
+    result as u8
+}
+
 pub fn and(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     cpu.register_a.0 = cpu.register_a.0 & param;
 
     update_zero_and_negative_flags(cpu, cpu.register_a.0);
 }
 
 pub fn asl(cpu: &mut CPU, mode: &AddressingMode) -> () {
+    shift_left(cpu, mode, Operation::Modify);
+}
+
+/// ASL, indexing its address with the dummy reads of `indexing`
+pub(crate) fn shift_left(cpu: &mut CPU, mode: &AddressingMode, indexing: Operation) {
     let mut new_value = 0;
     let mut bit7 = false;
 
@@ -62,11 +134,11 @@ pub fn asl(cpu: &mut CPU, mode: &AddressingMode) -> () {
             cpu.register_a.0 = new_value;
         }
         _ => {
-            let addr = AddressingMode::get_operand_address(cpu, mode);
-            let value = cpu.memory.read(addr);
+            let addr = AddressingMode::fetch_address(cpu, mode, indexing);
+            let value = cpu.read_for_modify(addr);
             bit7 = value.bit_7_is_set();
             new_value = value << 1;
-            cpu.memory.write(addr, new_value);
+            cpu.mem_write(addr, new_value);
         }
     }
 
@@ -75,18 +147,20 @@ pub fn asl(cpu: &mut CPU, mode: &AddressingMode) -> () {
 }
 
 pub fn bit(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     let result = cpu.register_a.0 & param;
 
     cpu.status.set_zero_flag(result == 0);
+    // The 65C02's BIT #imm only sets Z
+    if *mode == AddressingMode::Immediate {
+        return;
+    }
     cpu.status.set_overflow_flag(param.bit_6_is_set());
     cpu.status.set_negative_flag(param.bit_7_is_set());
 }
 
 pub fn cmp(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     let result = cpu.register_a.0.wrapping_sub(param);
 
     cpu.status.set_carry_flag(cpu.register_a.0 >= param);
@@ -95,24 +169,37 @@ pub fn cmp(cpu: &mut CPU, mode: &AddressingMode) -> () {
 }
 
 pub fn dec(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
-    let result = param.wrapping_sub(1);
-    cpu.memory.write(addr, result);
+    let result = match mode {
+        AddressingMode::Accumulator => {
+            cpu.register_a.0 = cpu.register_a.0.wrapping_sub(1);
+            cpu.register_a.0
+        }
+        _ => {
+            let addr = AddressingMode::fetch_address(cpu, mode, Operation::Modify);
+            let param = cpu.read_for_modify(addr);
+            let result = param.wrapping_sub(1);
+            cpu.mem_write(addr, result);
+            result
+        }
+    };
 
     cpu.status.set_zero_flag(result == 0);
     cpu.status.set_negative_flag(result.bit_7_is_set());
 }
 
 pub fn eor(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     cpu.register_a.0 = cpu.register_a.0 ^ param;
     cpu.status.set_zero_flag(cpu.register_a.0 == 0);
     cpu.status.set_negative_flag(cpu.register_a.bit_7_is_set());
 }
 
 pub fn lsr(cpu: &mut CPU, mode: &AddressingMode) -> () {
+    shift_right(cpu, mode, Operation::Modify);
+}
+
+/// LSR, indexing its address with the dummy reads of `indexing`
+pub(crate) fn shift_right(cpu: &mut CPU, mode: &AddressingMode, indexing: Operation) {
     let mut new_value = 0;
     let mut bit0 = false;
 
@@ -124,11 +211,11 @@ pub fn lsr(cpu: &mut CPU, mode: &AddressingMode) -> () {
             cpu.register_a.0 = new_value;
         }
         _ => {
-            let addr = AddressingMode::get_operand_address(cpu, mode);
-            let value = cpu.memory.read(addr);
+            let addr = AddressingMode::fetch_address(cpu, mode, indexing);
+            let value = cpu.read_for_modify(addr);
             bit0 = value.bit_0_is_set();
             new_value = value >> 1;
-            cpu.memory.write(addr, new_value);
+            cpu.mem_write(addr, new_value);
         }
     }
 
@@ -137,14 +224,18 @@ pub fn lsr(cpu: &mut CPU, mode: &AddressingMode) -> () {
 }
 
 pub fn ora(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     cpu.register_a.0 = cpu.register_a.0 | param;
     cpu.status.set_zero_flag(cpu.register_a.0 == 0);
     cpu.status.set_negative_flag(cpu.register_a.bit_7_is_set());
 }
 
 pub fn rol(cpu: &mut CPU, mode: &AddressingMode) -> () {
+    rotate_left(cpu, mode, Operation::Modify);
+}
+
+/// ROL, indexing its address with the dummy reads of `indexing`
+pub(crate) fn rotate_left(cpu: &mut CPU, mode: &AddressingMode, indexing: Operation) {
     let mut new_value = 0;
     let mut bit7 = false;
     let carry_flag = cpu.status.get_carry_flag();
@@ -158,12 +249,12 @@ pub fn rol(cpu: &mut CPU, mode: &AddressingMode) -> () {
             cpu.register_a.0 = new_value;
         }
         _ => {
-            let addr = AddressingMode::get_operand_address(cpu, mode);
-            let value = cpu.memory.read(addr);
+            let addr = AddressingMode::fetch_address(cpu, mode, indexing);
+            let value = cpu.read_for_modify(addr);
             bit7 = value.bit_7_is_set();
             new_value = value << 1;
             new_value |= carry_flag;
-            cpu.memory.write(addr, new_value);
+            cpu.mem_write(addr, new_value);
         }
     }
 
@@ -171,6 +262,11 @@ pub fn rol(cpu: &mut CPU, mode: &AddressingMode) -> () {
     cpu.status.set_carry_flag(bit7);
 }
 pub fn ror(cpu: &mut CPU, mode: &AddressingMode) -> () {
+    rotate_right(cpu, mode, Operation::Modify);
+}
+
+/// ROR, indexing its address with the dummy reads of `indexing`
+pub(crate) fn rotate_right(cpu: &mut CPU, mode: &AddressingMode, indexing: Operation) {
     let mut new_value = 0;
     let mut bit0 = false;
     let carry_flag = cpu.status.get_carry_flag();
@@ -184,12 +280,12 @@ pub fn ror(cpu: &mut CPU, mode: &AddressingMode) -> () {
             cpu.register_a.0 = new_value;
         }
         _ => {
-            let addr = AddressingMode::get_operand_address(cpu, mode);
-            let value = cpu.memory.read(addr);
+            let addr = AddressingMode::fetch_address(cpu, mode, indexing);
+            let value = cpu.read_for_modify(addr);
             bit0 = value.bit_0_is_set();
             new_value = value >> 1;
             new_value |= carry_flag << 7;
-            cpu.memory.write(addr, new_value);
+            cpu.mem_write(addr, new_value);
         }
     }
 
@@ -198,22 +294,36 @@ pub fn ror(cpu: &mut CPU, mode: &AddressingMode) -> () {
 }
 
 pub fn sbc(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
+    let param = AddressingMode::read_operand(cpu, mode);
+    subtract_with_borrow(cpu, param);
+}
+
+/// Subtracts `param` and the borrow from the accumulator, as SBC and ISC do
+pub(crate) fn subtract_with_borrow(cpu: &mut CPU, param: u8) {
     let carry_value = cpu.status.get_carry_flag();
-    let param = cpu.memory.read(addr);
 
+    // A - M - (1 - C) is A + !M + C, so this is ADC of the inverted operand
+    // and carry ends up set when no borrow was needed
+    let inverted = !param;
     let temp = (cpu.register_a.0 as u16)
-        .wrapping_sub(param as u16)
-        .wrapping_sub(carry_value as u16);
+        .wrapping_add(inverted as u16)
+        .wrapping_add(carry_value as u16);
     let result = (temp & 0xFF) as u8;
 
     // Setting flags
-    cpu.status.set_carry_flag(temp <= 0xFF);
+    cpu.status.set_carry_flag(temp > 0xFF);
     cpu.status.set_zero_flag(result == 0);
-    cpu.status.set_overflow_flag(
-        ((cpu.register_a.0 ^ param) & 0x80 == 0) && ((cpu.register_a.0 ^ result) & 0x80 != 0),
-    );
+    cpu.status
+        .set_overflow_flag((cpu.register_a.0 ^ result) & (inverted ^ result) & 0x80 != 0);
     cpu.status.set_negative_flag((result & 0b1000_0000) != 0);
 
-    cpu.register_a.0 = result;
+    cpu.register_a.0 = if !decimal_mode(cpu) {
+        result
+    } else if cpu.variant.is_cmos() {
+        let result = cmos_decimal_sbc(cpu.register_a.0, param, carry_value);
+        update_zero_and_negative_flags(cpu, result);
+        result
+    } else {
+        decimal_sbc(cpu.register_a.0, param, carry_value)
+    };
 }
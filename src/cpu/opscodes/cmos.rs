@@ -0,0 +1,141 @@
+use crate::cpu::addressing_mode::{AddressingMode, Operation};
+use crate::cpu::opscodes::arithmetic_logic;
+use crate::cpu::opscodes::control_flow::branch;
+use crate::cpu::opscodes::illegal::nop;
+use crate::cpu::CPU;
+use crate::util::shared::Comparison;
+use crate::util::u8_ext::BitwiseU8;
+
+fn update_zero_and_negative_flags(cpu: &mut CPU, value: u8) {
+    cpu.status.set_zero_flag(value.is_zero());
+    cpu.status.set_negative_flag(value.bit_7_is_set());
+}
+
+pub fn phx(cpu: &mut CPU) {
+    cpu.stack_push(cpu.register_x.0);
+}
+
+pub fn phy(cpu: &mut CPU) {
+    cpu.stack_push(cpu.register_y.0);
+}
+
+pub fn plx(cpu: &mut CPU) {
+    cpu.stack_dummy_read();
+    cpu.register_x.0 = cpu.stack_pull();
+    update_zero_and_negative_flags(cpu, cpu.register_x.0);
+}
+
+pub fn ply(cpu: &mut CPU) {
+    cpu.stack_dummy_read();
+    cpu.register_y.0 = cpu.stack_pull();
+    update_zero_and_negative_flags(cpu, cpu.register_y.0);
+}
+
+/// ADC and SBC take a cycle longer in decimal mode, which the 65C02 spends
+/// correcting the flags, reading the next opcode's address meanwhile
+pub fn adc(cpu: &mut CPU, mode: &AddressingMode) {
+    arithmetic_logic::adc(cpu, mode);
+    decimal_cycle(cpu);
+}
+
+pub fn sbc(cpu: &mut CPU, mode: &AddressingMode) {
+    arithmetic_logic::sbc(cpu, mode);
+    decimal_cycle(cpu);
+}
+
+fn decimal_cycle(cpu: &mut CPU) {
+    if cpu.status.get_decimal_flag() != 0 {
+        cpu.dummy_read(cpu.program_counter);
+    }
+}
+
+// With absolute,X the shifts and rotates only spend a cycle fixing the high
+// byte of the address when the page is crossed, as reads do. INC and DEC
+// always spend it, as on the NMOS 6502.
+
+pub fn asl(cpu: &mut CPU, mode: &AddressingMode) {
+    arithmetic_logic::shift_left(cpu, mode, Operation::Read);
+}
+
+pub fn lsr(cpu: &mut CPU, mode: &AddressingMode) {
+    arithmetic_logic::shift_right(cpu, mode, Operation::Read);
+}
+
+pub fn rol(cpu: &mut CPU, mode: &AddressingMode) {
+    arithmetic_logic::rotate_left(cpu, mode, Operation::Read);
+}
+
+pub fn ror(cpu: &mut CPU, mode: &AddressingMode) {
+    arithmetic_logic::rotate_right(cpu, mode, Operation::Read);
+}
+
+pub fn stz(cpu: &mut CPU, mode: &AddressingMode) {
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Write);
+    cpu.mem_write(addr, 0);
+}
+
+/// Clears the bits that are set in A. Z is set as BIT would set it.
+pub fn trb(cpu: &mut CPU, mode: &AddressingMode) {
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Modify);
+    let value = cpu.read_for_modify(addr);
+    cpu.status.set_zero_flag(cpu.register_a.0 & value == 0);
+    cpu.mem_write(addr, value & !cpu.register_a.0);
+}
+
+/// Sets the bits that are set in A. Z is set as BIT would set it.
+pub fn tsb(cpu: &mut CPU, mode: &AddressingMode) {
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Modify);
+    let value = cpu.read_for_modify(addr);
+    cpu.status.set_zero_flag(cpu.register_a.0 & value == 0);
+    cpu.mem_write(addr, value | cpu.register_a.0);
+}
+
+pub fn rmb(cpu: &mut CPU, mode: &AddressingMode, bit: u8) {
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Modify);
+    let value = cpu.read_for_modify(addr);
+    cpu.mem_write(addr, value & !(1 << bit));
+}
+
+pub fn smb(cpu: &mut CPU, mode: &AddressingMode, bit: u8) {
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Modify);
+    let value = cpu.read_for_modify(addr);
+    cpu.mem_write(addr, value | (1 << bit));
+}
+
+pub fn bbr(cpu: &mut CPU, mode: &AddressingMode, bit: u8) {
+    branch_on_bit(cpu, mode, bit, false);
+}
+
+pub fn bbs(cpu: &mut CPU, mode: &AddressingMode, bit: u8) {
+    branch_on_bit(cpu, mode, bit, true);
+}
+
+/// Like the relative branches, with the displacement after the zero page
+/// operand, which is read twice before the displacement is fetched
+fn branch_on_bit(cpu: &mut CPU, mode: &AddressingMode, bit: u8, set: bool) {
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Read);
+    let value = cpu.mem_read(addr);
+    cpu.dummy_read(addr);
+    branch(cpu, value.bit_is_set_at(bit as usize) == set);
+}
+
+pub fn wai(cpu: &mut CPU) {
+    cpu.dummy_read(cpu.program_counter);
+    cpu.waiting = true;
+}
+
+/// Like KIL, the program counter stays on the STP
+pub fn stp(cpu: &mut CPU) {
+    cpu.dummy_read(cpu.program_counter);
+    cpu.jammed = true;
+    cpu.program_counter = cpu.program_counter.wrapping_sub(1);
+}
+
+/// $5C is four cycles longer than an absolute read, modelled here as reads
+/// of $FFFF
+pub fn long_nop(cpu: &mut CPU, mode: &AddressingMode) {
+    nop(cpu, mode);
+    for _ in 0..4 {
+        cpu.dummy_read(0xFFFF);
+    }
+}
@@ -19,38 +19,104 @@ fn update_carry_zero_and_negative_flags(cpu: &mut CPU, comparator: u8, register:
     ()
 }
 
-pub fn bcc(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    // TODO: Handle cycles
-    if cpu.status.get_carry_flag() == 0 {
-        let addr = AddressingMode::get_operand_address(cpu, mode);
-        // Read memory as signed i8 for negatives before casting to i16
-        let relative_displacement = cpu.memory.read(addr) as i8 as i16;
-
-        cpu.program_counter =
-            (cpu.program_counter as i16).wrapping_add(relative_displacement) as u16;
+/// Relative branches add a signed displacement to the address after the
+/// branch. Taking the branch costs a cycle, and another when the target is
+/// on a different page, both spent reading from the program counter before
+/// its high byte is fixed up.
+pub(crate) fn branch(cpu: &mut CPU, condition: bool) {
+    // Read memory as signed i8 for negatives before casting to u16
+    let relative_displacement = cpu.fetch() as i8 as u16;
+    if !condition {
+        return;
     }
+
+    let pc = cpu.program_counter;
+    let target = pc.wrapping_add(relative_displacement);
+    cpu.skip_interrupt_poll();
+    cpu.dummy_read(pc);
+    if target & 0xFF00 != pc & 0xFF00 {
+        cpu.dummy_read((pc & 0xFF00) | (target & 0x00FF));
+    }
+    cpu.program_counter = target;
 }
 
-pub fn bcs(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    // TODO: Handle cycles
-    if cpu.status.get_carry_flag() != 0 {
-        let addr = AddressingMode::get_operand_address(cpu, mode);
-        // Read memory as signed i8 for negatives before casting to i16
-        let relative_displacement = cpu.memory.read(addr) as i8 as i16;
+pub fn bcc(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_carry_flag() == 0);
+}
 
-        cpu.program_counter =
-            (cpu.program_counter as i16).wrapping_add(relative_displacement) as u16;
-    }
+pub fn bcs(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_carry_flag() != 0);
 }
 
-pub fn beq(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    // TODO: Handle cycles
-    if cpu.status.get_zero_flag() == 0 {
-        let addr = AddressingMode::get_operand_address(cpu, mode);
-        // Read memory as signed i8 for negatives before casting to i16
-        let relative_displacement = cpu.memory.read(addr) as i8 as i16;
+pub fn beq(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_zero_flag() != 0);
+}
 
-        cpu.program_counter =
-            (cpu.program_counter as i16).wrapping_add(relative_displacement) as u16;
-    }
+pub fn bmi(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_negative_flag() != 0);
+}
+
+pub fn bne(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_zero_flag() == 0);
+}
+
+pub fn bpl(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_negative_flag() == 0);
+}
+
+pub fn bra(cpu: &mut CPU) {
+    branch(cpu, true);
+}
+
+pub fn bvc(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_overflow_flag() == 0);
+}
+
+pub fn bvs(cpu: &mut CPU) -> () {
+    branch(cpu, cpu.status.get_overflow_flag() != 0);
+}
+
+pub fn jmp(cpu: &mut CPU, mode: &AddressingMode) -> () {
+    let addr = cpu.fetch_u16();
+
+    cpu.program_counter = match mode {
+        AddressingMode::Indirect if !cpu.variant.is_cmos() => {
+            // The 6502 doesn't carry into the high byte when fetching the
+            // pointer, so JMP ($10FF) reads its high byte from $1000
+            let lo = cpu.mem_read(addr);
+            let hi = cpu.mem_read((addr & 0xFF00) | (addr.wrapping_add(1) & 0x00FF));
+            u16::from_le_bytes([lo, hi])
+        }
+        // The 65C02 fetches the pointer without wrapping, taking a cycle
+        // more to do it
+        AddressingMode::Indirect | AddressingMode::Absolute_Indirect_X => {
+            let pointer = match mode {
+                AddressingMode::Absolute_Indirect_X => addr.wrapping_add(cpu.register_x.0 as u16),
+                _ => addr,
+            };
+            cpu.dummy_read(cpu.program_counter.wrapping_sub(1));
+            let lo = cpu.mem_read(pointer);
+            let hi = cpu.mem_read(pointer.wrapping_add(1));
+            u16::from_le_bytes([lo, hi])
+        }
+        _ => addr,
+    };
+}
+
+pub fn jsr(cpu: &mut CPU) -> () {
+    let lo = cpu.fetch();
+    cpu.stack_dummy_read();
+    // The pushed return address points at the last byte of the JSR, whose
+    // high address byte is only fetched after the push
+    cpu.stack_push_u16(cpu.program_counter);
+    let hi = cpu.fetch();
+    cpu.program_counter = u16::from_le_bytes([lo, hi]);
+}
+
+pub fn rts(cpu: &mut CPU) -> () {
+    cpu.stack_dummy_read();
+    let addr = cpu.stack_pull_u16();
+    // The return address is read again while it is incremented
+    cpu.dummy_read(addr);
+    cpu.program_counter = addr.wrapping_add(1);
 }
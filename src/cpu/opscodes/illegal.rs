@@ -0,0 +1,212 @@
+use crate::cpu::addressing_mode::{AddressingMode, Operation};
+use crate::cpu::opscodes::arithmetic_logic::{add_with_carry, subtract_with_borrow};
+use crate::cpu::CPU;
+use crate::util::shared::Comparison;
+use crate::util::u8_ext::BitwiseU8;
+
+/// Constant ORed into A by XAA before the AND. It varies between chips
+/// and with temperature; $EE is what most NMOS parts show.
+const XAA_MAGIC: u8 = 0xEE;
+/// LXA on the 2A03 acts as if the constant were $FF, loading the operand
+const LXA_MAGIC: u8 = 0xFF;
+
+fn update_zero_and_negative_flags(cpu: &mut CPU, value: u8) {
+    cpu.status.set_zero_flag(value.is_zero());
+    cpu.status.set_negative_flag(value.bit_7_is_set());
+}
+
+/// Reads, modifies and writes back the operand, returning the new value
+fn read_modify_write<F>(cpu: &mut CPU, mode: &AddressingMode, modify: F) -> u8
+where
+    F: FnOnce(&mut CPU, u8) -> u8,
+{
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Modify);
+    let value = cpu.read_for_modify(addr);
+    let result = modify(cpu, value);
+    cpu.mem_write(addr, result);
+    result
+}
+
+/// ASL, then ORA with the result
+pub fn slo(cpu: &mut CPU, mode: &AddressingMode) {
+    let result = read_modify_write(cpu, mode, |cpu, value| {
+        cpu.status.set_carry_flag(value.bit_7_is_set());
+        value << 1
+    });
+    cpu.register_a.0 |= result;
+    update_zero_and_negative_flags(cpu, cpu.register_a.0);
+}
+
+/// ROL, then AND with the result
+pub fn rla(cpu: &mut CPU, mode: &AddressingMode) {
+    let result = read_modify_write(cpu, mode, |cpu, value| {
+        let carry = cpu.status.get_carry_flag();
+        cpu.status.set_carry_flag(value.bit_7_is_set());
+        (value << 1) | carry
+    });
+    cpu.register_a.0 &= result;
+    update_zero_and_negative_flags(cpu, cpu.register_a.0);
+}
+
+/// LSR, then EOR with the result
+pub fn sre(cpu: &mut CPU, mode: &AddressingMode) {
+    let result = read_modify_write(cpu, mode, |cpu, value| {
+        cpu.status.set_carry_flag(value.bit_0_is_set());
+        value >> 1
+    });
+    cpu.register_a.0 ^= result;
+    update_zero_and_negative_flags(cpu, cpu.register_a.0);
+}
+
+/// ROR, then ADC with the result
+pub fn rra(cpu: &mut CPU, mode: &AddressingMode) {
+    let result = read_modify_write(cpu, mode, |cpu, value| {
+        let carry = cpu.status.get_carry_flag();
+        cpu.status.set_carry_flag(value.bit_0_is_set());
+        (value >> 1) | (carry << 7)
+    });
+    add_with_carry(cpu, result);
+}
+
+/// DEC, then CMP with the result
+pub fn dcp(cpu: &mut CPU, mode: &AddressingMode) {
+    let result = read_modify_write(cpu, mode, |_, value| value.wrapping_sub(1));
+    let a = cpu.register_a.0;
+    cpu.status.set_carry_flag(a >= result);
+    update_zero_and_negative_flags(cpu, a.wrapping_sub(result));
+}
+
+/// INC, then SBC with the result
+pub fn isc(cpu: &mut CPU, mode: &AddressingMode) {
+    let result = read_modify_write(cpu, mode, |_, value| value.wrapping_add(1));
+    subtract_with_borrow(cpu, result);
+}
+
+/// LDA and LDX at once
+pub fn lax(cpu: &mut CPU, mode: &AddressingMode) {
+    let value = AddressingMode::read_operand(cpu, mode);
+    cpu.register_a.0 = value;
+    cpu.register_x.0 = value;
+    update_zero_and_negative_flags(cpu, value);
+}
+
+/// Stores A AND X, leaving the flags alone
+pub fn sax(cpu: &mut CPU, mode: &AddressingMode) {
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Write);
+    cpu.mem_write(addr, cpu.register_a.0 & cpu.register_x.0);
+}
+
+fn immediate(cpu: &mut CPU, mode: &AddressingMode) -> u8 {
+    AddressingMode::read_operand(cpu, mode)
+}
+
+/// AND, then copy N into C
+pub fn anc(cpu: &mut CPU, mode: &AddressingMode) {
+    cpu.register_a.0 &= immediate(cpu, mode);
+    update_zero_and_negative_flags(cpu, cpu.register_a.0);
+    cpu.status.set_carry_flag(cpu.register_a.bit_7_is_set());
+}
+
+/// AND, then LSR A
+pub fn alr(cpu: &mut CPU, mode: &AddressingMode) {
+    let value = cpu.register_a.0 & immediate(cpu, mode);
+    cpu.status.set_carry_flag(value.bit_0_is_set());
+    cpu.register_a.0 = value >> 1;
+    update_zero_and_negative_flags(cpu, cpu.register_a.0);
+}
+
+/// AND, then ROR A, with C and V taken from bits 6 and 5 of the result
+pub fn arr(cpu: &mut CPU, mode: &AddressingMode) {
+    let value = cpu.register_a.0 & immediate(cpu, mode);
+    let result = (value >> 1) | (cpu.status.get_carry_flag() << 7);
+    cpu.register_a.0 = result;
+    update_zero_and_negative_flags(cpu, result);
+    cpu.status.set_carry_flag(result.bit_6_is_set());
+    cpu.status
+        .set_overflow_flag(result.bit_6_is_set() != result.bit_5_is_set());
+}
+
+/// X = (A AND X) - operand, setting C like CMP and ignoring the borrow in
+pub fn axs(cpu: &mut CPU, mode: &AddressingMode) {
+    let value = immediate(cpu, mode);
+    let and = cpu.register_a.0 & cpu.register_x.0;
+    cpu.register_x.0 = and.wrapping_sub(value);
+    cpu.status.set_carry_flag(and >= value);
+    update_zero_and_negative_flags(cpu, cpu.register_x.0);
+}
+
+pub fn xaa(cpu: &mut CPU, mode: &AddressingMode) {
+    let value = immediate(cpu, mode);
+    cpu.register_a.0 = (cpu.register_a.0 | XAA_MAGIC) & cpu.register_x.0 & value;
+    update_zero_and_negative_flags(cpu, cpu.register_a.0);
+}
+
+pub fn lxa(cpu: &mut CPU, mode: &AddressingMode) {
+    let value = (cpu.register_a.0 | LXA_MAGIC) & immediate(cpu, mode);
+    cpu.register_a.0 = value;
+    cpu.register_x.0 = value;
+    update_zero_and_negative_flags(cpu, value);
+}
+
+/// A = X = S = operand AND S
+pub fn las(cpu: &mut CPU, mode: &AddressingMode) {
+    let value = AddressingMode::read_operand(cpu, mode) & cpu.register_s.0;
+    cpu.register_a.0 = value;
+    cpu.register_x.0 = value;
+    cpu.register_s.0 = value;
+    update_zero_and_negative_flags(cpu, value);
+}
+
+/// Stores `value` AND (high byte of the base address + 1), the way AHX,
+/// TAS, SHX and SHY do. When indexing crosses a page the stored value also
+/// replaces the high byte of the address.
+fn store_and_high(cpu: &mut CPU, mode: &AddressingMode, value: u8) {
+    let (base, index) = match mode {
+        AddressingMode::Indirect_Y => {
+            let pointer = cpu.fetch();
+            let lo = cpu.mem_read(pointer as u16);
+            let hi = cpu.mem_read(pointer.wrapping_add(1) as u16);
+            (u16::from_le_bytes([lo, hi]), cpu.register_y.0)
+        }
+        AddressingMode::Absolute_X => (cpu.fetch_u16(), cpu.register_x.0),
+        _ => (cpu.fetch_u16(), cpu.register_y.0),
+    };
+    let mut addr = base.wrapping_add(index as u16);
+    cpu.dummy_read((base & 0xFF00) | (addr & 0x00FF));
+    let result = value & ((base >> 8) as u8).wrapping_add(1);
+    if addr & 0xFF00 != base & 0xFF00 {
+        addr = (result as u16) << 8 | (addr & 0x00FF);
+    }
+    cpu.mem_write(addr, result);
+}
+
+pub fn ahx(cpu: &mut CPU, mode: &AddressingMode) {
+    store_and_high(cpu, mode, cpu.register_a.0 & cpu.register_x.0);
+}
+
+/// S = A AND X, then stores like AHX
+pub fn tas(cpu: &mut CPU, mode: &AddressingMode) {
+    cpu.register_s.0 = cpu.register_a.0 & cpu.register_x.0;
+    store_and_high(cpu, mode, cpu.register_s.0);
+}
+
+pub fn shx(cpu: &mut CPU, mode: &AddressingMode) {
+    store_and_high(cpu, mode, cpu.register_x.0);
+}
+
+pub fn shy(cpu: &mut CPU, mode: &AddressingMode) {
+    store_and_high(cpu, mode, cpu.register_y.0);
+}
+
+/// The multi-byte NOPs still read their operand
+pub fn nop(cpu: &mut CPU, mode: &AddressingMode) {
+    if *mode != AddressingMode::NoneAddressing {
+        AddressingMode::read_operand(cpu, mode);
+    }
+}
+
+/// Locks up the CPU until reset, with the program counter left on the KIL
+pub fn kil(cpu: &mut CPU) {
+    cpu.jammed = true;
+    cpu.program_counter = cpu.program_counter.wrapping_sub(1);
+}
@@ -0,0 +1,17 @@
+use crate::cpu::opscodes::stack::pull_status;
+use crate::cpu::CPU;
+
+pub fn rti(cpu: &mut CPU) -> () {
+    cpu.stack_dummy_read();
+    pull_status(cpu);
+    cpu.program_counter = cpu.stack_pull_u16();
+    ()
+}
+
+/// The software interrupt through the IRQ vector. `CPU::step` has already
+/// read the padding byte, which the return address skips, and the status
+/// is pushed with B set.
+pub fn brk(cpu: &mut CPU) {
+    cpu.program_counter = cpu.program_counter.wrapping_add(1);
+    cpu.enter_interrupt_handler(CPU::IRQ_VECTOR, cpu.status.pushed(true));
+}
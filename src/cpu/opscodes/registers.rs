@@ -1,4 +1,4 @@
-use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::addressing_mode::{AddressingMode, Operation};
 use crate::cpu::Register;
 use crate::cpu::CPU;
 use crate::util::shared::{AdjustBy1, Comparison};
@@ -20,15 +20,13 @@ fn update_carry_zero_and_negative_flags(cpu: &mut CPU, comparator: u8, register:
 }
 
 pub fn cpx(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let operand = cpu.memory.read(addr);
+    let operand = AddressingMode::read_operand(cpu, mode);
     update_carry_zero_and_negative_flags(cpu, operand, cpu.register_x);
     ()
 }
 
 pub fn cpy(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let operand = cpu.memory.read(addr);
+    let operand = AddressingMode::read_operand(cpu, mode);
     update_carry_zero_and_negative_flags(cpu, operand, cpu.register_y);
     ()
 }
@@ -46,10 +44,19 @@ pub fn dey(cpu: &mut CPU) -> () {
 }
 
 pub fn inc(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
-    let result = param.wrapping_add(1);
-    cpu.memory.write(addr, result);
+    let result = match mode {
+        AddressingMode::Accumulator => {
+            cpu.register_a.increment();
+            cpu.register_a.0
+        }
+        _ => {
+            let addr = AddressingMode::fetch_address(cpu, mode, Operation::Modify);
+            let param = cpu.read_for_modify(addr);
+            let result = param.wrapping_add(1);
+            cpu.mem_write(addr, result);
+            result
+        }
+    };
     update_zero_and_negative_flags(cpu, result);
     ()
 }
@@ -67,51 +74,46 @@ pub fn iny(cpu: &mut CPU) -> () {
 }
 
 pub fn lda(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     cpu.register_a.0 = param;
     update_zero_and_negative_flags(cpu, cpu.register_a.0);
     ()
 }
 
 pub fn ldx(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     cpu.register_x.0 = param;
     update_zero_and_negative_flags(cpu, cpu.register_x.0);
     ()
 }
 
 pub fn ldy(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    let param = cpu.memory.read(addr);
+    let param = AddressingMode::read_operand(cpu, mode);
     cpu.register_y.0 = param;
     update_zero_and_negative_flags(cpu, cpu.register_y.0);
     ()
 }
 
 pub fn pha(cpu: &mut CPU) -> () {
-    let stack_pointer = cpu.register_s;
-    cpu.memory.push_to_stack(stack_pointer.0, cpu.register_a.0);
-    cpu.register_s.decrement();
+    cpu.stack_push(cpu.register_a.0);
     ()
 }
 
 pub fn sta(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    cpu.memory.write(addr, cpu.register_a.0);
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Write);
+    cpu.mem_write(addr, cpu.register_a.0);
     ()
 }
 
 pub fn stx(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    cpu.memory.write(addr, cpu.register_x.0);
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Write);
+    cpu.mem_write(addr, cpu.register_x.0);
     ()
 }
 
 pub fn sty(cpu: &mut CPU, mode: &AddressingMode) -> () {
-    let addr = AddressingMode::get_operand_address(cpu, mode);
-    cpu.memory.write(addr, cpu.register_y.0);
+    let addr = AddressingMode::fetch_address(cpu, mode, Operation::Write);
+    cpu.mem_write(addr, cpu.register_y.0);
     ()
 }
 
@@ -139,9 +141,9 @@ pub fn txa(cpu: &mut CPU) -> () {
     ()
 }
 
+/// Unlike the other transfers, TXS leaves the flags alone
 pub fn txs(cpu: &mut CPU) -> () {
     cpu.register_s = cpu.register_x;
-    update_zero_and_negative_flags(cpu, cpu.register_s.0);
     ()
 }
 
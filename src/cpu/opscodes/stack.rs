@@ -0,0 +1,28 @@
+use crate::cpu::CPU;
+use crate::util::shared::Comparison;
+use crate::util::u8_ext::BitwiseU8;
+
+pub fn php(cpu: &mut CPU) -> () {
+    cpu.stack_push(cpu.status.pushed(true));
+    ()
+}
+
+pub fn pla(cpu: &mut CPU) -> () {
+    cpu.stack_dummy_read();
+    cpu.register_a.0 = cpu.stack_pull();
+    cpu.status.set_zero_flag(cpu.register_a.is_zero());
+    cpu.status.set_negative_flag(cpu.register_a.bit_7_is_set());
+    ()
+}
+
+pub fn plp(cpu: &mut CPU) -> () {
+    cpu.stack_dummy_read();
+    pull_status(cpu);
+    ()
+}
+
+pub fn pull_status(cpu: &mut CPU) -> () {
+    let pulled = cpu.stack_pull();
+    cpu.status.pull(pulled);
+    ()
+}
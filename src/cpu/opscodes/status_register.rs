@@ -5,7 +5,32 @@ pub fn clc(cpu: &mut CPU) -> () {
     ()
 }
 
+pub fn cld(cpu: &mut CPU) -> () {
+    cpu.status.set_decimal_flag(false);
+    ()
+}
+
+pub fn cli(cpu: &mut CPU) -> () {
+    cpu.status.set_interupt_disable_flag(false);
+    ()
+}
+
+pub fn clv(cpu: &mut CPU) -> () {
+    cpu.status.set_overflow_flag(false);
+    ()
+}
+
 pub fn sec(cpu: &mut CPU) -> () {
     cpu.status.set_carry_flag(true);
     ()
 }
+
+pub fn sed(cpu: &mut CPU) -> () {
+    cpu.status.set_decimal_flag(true);
+    ()
+}
+
+pub fn sei(cpu: &mut CPU) -> () {
+    cpu.status.set_interupt_disable_flag(true);
+    ()
+}
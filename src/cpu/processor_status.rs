@@ -1,3 +1,4 @@
+use crate::savestate::{Savestate, SavestateError, StateReader, StateWriter};
 use crate::util::shared::{AdjustBy1, Comparison};
 use crate::util::u8_ext::BitwiseU8;
 use std::fmt;
@@ -17,9 +18,67 @@ impl fmt::Display for ProcessorStatus {
     }
 }
 
+/// Status flags by bit position. `Break` and `Unused` aren't latched by
+/// the 6502: they only mean something in copies of the status on the stack.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Carry = 0,
+    Zero = 1,
+    InterruptDisable = 2,
+    Decimal = 3,
+    Break = 4,
+    Unused = 5,
+    Overflow = 6,
+    Negative = 7,
+}
+
+impl Flag {
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The bits that don't exist in the register itself
+const STACK_ONLY_BITS: u8 = 0b0011_0000;
+
 impl ProcessorStatus {
+    /// Status after power-on: only I is set, and B and bit 5 read as 1
+    pub const POWER_ON: u8 = 0x34;
+
+    /// B and bit 5 always read as 1 outside the stack, whatever `val` has
     pub fn new(val: u8) -> Self {
-        ProcessorStatus(val)
+        ProcessorStatus(val | STACK_ONLY_BITS)
+    }
+
+    pub fn is_set(&self, flag: Flag) -> bool {
+        self.0 & flag.mask() != 0
+    }
+
+    /// Sets or clears `flag`. B and bit 5 can't be changed.
+    pub fn set(&mut self, flag: Flag, state: bool) {
+        if flag.mask() & STACK_ONLY_BITS != 0 {
+            return;
+        }
+        match state {
+            true => self.0 |= flag.mask(),
+            false => self.0 &= !flag.mask(),
+        }
+    }
+
+    /// The copy pushed to the stack. Bit 5 is always set, and B tells
+    /// whether PHP or BRK pushed it rather than an IRQ or NMI.
+    pub fn pushed(&self, by_instruction: bool) -> u8 {
+        let copy = (self.0 & !STACK_ONLY_BITS) | Flag::Unused.mask();
+        match by_instruction {
+            true => copy | Flag::Break.mask(),
+            false => copy,
+        }
+    }
+
+    /// Takes the flags from a value pulled by PLP or RTI, leaving B and
+    /// bit 5 as they were
+    pub fn pull(&mut self, value: u8) {
+        self.0 = (value & !STACK_ONLY_BITS) | (self.0 & STACK_ONLY_BITS);
     }
 
     pub fn set_bit_at(&mut self, index: usize) -> () {
@@ -62,10 +121,28 @@ impl ProcessorStatus {
         self.bit_2_is_set() as u8
     }
 
+    pub fn set_interupt_disable_flag(&mut self, state: bool) -> () {
+        match state {
+            true => self.set_bit_at(2),
+            false => self.unset_bit_at(2),
+        }
+    }
+
     pub fn get_decimal_flag(&self) -> u8 {
         self.bit_3_is_set() as u8
     }
 
+    pub fn set_decimal_flag(&mut self, state: bool) -> () {
+        match state {
+            true => self.set_bit_at(3),
+            false => self.unset_bit_at(3),
+        }
+    }
+
+    pub fn get_break_flag(&self) -> u8 {
+        self.bit_4_is_set() as u8
+    }
+
     pub fn get_overflow_flag(&self) -> u8 {
         self.bit_6_is_set() as u8
     }
@@ -89,6 +166,17 @@ impl ProcessorStatus {
     }
 }
 
+impl Savestate for ProcessorStatus {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.0);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError> {
+        *self = ProcessorStatus::new(state.read_u8()?);
+        Ok(())
+    }
+}
+
 impl AdjustBy1 for ProcessorStatus {
     fn decrement(&mut self) -> () {
         self.0 = self.0.wrapping_sub(1);
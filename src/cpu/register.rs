@@ -1,3 +1,4 @@
+use crate::savestate::{Savestate, SavestateError, StateReader, StateWriter};
 use crate::util::shared::{AdjustBy1, Comparison};
 use crate::util::u8_ext::BitwiseU8;
 use std::fmt;
@@ -23,6 +24,17 @@ impl Register {
     }
 }
 
+impl Savestate for Register {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.0);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError> {
+        self.0 = state.read_u8()?;
+        Ok(())
+    }
+}
+
 impl AdjustBy1 for Register {
     fn decrement(&mut self) -> () {
         self.0 = self.0.wrapping_sub(1);
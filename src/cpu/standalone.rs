@@ -0,0 +1,115 @@
+use crate::cpu::{Stop, CPU};
+
+/// Why `run_until_trap` stopped
+#[derive(Clone, Debug, PartialEq)]
+pub enum Halt {
+    /// An instruction jumped or branched to itself, which test programs
+    /// use to signal success or failure
+    Trap(u16),
+    /// KIL or STP locked up the CPU at `pc`
+    Jammed(u16),
+    /// WAI at `pc` put the CPU to sleep
+    Waiting(u16),
+    /// The illegal opcode at `pc` was rejected, as
+    /// `CPU::reject_illegal_opcodes` asks
+    IllegalOpcode(u16),
+    /// `limit` instructions ran without trapping
+    Limit,
+}
+
+/// Runs a program loaded with `CPU::load_flat` until it traps
+///
+/// Test suites such as Klaus Dormann's 6502 functional test end in a jump
+/// to self, at one address on success and elsewhere on failure, so the
+/// caller compares the trap address against the program's listing. BRK
+/// ends `CPU::run` once it has entered its handler, but here the program
+/// carries on in the handler as such programs expect.
+pub fn run_until_trap(cpu: &mut CPU, limit: u64) -> Halt {
+    for _ in 0..limit {
+        let pc = cpu.program_counter;
+        match cpu.step().stop() {
+            None if cpu.waiting => return Halt::Waiting(pc),
+            None | Some(Stop::Brk) => {}
+            Some(Stop::Jammed) => return Halt::Jammed(pc),
+            Some(Stop::Waiting) => return Halt::Waiting(pc),
+            Some(Stop::IllegalOpcode) => return Halt::IllegalOpcode(pc),
+        }
+        if cpu.program_counter == pc {
+            return Halt::Trap(pc);
+        }
+    }
+    Halt::Limit
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::assembler::assemble;
+    use crate::cpu::variant::Variant;
+
+    fn run(source: &str) -> (CPU, Halt) {
+        let assembly = assemble(source).unwrap();
+        let mut cpu = CPU::new();
+        cpu.load_flat(&assembly.bytes(), 0x0400, assembly.labels["start"]);
+        let halt = run_until_trap(&mut cpu, 1000);
+        (cpu, halt)
+    }
+
+    #[test]
+    fn test_traps_report_their_address() {
+        let (cpu, halt) = run("
+            .org $0400
+            start:  LDX #5
+            loop:   DEX
+                    BNE loop
+                    CPX #0
+                    BEQ success
+            failure: JMP failure
+            success: BEQ success
+        ");
+        assert_eq!(halt, Halt::Trap(0x040C));
+        assert_eq!(cpu.register_x.0, 0);
+        // No reset vector was used, and nothing outside the image was loaded
+        assert_eq!(cpu.memory.read_u16(0xFFFC), 0);
+    }
+
+    #[test]
+    fn test_brk_is_a_software_interrupt() {
+        let (mut cpu, halt) = run("
+            .org $0400
+            start:  CLI
+                    SEC
+                    BRK
+                    .byte $EA
+            done:   JMP done
+            irq:    PHP
+                    PLA
+                    STA $00
+                    RTI
+            .org $FFFE
+                    .word irq
+        ");
+        assert_eq!(halt, Halt::Trap(0x0404));
+        // The handler saw I set, and the status BRK pushed had B set
+        assert_eq!(cpu.memory.read(0x00), 0b0011_0101);
+        assert_eq!(cpu.memory.read(0x01FB), 0b0011_0001);
+
+        cpu.memory.write(0x0404, 0x02);
+        assert_eq!(run_until_trap(&mut cpu, 100), Halt::Jammed(0x0404));
+    }
+
+    #[test]
+    fn test_only_brk_is_a_software_interrupt() {
+        let mut cpu = CPU::new();
+        cpu.load_flat(&[0xE8, 0xA7, 0x10], 0x0400, 0x0400); // INX; LAX $10
+        cpu.reject_illegal_opcodes = true;
+        assert_eq!(run_until_trap(&mut cpu, 100), Halt::IllegalOpcode(0x0401));
+        assert_eq!(cpu.program_counter, 0x0401);
+
+        cpu.variant = Variant::Wdc65C02;
+        cpu.load_flat(&[0xCB], 0x0400, 0x0400); // WAI
+        assert_eq!(run_until_trap(&mut cpu, 100), Halt::Waiting(0x0400));
+        assert_eq!(run_until_trap(&mut cpu, 100), Halt::Waiting(0x0401));
+        assert_eq!(cpu.register_s.0, 0xFD);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::disassembler::{disassemble_one_as, Instruction, MemoryReader};
+use crate::cpu::CPU;
+use crate::symbols::SymbolTable;
+use std::io::{self, Write};
+
+/// PPU dots per scanline and scanlines per frame on NTSC
+const DOTS_PER_SCANLINE: u64 = 341;
+const SCANLINES_PER_FRAME: u64 = 262;
+
+/// Formats the instruction at the program counter the way nestest.log does:
+///
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+///
+/// Operands are annotated with the effective address and the value found
+/// there before the instruction runs. Undocumented opcodes are marked with
+/// a `*` before the mnemonic.
+pub fn trace(cpu: &CPU) -> String {
+    trace_with(cpu, &SymbolTable::new())
+}
+
+/// Like `trace`, with labelled operand addresses
+pub fn trace_with(cpu: &CPU, symbols: &SymbolTable) -> String {
+    let instruction = disassemble_one_as(&cpu.memory, cpu.program_counter, cpu.variant);
+    let bytes: Vec<String> = instruction
+        .bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    let marker = if instruction.opcode.illegal { '*' } else { ' ' };
+    let asm = format!(
+        "{:04X}  {:<8} {}{} {}",
+        instruction.address,
+        bytes.join(" "),
+        marker,
+        instruction.mnemonic(),
+        annotated_operand(cpu, &instruction, symbols)
+    );
+
+    // Until there is a PPU its position is derived from the CPU clock
+    let dots = cpu.cycles * 3;
+    let scanline = (dots / DOTS_PER_SCANLINE) % SCANLINES_PER_FRAME;
+    let dot = dots % DOTS_PER_SCANLINE;
+    // nestest.log shows P the way an interrupt would push it, B clear
+
+    format!(
+        "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        asm.trim_end(),
+        cpu.register_a.0,
+        cpu.register_x.0,
+        cpu.register_y.0,
+        cpu.status.pushed(false),
+        cpu.register_s.0,
+        scanline,
+        dot,
+        cpu.cycles
+    )
+}
+
+fn annotated_operand(cpu: &CPU, instruction: &Instruction, symbols: &SymbolTable) -> String {
+    let operand = instruction.operand_with(symbols);
+    let opcode = instruction.opcode;
+    let memory = &cpu.memory;
+    let x = cpu.register_x.0;
+    let y = cpu.register_y.0;
+
+    match opcode.mode {
+        AddressingMode::ZeroPage => {
+            let addr = instruction.bytes[1] as u16;
+            format!("{} = {:02X}", operand, memory.peek(addr))
+        }
+        AddressingMode::ZeroPage_X | AddressingMode::ZeroPage_Y => {
+            let index = if opcode.mode == AddressingMode::ZeroPage_X {
+                x
+            } else {
+                y
+            };
+            let addr = instruction.bytes[1].wrapping_add(index);
+            format!(
+                "{} @ {:02X} = {:02X}",
+                operand,
+                addr,
+                memory.peek(addr as u16)
+            )
+        }
+        AddressingMode::Absolute if matches!(opcode.mnemonic, "JMP" | "JSR") => operand,
+        AddressingMode::Absolute => {
+            let addr = memory.peek_u16(instruction.address.wrapping_add(1));
+            format!("{} = {:02X}", operand, memory.peek(addr))
+        }
+        AddressingMode::Absolute_X | AddressingMode::Absolute_Y => {
+            let index = if opcode.mode == AddressingMode::Absolute_X {
+                x
+            } else {
+                y
+            };
+            let base = memory.peek_u16(instruction.address.wrapping_add(1));
+            let addr = base.wrapping_add(index as u16);
+            format!("{} @ {:04X} = {:02X}", operand, addr, memory.peek(addr))
+        }
+        AddressingMode::Indirect => {
+            let ptr = memory.peek_u16(instruction.address.wrapping_add(1));
+            let lo = memory.peek(ptr);
+            // Only the NMOS parts wrap within the page, as JMP does
+            let hi = if cpu.variant.is_cmos() {
+                memory.peek(ptr.wrapping_add(1))
+            } else {
+                memory.peek((ptr & 0xFF00) | (ptr.wrapping_add(1) & 0x00FF))
+            };
+            format!("{} = {:04X}", operand, u16::from_le_bytes([lo, hi]))
+        }
+        AddressingMode::Absolute_Indirect_X => {
+            let base = memory.peek_u16(instruction.address.wrapping_add(1));
+            let ptr = base.wrapping_add(x as u16);
+            format!("{} @ {:04X} = {:04X}", operand, ptr, memory.peek_u16(ptr))
+        }
+        AddressingMode::Indirect_X => {
+            let ptr = instruction.bytes[1].wrapping_add(x);
+            let addr = zero_page_u16(cpu, ptr);
+            format!(
+                "{} @ {:02X} = {:04X} = {:02X}",
+                operand,
+                ptr,
+                addr,
+                memory.peek(addr)
+            )
+        }
+        AddressingMode::Indirect_Y => {
+            let base = zero_page_u16(cpu, instruction.bytes[1]);
+            let addr = base.wrapping_add(y as u16);
+            format!(
+                "{} = {:04X} @ {:04X} = {:02X}",
+                operand,
+                base,
+                addr,
+                memory.peek(addr)
+            )
+        }
+        AddressingMode::ZeroPage_Indirect => {
+            let addr = zero_page_u16(cpu, instruction.bytes[1]);
+            format!("{} = {:04X} = {:02X}", operand, addr, memory.peek(addr))
+        }
+        _ => operand,
+    }
+}
+
+/// Pointers in the zero page wrap around instead of crossing into page one
+fn zero_page_u16(cpu: &CPU, ptr: u8) -> u16 {
+    let lo = cpu.memory.peek(ptr as u16);
+    let hi = cpu.memory.peek(ptr.wrapping_add(1) as u16);
+    u16::from_le_bytes([lo, hi])
+}
+
+/// Writes a nestest-format line for every instruction to `out`
+///
+/// ```ignore
+/// let mut tracer = Tracer::new(io::stdout());
+/// cpu.run_with_callback(|cpu| tracer.trace(cpu).unwrap());
+/// ```
+pub struct Tracer<W: Write> {
+    out: W,
+    pub symbols: SymbolTable,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W) -> Self {
+        Tracer::with_symbols(out, SymbolTable::new())
+    }
+
+    pub fn with_symbols(out: W, symbols: SymbolTable) -> Self {
+        Tracer { out, symbols }
+    }
+
+    pub fn trace(&mut self, cpu: &CPU) -> io::Result<()> {
+        writeln!(self.out, "{}", trace_with(cpu, &self.symbols))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::variant::Variant;
+    use crate::cpu::ProcessorStatus;
+
+    #[test]
+    fn test_format_matches_nestest() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x4C, 0xF5, 0xC5]);
+        cpu.reset();
+        cpu.register_s.0 = 0xFD;
+        cpu.status = ProcessorStatus::new(0x24);
+        assert_eq!(
+            trace(&cpu),
+            "8000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+    }
+
+    #[test]
+    fn test_effective_address_annotations() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![
+            0x86, 0x00, // STX $00
+            0xB5, 0x33, // LDA $33,X
+            0xBD, 0x00, 0x03, // LDA $0300,X
+            0xA1, 0x80, // LDA ($80,X)
+            0xB1, 0x89, // LDA ($89),Y
+            0x6C, 0xFF, 0x02, // JMP ($02FF)
+            0xA7, 0x35, // LAX $35
+        ]);
+        cpu.reset();
+        cpu.register_x.0 = 0x02;
+        cpu.register_y.0 = 0x34;
+        cpu.memory.write(0x35, 0x12);
+        cpu.memory.write_u16(0x82, 0x0200);
+        cpu.memory.write_u16(0x89, 0x0300);
+        cpu.memory.write(0x0200, 0x5A);
+        cpu.memory.write(0x0302, 0x89);
+        cpu.memory.write(0x02FF, 0x7E);
+        cpu.memory.write(0x0200, 0xDB);
+
+        let lines: Vec<String> = [0x8000, 0x8002, 0x8004, 0x8007, 0x8009, 0x800B, 0x800E]
+            .iter()
+            .map(|pc| {
+                cpu.program_counter = *pc;
+                trace(&cpu)[..47].trim_end().to_string()
+            })
+            .collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "8000  86 00     STX $00 = 00",
+                "8002  B5 33     LDA $33,X @ 35 = 12",
+                "8004  BD 00 03  LDA $0300,X @ 0302 = 89",
+                "8007  A1 80     LDA ($80,X) @ 82 = 0200 = DB",
+                "8009  B1 89     LDA ($89),Y = 0300 @ 0334 = 00",
+                "800B  6C FF 02  JMP ($02FF) = DB7E",
+                "800E  A7 35    *LAX $35 = 12",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cmos_indirect_jumps_cross_the_page() {
+        let mut cpu = CPU::new();
+        cpu.variant = Variant::Wdc65C02;
+        cpu.load(vec![
+            0x6C, 0xFF, 0x02, // JMP ($02FF)
+            0x7C, 0xFE, 0x02, // JMP ($02FE,X)
+        ]);
+        cpu.reset();
+        cpu.register_x.0 = 0x01;
+        cpu.memory.write(0x02FF, 0x7E);
+        cpu.memory.write(0x0300, 0x12);
+        cpu.memory.write(0x0200, 0xDB);
+
+        let lines: Vec<String> = [0x8000, 0x8003]
+            .iter()
+            .map(|pc| {
+                cpu.program_counter = *pc;
+                trace(&cpu)[..47].trim_end().to_string()
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "8000  6C FF 02  JMP ($02FF) = 127E",
+                "8003  7C FE 02  JMP ($02FE,X) @ 02FF = 127E",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tracer_logs_each_instruction() {
+        let mut cpu = CPU::new();
+        let mut tracer = Tracer::new(Vec::new());
+        cpu.load(vec![0xA9, 0x01, 0xAA, 0x00]); // LDA #$01, TAX, BRK
+        cpu.reset();
+        cpu.run_with_callback(|cpu| tracer.trace(cpu).unwrap());
+
+        let log = String::from_utf8(tracer.into_inner()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("8000  A9 01     LDA #$01"));
+        assert!(lines[1].starts_with("8002  AA        TAX "));
+        assert!(lines[1].contains("A:01 X:00"));
+        assert!(lines[1].ends_with("CYC:9"));
+        assert!(lines[2].starts_with("8003  00        BRK "));
+    }
+}
@@ -0,0 +1,48 @@
+use crate::cpu::opscodes::{OpCode, CMOS_OPCODES, CMOS_OPS_CODES, CPU_OPS_CODES, OPCODES};
+
+/// Which member of the 6502 family the CPU behaves as
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    /// The NES's Ricoh 2A03: an NMOS 6502 with decimal mode disconnected,
+    /// so the D flag can be set but ADC and SBC ignore it
+    #[default]
+    Ricoh2A03,
+    /// A stock NMOS 6502, e.g. the MOS 6502 or 6510
+    Nmos6502,
+    /// The WDC 65C02, with the Rockwell bit instructions and WAI/STP. The
+    /// NMOS undocumented opcodes are all NOPs here.
+    Wdc65C02,
+}
+
+impl Variant {
+    /// Whether ADC and SBC do BCD arithmetic while the D flag is set
+    pub fn has_decimal_mode(self) -> bool {
+        match self {
+            Variant::Ricoh2A03 => false,
+            Variant::Nmos6502 | Variant::Wdc65C02 => true,
+        }
+    }
+
+    /// The CMOS redesign, which fixed the NMOS quirks: JMP ($xxFF) reads
+    /// its pointer across the page and decimal mode sets N and Z from the
+    /// BCD result
+    pub fn is_cmos(self) -> bool {
+        self == Variant::Wdc65C02
+    }
+
+    /// Every opcode this variant decodes, in assembler preference order
+    pub fn opcode_table(self) -> &'static [OpCode] {
+        match self {
+            Variant::Ricoh2A03 | Variant::Nmos6502 => &CPU_OPS_CODES,
+            Variant::Wdc65C02 => &CMOS_OPS_CODES,
+        }
+    }
+
+    /// What each of the 256 opcodes decodes to
+    pub fn opcodes(self) -> &'static [&'static OpCode; 256] {
+        match self {
+            Variant::Ricoh2A03 | Variant::Nmos6502 => &OPCODES,
+            Variant::Wdc65C02 => &CMOS_OPCODES,
+        }
+    }
+}
@@ -0,0 +1,582 @@
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::opscodes::OpCode;
+pub use crate::cpu::processor_status::Flag;
+use crate::cpu::{Step, Stop, CPU};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+
+pub mod gdb;
+pub mod monitor;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuRegister {
+    A,
+    X,
+    Y,
+    S,
+    P,
+    PC,
+}
+
+impl CpuRegister {
+    pub fn value(self, cpu: &CPU) -> u16 {
+        match self {
+            CpuRegister::A => cpu.register_a.0 as u16,
+            CpuRegister::X => cpu.register_x.0 as u16,
+            CpuRegister::Y => cpu.register_y.0 as u16,
+            CpuRegister::S => cpu.register_s.0 as u16,
+            CpuRegister::P => cpu.status.0 as u16,
+            CpuRegister::PC => cpu.program_counter,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    Register(CpuRegister, Comparison, u16),
+    Flag(Flag, bool),
+    All(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        match self {
+            Condition::Register(register, comparison, value) => {
+                let current = register.value(cpu);
+                match comparison {
+                    Comparison::Equal => current == *value,
+                    Comparison::NotEqual => current != *value,
+                    Comparison::Less => current < *value,
+                    Comparison::LessOrEqual => current <= *value,
+                    Comparison::Greater => current > *value,
+                    Comparison::GreaterOrEqual => current >= *value,
+                }
+            }
+            Condition::Flag(flag, set) => cpu.status.is_set(*flag) == *set,
+            Condition::All(conditions) => conditions.iter().all(|c| c.holds(cpu)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, Access::Read)
+                | (WatchKind::Write, Access::Write)
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+/// Why a debugger run call returned
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopReason {
+    /// The program counter reached an execution breakpoint
+    Breakpoint(u16),
+    /// The instruction or interrupt that just ran accessed a watched
+    /// address
+    Watchpoint { addr: u16, access: Access },
+    /// A standalone condition became true
+    Condition(usize),
+    /// The instruction at the program counter is one we break on
+    Opcode(u8),
+    /// The instruction at the program counter is an undocumented one
+    IllegalOpcode(u8),
+    /// A single step finished
+    Step,
+    /// The instruction budget of `run_for` ran out
+    InstructionLimit,
+    /// BRK, KIL, STP or WAI ended execution, for the reason given
+    Halted(Stop),
+}
+
+/// Breakpoints, watchpoints and conditions checked around `CPU::step`
+///
+/// Breakpoints, conditions and opcode breaks are checked before an
+/// instruction runs, so they leave the CPU at the instruction that
+/// triggered them. Resuming from there skips only the check that stopped,
+/// and runs everything else. With nothing set, `run` is plain `CPU::run`.
+///
+/// Watchpoints see every data access the CPU makes on the bus, including
+/// stack traffic, pointer reads and dummy reads, and stop once the
+/// instruction that made the access has finished.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    conditions: Vec<Condition>,
+    break_opcodes: BTreeSet<u8>,
+    pub break_on_illegal: bool,
+    /// Where the last run stopped before an instruction, and why
+    stopped_at: Option<(u16, StopReason)>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr, None);
+    }
+
+    /// Breaks at `addr` only when `condition` holds there
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: Condition) {
+        self.breakpoints.insert(addr, Some(condition));
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (&u16, &Option<Condition>)> {
+        self.breakpoints.iter()
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { range, kind });
+    }
+
+    pub fn remove_watchpoint(&mut self, range: &RangeInclusive<u16>) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.range != *range);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Breaks before any instruction at which `condition` holds, returning
+    /// the index reported in `StopReason::Condition`
+    pub fn add_condition(&mut self, condition: Condition) -> usize {
+        self.conditions.push(condition);
+        self.conditions.len() - 1
+    }
+
+    pub fn clear_conditions(&mut self) {
+        self.conditions.clear();
+    }
+
+    pub fn break_on_opcode(&mut self, code: u8) {
+        self.break_opcodes.insert(code);
+    }
+
+    pub fn clear_opcode_breaks(&mut self) {
+        self.break_opcodes.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.conditions.is_empty()
+            && self.break_opcodes.is_empty()
+            && !self.break_on_illegal
+    }
+
+    /// Runs until something set in the debugger fires or the CPU halts
+    pub fn run(&mut self, cpu: &mut CPU) -> StopReason {
+        if self.is_empty() {
+            loop {
+                match self.step(cpu) {
+                    StopReason::Step => {}
+                    reason => return reason,
+                }
+            }
+        }
+        self.run_until(cpu, None)
+    }
+
+    /// Like `run`, but stops after at most `limit` instructions
+    pub fn run_for(&mut self, cpu: &mut CPU, limit: u64) -> StopReason {
+        self.run_until(cpu, Some(limit))
+    }
+
+    /// Executes exactly one instruction, ignoring breakpoints but not
+    /// watchpoints. A watchpoint the instruction hits is reported even if
+    /// it then halted the CPU.
+    pub fn step(&mut self, cpu: &mut CPU) -> StopReason {
+        self.stopped_at = None;
+        let (step, hit) = self.watch(cpu, CPU::step);
+        match step {
+            Step::Stopped(Stop::IllegalOpcode) => {
+                StopReason::IllegalOpcode(cpu.memory.read(cpu.program_counter))
+            }
+            Step::Stopped(stop) => hit.unwrap_or(StopReason::Halted(stop)),
+            Step::Instruction | Step::Interrupt(_) => hit.unwrap_or(StopReason::Step),
+        }
+    }
+
+    /// Runs `run` on the CPU, returning the first watchpoint its bus
+    /// accesses hit
+    fn watch<T>(&self, cpu: &mut CPU, run: impl FnOnce(&mut CPU) -> T) -> (T, Option<StopReason>) {
+        if self.watchpoints.is_empty() {
+            return (run(cpu), None);
+        }
+        cpu.access_log = Some(Vec::new());
+        let result = run(cpu);
+        let accesses = cpu.access_log.take().unwrap_or_default();
+        let hit = accesses
+            .into_iter()
+            .find(|&(addr, access)| {
+                self.watchpoints
+                    .iter()
+                    .any(|w| w.range.contains(&addr) && w.kind.matches(access))
+            })
+            .map(|(addr, access)| StopReason::Watchpoint { addr, access });
+        (result, hit)
+    }
+
+    fn run_until(&mut self, cpu: &mut CPU, limit: Option<u64>) -> StopReason {
+        let mut executed = 0;
+
+        loop {
+            if limit.is_some_and(|limit| executed >= limit) {
+                return StopReason::InstructionLimit;
+            }
+            // An interrupt that is due comes before the instruction at the
+            // program counter, so that is only checked once it will run
+            if let (_, Some(hit)) = self.watch(cpu, CPU::take_interrupt) {
+                return hit;
+            }
+            if let Some(reason) = self.check_resuming(cpu) {
+                return reason;
+            }
+
+            match self.step(cpu) {
+                StopReason::Step => executed += 1,
+                reason => return reason,
+            }
+        }
+    }
+
+    /// Like `check`, but passes over the stop the last run ended with if
+    /// the CPU is still where it stopped, so that resuming makes progress
+    pub fn check_resuming(&mut self, cpu: &CPU) -> Option<StopReason> {
+        let skip = match self.stopped_at.take() {
+            Some((pc, reason)) if pc == cpu.program_counter => Some(reason),
+            _ => None,
+        };
+        let reason = self.check_skipping(cpu, skip.as_ref())?;
+        self.stopped_at = Some((cpu.program_counter, reason.clone()));
+        Some(reason)
+    }
+
+    /// Returns why execution should stop before the next instruction
+    pub fn check(&self, cpu: &CPU) -> Option<StopReason> {
+        self.check_skipping(cpu, None)
+    }
+
+    fn check_skipping(&self, cpu: &CPU, skip: Option<&StopReason>) -> Option<StopReason> {
+        let pc = cpu.program_counter;
+        let wanted = |reason: StopReason| Some(&reason) != skip;
+
+        if let Some(condition) = self.breakpoints.get(&pc) {
+            if condition
+                .as_ref()
+                .is_none_or(|condition| condition.holds(cpu))
+                && wanted(StopReason::Breakpoint(pc))
+            {
+                return Some(StopReason::Breakpoint(pc));
+            }
+        }
+        for (index, condition) in self.conditions.iter().enumerate() {
+            if condition.holds(cpu) && wanted(StopReason::Condition(index)) {
+                return Some(StopReason::Condition(index));
+            }
+        }
+
+        let code = cpu.memory.read(pc);
+        if self.break_opcodes.contains(&code) && wanted(StopReason::Opcode(code)) {
+            return Some(StopReason::Opcode(code));
+        }
+        let opcode = cpu.variant.opcodes()[code as usize];
+        if opcode.illegal && self.break_on_illegal && wanted(StopReason::IllegalOpcode(code)) {
+            return Some(StopReason::IllegalOpcode(code));
+        }
+        None
+    }
+}
+
+/// The operand address the instruction at the program counter reads or
+/// writes, which the code/data logger marks as data. Stack, pointer and
+/// dummy accesses are left out; watchpoints see those on the bus instead.
+pub fn data_accesses(cpu: &CPU, opcode: &OpCode) -> Vec<(u16, Access)> {
+    match opcode.mode {
+        AddressingMode::Accumulator
+        | AddressingMode::Immediate
+        | AddressingMode::Relative
+        | AddressingMode::Indirect
+        | AddressingMode::Absolute_Indirect_X
+        | AddressingMode::NoneAddressing => return Vec::new(),
+        _ => {}
+    }
+    if matches!(opcode.mnemonic, "JMP" | "JSR") {
+        return Vec::new();
+    }
+
+    let operand = cpu.program_counter.wrapping_add(1);
+    let addr = AddressingMode::get_absolute_address(cpu, &opcode.mode, operand);
+    match opcode.mnemonic {
+        "STA" | "STX" | "STY" | "STZ" | "SAX" | "AHX" | "TAS" | "SHX" | "SHY" => {
+            vec![(addr, Access::Write)]
+        }
+        "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" | "TRB" | "TSB" | "SLO" | "RLA" | "SRE"
+        | "RRA" | "DCP" | "ISC" => vec![(addr, Access::Read), (addr, Access::Write)],
+        mnemonic if mnemonic.starts_with("RMB") || mnemonic.starts_with("SMB") => {
+            vec![(addr, Access::Read), (addr, Access::Write)]
+        }
+        _ => vec![(addr, Access::Read)],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cpu_with(program: Vec<u8>) -> CPU {
+        let mut cpu = CPU::new();
+        cpu.load(program);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn test_execution_breakpoint_and_resume() {
+        let mut cpu = cpu_with(vec![0xA9, 0x01, 0xAA, 0xE8, 0x00]); // LDA #$01, TAX, INX, BRK
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x8003);
+
+        assert_eq!(debugger.run(&mut cpu), StopReason::Breakpoint(0x8003));
+        assert_eq!(cpu.register_x.0, 0x01);
+        assert_eq!(debugger.run(&mut cpu), StopReason::Halted(Stop::Brk));
+        assert_eq!(cpu.register_x.0, 0x02);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint_in_loop() {
+        // LDX #$00; loop: INX; JMP loop
+        let mut cpu = cpu_with(vec![0xA2, 0x00, 0xE8, 0x4C, 0x02, 0x80]);
+        let mut debugger = Debugger::new();
+        debugger.add_conditional_breakpoint(
+            0x8003,
+            Condition::Register(CpuRegister::X, Comparison::Equal, 0x10),
+        );
+
+        assert_eq!(debugger.run(&mut cpu), StopReason::Breakpoint(0x8003));
+        assert_eq!(cpu.register_x.0, 0x10);
+    }
+
+    #[test]
+    fn test_flag_condition() {
+        // LDX #$05; loop: DEX; BNE loop
+        let mut cpu = cpu_with(vec![0xA2, 0x05, 0xCA, 0xD0, 0xFD, 0x00]);
+        let mut debugger = Debugger::new();
+        let index = debugger.add_condition(Condition::Flag(Flag::Zero, true));
+
+        assert_eq!(debugger.run(&mut cpu), StopReason::Condition(index));
+        assert_eq!(cpu.register_x.0, 0);
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut cpu = cpu_with(vec![
+            0xA9, 0x07, // LDA #$07
+            0x8D, 0x00, 0x20, // STA $2000
+            0xAE, 0x02, 0x20, // LDX $2002
+            0x00,
+        ]);
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x2000..=0x2007, WatchKind::Write);
+
+        assert_eq!(
+            debugger.run(&mut cpu),
+            StopReason::Watchpoint {
+                addr: 0x2000,
+                access: Access::Write
+            }
+        );
+        assert_eq!(cpu.program_counter, 0x8005);
+
+        debugger.remove_watchpoint(&(0x2000..=0x2007));
+        debugger.add_watchpoint(0x2002..=0x2002, WatchKind::Read);
+        assert_eq!(
+            debugger.run(&mut cpu),
+            StopReason::Watchpoint {
+                addr: 0x2002,
+                access: Access::Read
+            }
+        );
+        assert_eq!(cpu.memory.read(0x2000), 0x07);
+    }
+
+    #[test]
+    fn test_watchpoints_see_every_bus_access() {
+        let mut cpu = cpu_with(vec![
+            0x48, // PHA
+            0xA1, 0x10, // LDA ($10,X)
+            0xBD, 0xF0, 0x20, // LDA $20F0,X
+            0x00,
+        ]);
+        cpu.register_x.0 = 0x20;
+        cpu.memory.write_u16(0x30, 0x0300);
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x0100..=0x01FF, WatchKind::Write);
+        debugger.add_watchpoint(0x0030..=0x0030, WatchKind::Read);
+        // Crossing a page, LDA abs,X first reads $2010 from the wrong page
+        debugger.add_watchpoint(0x2010..=0x2010, WatchKind::Read);
+
+        let stops: Vec<StopReason> = (0..3).map(|_| debugger.run(&mut cpu)).collect();
+        assert_eq!(
+            stops,
+            vec![
+                StopReason::Watchpoint {
+                    addr: 0x01FD,
+                    access: Access::Write
+                },
+                StopReason::Watchpoint {
+                    addr: 0x0030,
+                    access: Access::Read
+                },
+                StopReason::Watchpoint {
+                    addr: 0x2010,
+                    access: Access::Read
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watchpoint_hit_by_the_halting_instruction() {
+        let mut cpu = cpu_with(vec![0xE8, 0x00]);
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x0100..=0x01FF, WatchKind::Write);
+        assert_eq!(
+            debugger.run(&mut cpu),
+            StopReason::Watchpoint {
+                addr: 0x01FD,
+                access: Access::Write
+            }
+        );
+
+        debugger.remove_watchpoint(&(0x0100..=0x01FF));
+        let mut cpu = cpu_with(vec![0xE8, 0x00]);
+        assert_eq!(debugger.run(&mut cpu), StopReason::Halted(Stop::Brk));
+    }
+
+    #[test]
+    fn test_resuming_checks_everything_but_the_last_stop() {
+        // INX; INX; INX; BRK
+        let mut cpu = cpu_with(vec![0xE8, 0xE8, 0xE8, 0x00]);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x8000);
+        debugger.add_breakpoint(0x8001);
+        let index = debugger.add_condition(Condition::Register(
+            CpuRegister::X,
+            Comparison::Equal,
+            0x01,
+        ));
+
+        // A breakpoint at the entry point fires, and the condition that also
+        // holds at $8001 still does after the breakpoint there
+        assert_eq!(debugger.run(&mut cpu), StopReason::Breakpoint(0x8000));
+        assert_eq!(debugger.run(&mut cpu), StopReason::Breakpoint(0x8001));
+        assert_eq!(debugger.run(&mut cpu), StopReason::Condition(index));
+        assert_eq!(cpu.program_counter, 0x8001);
+
+        // After an instruction limit the next instruction is checked too
+        debugger.clear_conditions();
+        let mut cpu = cpu_with(vec![0xE8, 0xE8, 0xE8, 0x00]);
+        assert_eq!(debugger.run_for(&mut cpu, 1), StopReason::Breakpoint(0x8000));
+        assert_eq!(debugger.run_for(&mut cpu, 1), StopReason::InstructionLimit);
+        assert_eq!(debugger.run_for(&mut cpu, 1), StopReason::Breakpoint(0x8001));
+    }
+
+    #[test]
+    fn test_break_on_opcodes() {
+        let mut cpu = cpu_with(vec![0xE8, 0x02, 0x00]);
+        let mut debugger = Debugger::new();
+        debugger.break_on_illegal = true;
+        assert_eq!(debugger.run(&mut cpu), StopReason::IllegalOpcode(0x02));
+
+        // Without the break, KIL jams the CPU
+        let mut cpu = cpu_with(vec![0xE8, 0x02, 0x00]);
+        assert_eq!(
+            Debugger::new().run_for(&mut cpu, 10),
+            StopReason::Halted(Stop::Jammed)
+        );
+        assert!(cpu.jammed);
+
+        let mut cpu = cpu_with(vec![0xA7, 0x10, 0x00]); // LAX $10
+        cpu.reject_illegal_opcodes = true;
+        assert_eq!(
+            Debugger::new().step(&mut cpu),
+            StopReason::IllegalOpcode(0xA7)
+        );
+        assert_eq!(cpu.program_counter, 0x8000);
+
+        let mut cpu = cpu_with(vec![0xE8, 0xE8, 0x00]);
+        let mut debugger = Debugger::new();
+        debugger.break_on_opcode(0x00);
+        assert_eq!(debugger.run(&mut cpu), StopReason::Opcode(0x00));
+        assert_eq!(cpu.register_x.0, 2);
+    }
+
+    #[test]
+    fn test_run_for_limits_instructions() {
+        let mut cpu = cpu_with(vec![0xE8, 0x4C, 0x00, 0x80]); // loop: INX; JMP loop
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run_for(&mut cpu, 10), StopReason::InstructionLimit);
+        assert_eq!(cpu.register_x.0, 5);
+        assert_eq!(debugger.step(&mut cpu), StopReason::Step);
+        assert_eq!(cpu.register_x.0, 6);
+    }
+
+    #[test]
+    fn test_breakpoints_wait_for_a_due_interrupt() {
+        let mut cpu = cpu_with(vec![0xE8, 0xE8, 0x00]); // INX, INX, BRK
+        cpu.memory.load_at(0x9000, &[0xC8, 0x40]); // nmi: INY; RTI
+        cpu.memory.write_u16(CPU::NMI_VECTOR, 0x9000);
+        cpu.trigger_nmi();
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x8001);
+
+        // The NMI comes before the second INX, which is where it stops
+        assert_eq!(debugger.run(&mut cpu), StopReason::Breakpoint(0x8001));
+        assert_eq!((cpu.register_x.0, cpu.register_y.0), (1, 1));
+        assert_eq!(debugger.run(&mut cpu), StopReason::Halted(Stop::Brk));
+        assert_eq!(cpu.register_x.0, 2);
+    }
+}
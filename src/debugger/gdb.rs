@@ -0,0 +1,565 @@
+use crate::cpu::processor_status::ProcessorStatus;
+use crate::cpu::CPU;
+use crate::debugger::{Access, Debugger, StopReason, WatchKind};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+/// Instructions run between polls for a ^C from the client
+const SLICE: u64 = 10_000;
+
+/// Largest packet the stub accepts, as advertised in `qSupported`. Replies
+/// are kept within it too, whatever length the client asks for.
+const PACKET_SIZE: usize = 0x4000;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// GDB has no built-in 6502 target, so the register layout is described to
+/// the client with a target description
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.mos6502.core">
+    <reg name="a" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="x" bitsize="8" regnum="1" type="uint8"/>
+    <reg name="y" bitsize="8" regnum="2" type="uint8"/>
+    <reg name="s" bitsize="8" regnum="3" type="uint8"/>
+    <reg name="p" bitsize="8" regnum="4" type="uint8"/>
+    <reg name="pc" bitsize="16" regnum="5" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// GDB Remote Serial Protocol server for a single client
+///
+/// Registers are numbered A, X, Y, S, P, PC, with PC sent little-endian,
+/// and memory is the flat 64 KiB address space. Software and hardware
+/// breakpoints (`Z0`/`Z1`) both map to `Debugger` breakpoints, and `Z2`-`Z4`
+/// map to watchpoints. BRK halts the CPU and is reported as SIGTRAP, so the
+/// final state can still be inspected.
+pub struct GdbStub {
+    pub cpu: CPU,
+    pub debugger: Debugger,
+}
+
+impl GdbStub {
+    pub fn new(cpu: CPU) -> Self {
+        GdbStub {
+            cpu,
+            debugger: Debugger::new(),
+        }
+    }
+
+    /// Binds to `127.0.0.1:port` and serves the first client to connect
+    pub fn listen(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        self.serve(&listener)
+    }
+
+    pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        self.session(stream)
+    }
+
+    /// Answers packets until the client detaches, kills or disconnects
+    pub fn session(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut connection = Connection::new(stream);
+
+        while let Some(packet) = connection.read_packet()? {
+            let reply = match self.handle(&packet, &mut connection)? {
+                Some(reply) => reply,
+                None => break,
+            };
+            connection.write_packet(&reply)?;
+            if packet == "D" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the reply to `packet`, or `None` when the session is over
+    fn handle(&mut self, packet: &str, connection: &mut Connection) -> io::Result<Option<String>> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.change_breakpoint(command == "Z", args),
+            "s" | "c" => {
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(addr) => self.cpu.program_counter = addr,
+                        Err(_) => return Ok(Some(error_reply())),
+                    }
+                }
+                let reason = match command {
+                    "s" => Some(self.debugger.step(&mut self.cpu)),
+                    _ => self.resume(connection)?,
+                };
+                self.stop_reply(reason)
+            }
+            "H" | "D" => String::from("OK"),
+            "k" => return Ok(None),
+            "q" => self.query(args),
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    /// Runs until the debugger stops or the client sends ^C
+    fn resume(&mut self, connection: &mut Connection) -> io::Result<Option<StopReason>> {
+        loop {
+            match self.debugger.run_for(&mut self.cpu, SLICE) {
+                StopReason::InstructionLimit => {}
+                reason => return Ok(Some(reason)),
+            }
+            if connection.interrupted()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// `None` means the client interrupted execution
+    fn stop_reply(&self, reason: Option<StopReason>) -> String {
+        match reason {
+            None => stop_reply(SIGINT),
+            Some(StopReason::Watchpoint { addr, access }) => {
+                let kind = match access {
+                    Access::Read => "rwatch",
+                    Access::Write => "watch",
+                };
+                format!("T{:02x}{}:{:04x};", SIGTRAP, kind, addr)
+            }
+            Some(StopReason::Breakpoint(_)) => format!("T{:02x}swbreak:;", SIGTRAP),
+            Some(_) => stop_reply(SIGTRAP),
+        }
+    }
+
+    fn registers(&self) -> [u8; 7] {
+        let [pc_lo, pc_hi] = self.cpu.program_counter.to_le_bytes();
+        [
+            self.cpu.register_a.0,
+            self.cpu.register_x.0,
+            self.cpu.register_y.0,
+            self.cpu.register_s.0,
+            self.cpu.status.0,
+            pc_lo,
+            pc_hi,
+        ]
+    }
+
+    fn read_registers(&self) -> String {
+        encode_hex(&self.registers())
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        match decode_hex(args).as_deref() {
+            Some(&[a, x, y, s, p, pc_lo, pc_hi]) => {
+                self.cpu.register_a.0 = a;
+                self.cpu.register_x.0 = x;
+                self.cpu.register_y.0 = y;
+                self.cpu.register_s.0 = s;
+                self.cpu.status = ProcessorStatus::new(p);
+                self.cpu.program_counter = u16::from_le_bytes([pc_lo, pc_hi]);
+                String::from("OK")
+            }
+            _ => error_reply(),
+        }
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        let registers = self.registers();
+        match usize::from_str_radix(args, 16) {
+            Ok(index @ 0..=4) => encode_hex(&registers[index..=index]),
+            Ok(5) => encode_hex(&registers[5..]),
+            _ => error_reply(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let (index, value) = match args.split_once('=') {
+            Some((index, value)) => (usize::from_str_radix(index, 16), decode_hex(value)),
+            None => return error_reply(),
+        };
+        match (index, value.as_deref()) {
+            (Ok(0), Some(&[a])) => self.cpu.register_a.0 = a,
+            (Ok(1), Some(&[x])) => self.cpu.register_x.0 = x,
+            (Ok(2), Some(&[y])) => self.cpu.register_y.0 = y,
+            (Ok(3), Some(&[s])) => self.cpu.register_s.0 = s,
+            (Ok(4), Some(&[p])) => self.cpu.status = ProcessorStatus::new(p),
+            (Ok(5), Some(&[lo, hi])) => self.cpu.program_counter = u16::from_le_bytes([lo, hi]),
+            _ => return error_reply(),
+        }
+        String::from("OK")
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        match parse_address_length(args) {
+            Some((addr, len)) => {
+                // Each byte is sent as two hex digits
+                let bytes: Vec<u8> = (0..len.min(PACKET_SIZE / 2))
+                    .map(|offset| self.cpu.memory.read(addr.wrapping_add(offset as u16)))
+                    .collect();
+                encode_hex(&bytes)
+            }
+            None => error_reply(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let (region, data) = match args.split_once(':') {
+            Some(parts) => parts,
+            None => return error_reply(),
+        };
+        match (parse_address_length(region), decode_hex(data)) {
+            (Some((addr, len)), Some(bytes)) if bytes.len() == len => {
+                for (offset, byte) in bytes.into_iter().enumerate() {
+                    self.cpu
+                        .memory
+                        .write(addr.wrapping_add(offset as u16), byte);
+                }
+                String::from("OK")
+            }
+            _ => error_reply(),
+        }
+    }
+
+    /// `Z`/`z` packets are `type,addr,kind`
+    fn change_breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (kind, region) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(kind), Some(addr), Some(len)) => (kind, format!("{},{}", addr, len)),
+            _ => return error_reply(),
+        };
+        let (addr, len) = match parse_address_length(&region) {
+            Some(parsed) => parsed,
+            None => return error_reply(),
+        };
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(addr);
+                } else {
+                    self.debugger.remove_breakpoint(addr);
+                }
+                return String::from("OK");
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return String::new(),
+        };
+
+        let len = len.clamp(1, 0x10000) as u32;
+        let end = (addr as u32 + len - 1).min(0xFFFF) as u16;
+        if insert {
+            self.debugger.add_watchpoint(addr..=end, watch);
+        } else {
+            self.debugger.remove_watchpoint(&(addr..=end));
+        }
+        String::from("OK")
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            return format!("PacketSize={:x};qXfer:features:read+;swbreak+", PACKET_SIZE);
+        }
+        if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            return match parse_address_length(range) {
+                Some((offset, len)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = (offset as usize).min(xml.len());
+                    // One byte of the reply goes on the `m`/`l` prefix
+                    let len = len.min(PACKET_SIZE - 1);
+                    let end = start.saturating_add(len).min(xml.len());
+                    let more = if end < xml.len() { 'm' } else { 'l' };
+                    format!("{}{}", more, String::from_utf8_lossy(&xml[start..end]))
+                }
+                None => error_reply(),
+            };
+        }
+        match args {
+            "Attached" => String::from("1"),
+            "C" => String::from("QC1"),
+            "fThreadInfo" => String::from("m1"),
+            "sThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Framing and acknowledgement of `$payload#checksum` packets
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Self {
+        Connection {
+            stream,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.position == self.buffer.len() {
+            let mut chunk = [0; 1024];
+            let read = self.stream.read(&mut chunk)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            self.buffer = chunk[..read].to_vec();
+            self.position = 0;
+        }
+        self.position += 1;
+        Ok(Some(self.buffer[self.position - 1]))
+    }
+
+    /// Returns the next well-formed packet, or `None` once the client hangs up
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip acks and stray ^C until the start of a packet
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut payload = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => payload.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut() {
+                match self.read_byte()? {
+                    Some(byte) => *digit = byte,
+                    None => return Ok(None),
+                }
+            }
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|text| u8::from_str_radix(text, 16).ok());
+            if expected == Some(checksum_of(&payload)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, payload: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", payload, checksum_of(payload.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+
+    /// Polls, without blocking, for the ^C GDB sends to interrupt `c`
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if self.position < self.buffer.len() {
+            return Ok(self.buffer[self.position..].contains(&0x03));
+        }
+        self.stream.set_nonblocking(true)?;
+        let mut chunk = [0; 1024];
+        let result = self.stream.read(&mut chunk);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(read) => {
+                self.buffer = chunk[..read].to_vec();
+                self.position = 0;
+                Ok(self.buffer.contains(&0x03))
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+fn checksum_of(payload: &[u8]) -> u8 {
+    payload
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn error_reply() -> String {
+    String::from("E01")
+}
+
+fn parse_address_length(args: &str) -> Option<(u16, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    let addr = u32::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    Some((u16::try_from(addr).ok()?, len))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    /// Minimal scripted client standing in for GDB
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn send(&mut self, payload: &str) -> String {
+            let packet = format!("${}#{:02x}", payload, checksum_of(payload.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+
+            let mut reply = Vec::new();
+            let mut byte = [0];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                match byte[0] {
+                    b'+' if reply.is_empty() => {}
+                    b'#' => break,
+                    _ => reply.push(byte[0]),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+
+            assert_eq!(reply[0], b'$');
+            let payload = &reply[1..];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16);
+            assert_eq!(checksum, Ok(checksum_of(payload)));
+            String::from_utf8(payload.to_vec()).unwrap()
+        }
+
+        /// `k` gets no reply; the stub just closes the connection
+        fn kill(mut self) {
+            let packet = format!("$k#{:02x}", checksum_of(b"k"));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+        }
+    }
+
+    fn connect(program: Vec<u8>) -> (Client, thread::JoinHandle<CPU>) {
+        let mut cpu = CPU::new();
+        cpu.load(program);
+        cpu.reset();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut stub = GdbStub::new(cpu);
+            stub.serve(&listener).unwrap();
+            stub.cpu
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        (Client { stream }, server)
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let (mut client, server) = connect(vec![0xA9, 0x42, 0x00]);
+
+        assert_eq!(client.send("?"), "S05");
+        assert_eq!(client.send("g"), "000000fd340080");
+        assert_eq!(client.send("s"), "S05");
+        assert_eq!(client.send("p0"), "42");
+        assert_eq!(client.send("p5"), "0280");
+
+        assert_eq!(client.send("M0200,3:010203"), "OK");
+        assert_eq!(client.send("m01ff,5"), "0001020300");
+        assert_eq!(client.send("P1=7f"), "OK");
+        assert_eq!(client.send("P4=c1"), "OK");
+        assert_eq!(client.send("G0102030405"), "E01");
+        assert_eq!(client.send("vMustReplyEmpty"), "");
+        assert_eq!(client.send("D"), "OK");
+
+        let cpu = server.join().unwrap();
+        assert_eq!(cpu.register_x.0, 0x7F);
+        assert_eq!(cpu.status.0, 0xF1);
+        assert_eq!(cpu.memory.read(0x0201), 0x02);
+    }
+
+    #[test]
+    fn test_breakpoints_and_continue() {
+        // LDX #$03; loop: DEX; BNE loop; STX $10; BRK
+        let program = vec![0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x86, 0x10, 0x00];
+        let (mut client, server) = connect(program);
+
+        assert_eq!(client.send("Z0,8002,1"), "OK");
+        assert_eq!(client.send("c"), "T05swbreak:;");
+        assert_eq!(client.send("c"), "T05swbreak:;");
+        assert_eq!(client.send("p1"), "02");
+
+        assert_eq!(client.send("z0,8002,1"), "OK");
+        assert_eq!(client.send("Z2,10,1"), "OK");
+        assert_eq!(client.send("c"), "T05watch:0010;");
+        assert_eq!(client.send("p5"), "0780");
+        assert_eq!(client.send("z2,10,1"), "OK");
+        assert_eq!(client.send("c"), "S05");
+        client.kill();
+
+        let cpu = server.join().unwrap();
+        assert_eq!(cpu.memory.read(0x0010), 0);
+        // BRK at $8007 pushed the address past its padding byte
+        let return_address = cpu.memory.read_u16(0x0102 + cpu.register_s.0 as u16);
+        assert_eq!(return_address, 0x8009);
+    }
+
+    #[test]
+    fn test_target_description() {
+        let (mut client, server) = connect(vec![0x00]);
+
+        assert!(client
+            .send("qSupported:xmlRegisters=i386")
+            .contains("qXfer:features:read+"));
+        let first = client.send("qXfer:features:read:target.xml:0,20");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x20]));
+        let rest = client.send("qXfer:features:read:target.xml:20,1000");
+        assert_eq!(rest, format!("l{}", &TARGET_XML[0x20..]));
+        client.kill();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_oversized_lengths() {
+        let (mut client, server) = connect(vec![0x00]);
+
+        assert_eq!(client.send("m0,ffffffffffffffff").len(), PACKET_SIZE);
+        assert_eq!(client.send("Z2,0,10000"), "OK");
+        assert_eq!(client.send("z2,0,10000"), "OK");
+        assert_eq!(client.send("Z3,fff0,ffffffff"), "OK");
+        let xml = client.send("qXfer:features:read:target.xml:10,ffffffffffffffff");
+        assert_eq!(xml, format!("l{}", &TARGET_XML[0x10..]));
+        client.kill();
+        server.join().unwrap();
+    }
+}
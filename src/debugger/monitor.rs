@@ -0,0 +1,696 @@
+use crate::cpu::assembler::assemble_at;
+use crate::cpu::disassembler::{disassemble_as, disassemble_one_as};
+use crate::cpu::processor_status::ProcessorStatus;
+use crate::cpu::{Stop, CPU};
+use crate::debugger::{
+    Access, Comparison, Condition, CpuRegister, Debugger, StopReason, WatchKind,
+};
+use crate::symbols::SymbolTable;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+const RTS: u8 = 0x60;
+
+const HELP: &str = "\
+step [n]                 execute n instructions (default 1)
+next                     step, running JSR subroutines to completion
+finish                   run until the current subroutine returns
+continue                 run until a breakpoint, watchpoint or BRK
+break ADDR [REG OP VAL]  break at ADDR, optionally only when REG OP VAL
+delete ADDR              remove the breakpoint at ADDR
+watch [r|w|rw] A[-B]     break on reads and/or writes to A..=B
+unwatch A[-B]            remove a watchpoint
+regs                     show registers
+set REG VALUE            change A, X, Y, S, P or PC
+mem ADDR [LEN]           hex dump memory
+poke ADDR BYTE...        write bytes to memory
+asm ADDR INSTRUCTION     assemble an instruction into memory at ADDR
+dis [ADDR] [COUNT]       disassemble, around PC by default
+stack                    dump the stack page at $0100-$01FF
+label ADDR NAME [TEXT]   name ADDR, with an optional comment
+unlabel ADDR             remove the label at ADDR
+symbols FILE             load labels from a .dbg, .nl or .mlb file
+quit                     exit
+Numbers are hexadecimal, with an optional $ or 0x prefix. Labels can be
+used wherever an address is expected.";
+
+/// Line-oriented, gdb/monitor-style front end for `Debugger`
+///
+/// Commands are read one per line and results written as plain text, so a
+/// session can be scripted by piping commands in.
+pub struct Monitor {
+    pub cpu: CPU,
+    pub debugger: Debugger,
+    pub symbols: SymbolTable,
+}
+
+impl Monitor {
+    pub fn new(cpu: CPU) -> Self {
+        Monitor {
+            cpu,
+            debugger: Debugger::new(),
+            symbols: SymbolTable::new(),
+        }
+    }
+
+    /// Reads commands from `input` until `quit` or end of input
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        self.print_location(&mut out)?;
+        write!(out, "(nes) ")?;
+        out.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            if !self.execute(line.trim(), &mut out)? {
+                break;
+            }
+            write!(out, "(nes) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+
+    /// Runs a single command, returning false when the session should end
+    pub fn execute<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(true),
+        };
+
+        let result = match command {
+            "s" | "step" => self.step(args, out),
+            "n" | "next" => self.next(out),
+            "finish" => self.finish(out),
+            "c" | "continue" => {
+                let reason = self.debugger.run(&mut self.cpu);
+                self.report(reason, out)
+            }
+            "b" | "break" => self.add_breakpoint(args, out),
+            "delete" => self.delete_breakpoint(args, out),
+            "watch" => self.watch(args, out),
+            "unwatch" => self.unwatch(args, out),
+            "r" | "regs" => self.print_registers(out).map_err(Into::into),
+            "set" => self.set_register(args, out),
+            "x" | "mem" => self.dump_memory(args, out),
+            "poke" => self.poke(args, out),
+            "a" | "asm" => self.assemble(args, out),
+            "d" | "dis" => self.disassemble(args, out),
+            "stack" => self.dump_stack(out).map_err(Into::into),
+            "label" => self.label(args, out),
+            "unlabel" => self.unlabel(args, out),
+            "symbols" => self.load_symbols(args, out),
+            "h" | "help" => writeln!(out, "{}", HELP).map_err(Into::into),
+            "q" | "quit" => return Ok(false),
+            _ => Err(CommandError::Usage(format!(
+                "unknown command `{}`",
+                command
+            ))),
+        };
+
+        match result {
+            Ok(()) => Ok(true),
+            Err(CommandError::Io(error)) => Err(error),
+            Err(CommandError::Usage(message)) => {
+                writeln!(out, "error: {}", message)?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn step<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let count = match args.first() {
+            Some(arg) => parse_number(arg)?,
+            None => 1,
+        };
+        for _ in 0..count {
+            match self.debugger.step(&mut self.cpu) {
+                StopReason::Step => {}
+                reason => return self.report(reason, out),
+            }
+        }
+        self.print_location(out)?;
+        Ok(())
+    }
+
+    /// Steps over JSR by running until the stack is back where it was
+    fn next<W: Write>(&mut self, out: &mut W) -> CommandResult {
+        let instruction =
+            disassemble_one_as(&self.cpu.memory, self.cpu.program_counter, self.cpu.variant);
+        if instruction.mnemonic() != "JSR" {
+            return self.step(&[], out);
+        }
+
+        let return_addr = self.cpu.program_counter.wrapping_add(3);
+        let stack = self.cpu.register_s.0;
+        self.run_until(out, |cpu, _| {
+            cpu.program_counter == return_addr && cpu.register_s.0 == stack
+        })
+    }
+
+    /// Runs until an RTS pops the frame that was current when called
+    fn finish<W: Write>(&mut self, out: &mut W) -> CommandResult {
+        let stack = self.cpu.register_s.0;
+        self.run_until(out, |cpu, code| {
+            code == RTS && cpu.register_s.0 == stack.wrapping_add(2)
+        })
+    }
+
+    /// Runs until `done` holds after an instruction, given the opcode just
+    /// executed, or until the debugger stops first
+    fn run_until<W: Write, F>(&mut self, out: &mut W, done: F) -> CommandResult
+    where
+        F: Fn(&CPU, u8) -> bool,
+    {
+        loop {
+            if let Some(reason) = self.debugger.check_resuming(&self.cpu) {
+                return self.report(reason, out);
+            }
+
+            let code = self.cpu.memory.read(self.cpu.program_counter);
+            match self.debugger.step(&mut self.cpu) {
+                StopReason::Step => {}
+                reason => return self.report(reason, out),
+            }
+            if done(&self.cpu, code) {
+                self.print_location(out)?;
+                return Ok(());
+            }
+        }
+    }
+
+    fn add_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let addr = parse_address(args.first(), &self.symbols)?;
+        match args.len() {
+            1 => self.debugger.add_breakpoint(addr),
+            4 => {
+                let condition = parse_condition(&args[1..])?;
+                self.debugger.add_conditional_breakpoint(addr, condition);
+            }
+            _ => return Err(usage("break ADDR [REG OP VALUE]")),
+        }
+        writeln!(out, "Breakpoint at {}", self.describe(addr))?;
+        Ok(())
+    }
+
+    fn delete_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let addr = parse_address(args.first(), &self.symbols)?;
+        if self.debugger.remove_breakpoint(addr) {
+            writeln!(out, "Deleted breakpoint at ${:04X}", addr)?;
+            Ok(())
+        } else {
+            Err(CommandError::Usage(format!(
+                "no breakpoint at ${:04X}",
+                addr
+            )))
+        }
+    }
+
+    fn watch<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let (kind, range) = match args {
+            [range] => (WatchKind::ReadWrite, *range),
+            ["r", range] => (WatchKind::Read, *range),
+            ["w", range] => (WatchKind::Write, *range),
+            ["rw", range] => (WatchKind::ReadWrite, *range),
+            _ => return Err(usage("watch [r|w|rw] START[-END]")),
+        };
+        let range = parse_range(range, &self.symbols)?;
+        writeln!(
+            out,
+            "Watchpoint on ${:04X}-${:04X} ({:?})",
+            range.start(),
+            range.end(),
+            kind
+        )?;
+        self.debugger.add_watchpoint(range, kind);
+        Ok(())
+    }
+
+    fn unwatch<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let range = args.first().ok_or_else(|| usage("unwatch START[-END]"))?;
+        let range = parse_range(range, &self.symbols)?;
+        if self.debugger.remove_watchpoint(&range) {
+            writeln!(out, "Deleted watchpoint")?;
+            Ok(())
+        } else {
+            Err(CommandError::Usage(String::from("no such watchpoint")))
+        }
+    }
+
+    fn set_register<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let (register, value) = match args {
+            [register, value] => (parse_register(register)?, parse_number(value)?),
+            _ => return Err(usage("set REG VALUE")),
+        };
+        let byte = || u8::try_from(value).map_err(|_| usage("register holds one byte"));
+        match register {
+            CpuRegister::A => self.cpu.register_a.0 = byte()?,
+            CpuRegister::X => self.cpu.register_x.0 = byte()?,
+            CpuRegister::Y => self.cpu.register_y.0 = byte()?,
+            CpuRegister::S => self.cpu.register_s.0 = byte()?,
+            CpuRegister::P => self.cpu.status = ProcessorStatus::new(byte()?),
+            CpuRegister::PC => {
+                self.cpu.program_counter =
+                    u16::try_from(value).map_err(|_| usage("address out of range"))?
+            }
+        }
+        self.print_registers(out)?;
+        Ok(())
+    }
+
+    fn dump_memory<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let start = parse_address(args.first(), &self.symbols)?;
+        let len = match args.get(1) {
+            Some(len) => parse_number(len)?,
+            None => 0x40,
+        };
+        self.hex_dump(start as u32, len, out)?;
+        Ok(())
+    }
+
+    fn hex_dump<W: Write>(&self, start: u32, len: u32, out: &mut W) -> io::Result<()> {
+        let end = start.saturating_add(len).min(0x10000);
+        let mut row = start & !0xF;
+        while row < end {
+            write!(out, "{:04X}:", row)?;
+            let mut ascii = String::new();
+            for addr in row..row + 16 {
+                if addr < start || addr >= end {
+                    write!(out, "   ")?;
+                    ascii.push(' ');
+                    continue;
+                }
+                let byte = self.cpu.memory.read(addr as u16);
+                write!(out, " {:02X}", byte)?;
+                ascii.push(match byte {
+                    0x20..=0x7E => byte as char,
+                    _ => '.',
+                });
+            }
+            writeln!(out, "  {}", ascii)?;
+            row += 16;
+        }
+        Ok(())
+    }
+
+    fn poke<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let start = parse_address(args.first(), &self.symbols)?;
+        if args.len() < 2 {
+            return Err(usage("poke ADDR BYTE..."));
+        }
+        for (offset, arg) in args[1..].iter().enumerate() {
+            let byte = u8::try_from(parse_number(arg)?).map_err(|_| usage("bytes are 00-FF"))?;
+            self.cpu
+                .memory
+                .write(start.wrapping_add(offset as u16), byte);
+        }
+        self.hex_dump(start as u32, (args.len() - 1) as u32, out)?;
+        Ok(())
+    }
+
+    fn assemble<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let start = parse_address(args.first(), &self.symbols)?;
+        if args.len() < 2 {
+            return Err(usage("asm ADDR INSTRUCTION"));
+        }
+        let labels: BTreeMap<String, u16> = self
+            .symbols
+            .iter()
+            .map(|(addr, symbol)| (symbol.name.clone(), addr))
+            .collect();
+        let bytes = assemble_at(start, &args[1..].join(" "), &labels, self.cpu.variant)
+            .map_err(|error| CommandError::Usage(error.message))?;
+
+        self.cpu.memory.load_at(start, &bytes);
+        let end = start.wrapping_add(bytes.len() as u16);
+        for instruction in disassemble_as(&self.cpu.memory, start, end, self.cpu.variant) {
+            writeln!(out, "{}", instruction.display_with(&self.symbols))?;
+        }
+        Ok(())
+    }
+
+    fn disassemble<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let pc = self.cpu.program_counter;
+        // No more instructions than there are addresses
+        let count = match args.get(1) {
+            Some(count) => parse_number(count)?.min(0x10000),
+            None => 10,
+        };
+        let start = match args.first() {
+            Some(addr) => parse_address(Some(addr), &self.symbols)?,
+            None => self.start_before(pc, 3),
+        };
+
+        let end = (start as u32)
+            .saturating_add(count.saturating_mul(3))
+            .min(0xFFFF) as u16;
+        for instruction in disassemble_as(&self.cpu.memory, start, end, self.cpu.variant)
+            .iter()
+            .take(count as usize)
+        {
+            if let Some(name) = self.symbols.name(instruction.address) {
+                writeln!(out, "{}:", name)?;
+            }
+            let marker = if instruction.address == pc { '>' } else { ' ' };
+            writeln!(
+                out,
+                "{} {}",
+                marker,
+                instruction.display_with(&self.symbols)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Finds an address up to `count` instructions before `pc` from which a
+    /// linear sweep lands exactly on `pc`
+    fn start_before(&self, pc: u16, count: usize) -> u16 {
+        let span = count.saturating_mul(3).min(0xFFFF) as u16;
+        for back in (1..=span).rev() {
+            let start = match pc.checked_sub(back) {
+                Some(start) => start,
+                None => continue,
+            };
+            let lines = disassemble_as(&self.cpu.memory, start, pc, self.cpu.variant);
+            if lines.len() <= count + 1 && lines.iter().any(|line| line.address == pc) {
+                return start;
+            }
+        }
+        pc
+    }
+
+    fn dump_stack<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let sp = 0x0100 + self.cpu.register_s.0 as u16;
+        writeln!(out, "SP=${:04X}", sp)?;
+        for row in (0x0100..0x0200).step_by(16) {
+            write!(out, "{:04X}:", row)?;
+            for addr in row..row + 16 {
+                let marker = if addr == sp { '>' } else { ' ' };
+                write!(out, "{}{:02X}", marker, self.cpu.memory.read(addr))?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn label<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let (addr, name, comment) = match args {
+            [addr, name, comment @ ..] => {
+                (parse_address(Some(addr), &self.symbols)?, *name, comment)
+            }
+            _ => return Err(usage("label ADDR NAME [COMMENT]")),
+        };
+        let comment = comment.join(" ");
+        let comment = Some(comment.as_str()).filter(|comment| !comment.is_empty());
+        self.symbols.define(addr, name, comment);
+        writeln!(out, "{} = ${:04X}", name, addr)?;
+        Ok(())
+    }
+
+    fn unlabel<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let addr = parse_address(args.first(), &self.symbols)?;
+        if !self.symbols.remove(addr) {
+            return Err(CommandError::Usage(format!("no label at ${:04X}", addr)));
+        }
+        writeln!(out, "Removed label at ${:04X}", addr)?;
+        Ok(())
+    }
+
+    fn load_symbols<W: Write>(&mut self, args: &[&str], out: &mut W) -> CommandResult {
+        let path = args.first().ok_or_else(|| usage("symbols FILE"))?;
+        self.symbols
+            .load_file(Path::new(path))
+            .map_err(|error| CommandError::Usage(format!("{}: {}", path, error)))?;
+        writeln!(out, "Loaded {}", path)?;
+        Ok(())
+    }
+
+    /// `$8000 <reset>`, or just the address when it has no label
+    fn describe(&self, addr: u16) -> String {
+        match self.symbols.name(addr) {
+            Some(name) => format!("${:04X} <{}>", addr, name),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    fn print_registers<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, name)| match self.cpu.status.0 & (0x80 >> i) {
+                0 => '.',
+                _ => name,
+            })
+            .collect();
+        writeln!(
+            out,
+            "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} [{}] SP:{:02X} PC:{:04X} CYC:{}",
+            self.cpu.register_a.0,
+            self.cpu.register_x.0,
+            self.cpu.register_y.0,
+            self.cpu.status.0,
+            flags,
+            self.cpu.register_s.0,
+            self.cpu.program_counter,
+            self.cpu.cycles
+        )
+    }
+
+    fn print_location<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let instruction =
+            disassemble_one_as(&self.cpu.memory, self.cpu.program_counter, self.cpu.variant);
+        writeln!(out, "{}", instruction.display_with(&self.symbols))
+    }
+
+    fn report<W: Write>(&self, reason: StopReason, out: &mut W) -> CommandResult {
+        match reason {
+            StopReason::Breakpoint(addr) => writeln!(out, "Breakpoint at {}", self.describe(addr))?,
+            StopReason::Watchpoint { addr, access } => {
+                let access = match access {
+                    Access::Read => "Read",
+                    Access::Write => "Write",
+                };
+                writeln!(out, "{} of {}", access, self.describe(addr))?
+            }
+            StopReason::Condition(index) => writeln!(out, "Condition {} met", index)?,
+            StopReason::Opcode(code) => writeln!(out, "Opcode ${:02X}", code)?,
+            StopReason::IllegalOpcode(code) => writeln!(out, "Illegal opcode ${:02X}", code)?,
+            StopReason::InstructionLimit | StopReason::Step => {}
+            StopReason::Halted(Stop::Brk) => writeln!(out, "Halted at BRK")?,
+            StopReason::Halted(Stop::Jammed) => writeln!(out, "Halted: the CPU is jammed")?,
+            StopReason::Halted(Stop::Waiting) => writeln!(out, "Halted: waiting for an interrupt")?,
+            StopReason::Halted(Stop::IllegalOpcode) => {
+                writeln!(out, "Halted at an illegal opcode")?
+            }
+        }
+        self.print_location(out)?;
+        Ok(())
+    }
+}
+
+enum CommandError {
+    Io(io::Error),
+    Usage(String),
+}
+
+impl From<io::Error> for CommandError {
+    fn from(error: io::Error) -> Self {
+        CommandError::Io(error)
+    }
+}
+
+type CommandResult = Result<(), CommandError>;
+
+fn usage(message: &str) -> CommandError {
+    CommandError::Usage(format!("usage: {}", message))
+}
+
+fn parse_number(text: &str) -> Result<u32, CommandError> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u32::from_str_radix(digits, 16)
+        .map_err(|_| CommandError::Usage(format!("`{}` is not a hex number", text)))
+}
+
+/// Hex numbers take precedence over labels that happen to look like one
+fn parse_address(text: Option<&&str>, symbols: &SymbolTable) -> Result<u16, CommandError> {
+    let text = text.ok_or_else(|| CommandError::Usage(String::from("missing address")))?;
+    match parse_number(text) {
+        Ok(addr) => u16::try_from(addr)
+            .map_err(|_| CommandError::Usage(format!("`{}` is not an address", text))),
+        Err(error) => symbols.address_of(text).ok_or(error),
+    }
+}
+
+fn parse_range(text: &str, symbols: &SymbolTable) -> Result<RangeInclusive<u16>, CommandError> {
+    match text.split_once('-') {
+        Some((start, end)) => {
+            Ok(parse_address(Some(&start), symbols)?..=parse_address(Some(&end), symbols)?)
+        }
+        None => {
+            let addr = parse_address(Some(&text), symbols)?;
+            Ok(addr..=addr)
+        }
+    }
+}
+
+fn parse_register(text: &str) -> Result<CpuRegister, CommandError> {
+    match text.to_ascii_lowercase().as_str() {
+        "a" => Ok(CpuRegister::A),
+        "x" => Ok(CpuRegister::X),
+        "y" => Ok(CpuRegister::Y),
+        "s" | "sp" => Ok(CpuRegister::S),
+        "p" => Ok(CpuRegister::P),
+        "pc" => Ok(CpuRegister::PC),
+        _ => Err(CommandError::Usage(format!("unknown register `{}`", text))),
+    }
+}
+
+fn parse_condition(args: &[&str]) -> Result<Condition, CommandError> {
+    let register = parse_register(args[0])?;
+    let comparison = match args[1] {
+        "==" => Comparison::Equal,
+        "!=" => Comparison::NotEqual,
+        "<" => Comparison::Less,
+        "<=" => Comparison::LessOrEqual,
+        ">" => Comparison::Greater,
+        ">=" => Comparison::GreaterOrEqual,
+        op => return Err(CommandError::Usage(format!("unknown comparison `{}`", op))),
+    };
+    let value = u16::try_from(parse_number(args[2])?)
+        .map_err(|_| CommandError::Usage(String::from("value out of range")))?;
+    Ok(Condition::Register(register, comparison, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::variant::Variant;
+
+    fn session(program: Vec<u8>, commands: &str) -> String {
+        let mut cpu = CPU::new();
+        cpu.load(program);
+        cpu.reset();
+        let mut output = Vec::new();
+        Monitor::new(cpu)
+            .run(commands.as_bytes(), &mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_step_and_registers() {
+        let output = session(vec![0xA9, 0x42, 0xAA, 0x00], "step 2\nregs\nquit\n");
+        assert!(output.contains("8003  00        BRK"));
+        assert!(output.contains("A:42 X:42 Y:00"));
+    }
+
+    #[test]
+    fn test_breakpoint_and_continue() {
+        // LDX #$03; loop: DEX; BNE loop; BRK
+        let program = vec![0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x00];
+        let output = session(program, "break 8002 x == 1\ncontinue\nregs\ncontinue\n");
+        assert!(output.contains("Breakpoint at $8002\n8002"));
+        assert!(output.contains("X:01"));
+        assert!(output.contains("Halted at BRK"));
+    }
+
+    #[test]
+    fn test_reports_why_the_cpu_halted() {
+        // INX; KIL
+        let output = session(vec![0xE8, 0x02], "continue\n");
+        assert!(output.contains("Halted: the CPU is jammed"));
+        assert!(!output.contains("Halted at BRK"));
+    }
+
+    #[test]
+    fn test_next_steps_over_subroutine() {
+        // JSR $8006; LDA #$01; BRK; sub: LDX #$05; RTS
+        let program = vec![0x20, 0x06, 0x80, 0xA9, 0x01, 0x00, 0xA2, 0x05, 0x60];
+        let output = session(program, "next\nregs\n");
+        assert!(output.contains("8003  A9 01     LDA #$01"));
+        assert!(output.contains("X:05"));
+        assert!(output.contains("PC:8003"));
+    }
+
+    #[test]
+    fn test_memory_commands() {
+        let output = session(
+            vec![0x00],
+            "poke 10 de ad\nmem $10 2\nset a ff\nset p 01\nregs\nbogus\n",
+        );
+        assert!(output.contains("0010: DE AD"));
+        assert!(output.contains("A:FF"));
+        // B and bit 5 can't be cleared
+        assert!(output.contains("P:31 [..-B...C]"));
+        assert!(output.contains("error: unknown command `bogus`"));
+    }
+
+    #[test]
+    fn test_oversized_ranges_stop_at_end_of_memory() {
+        let output = session(vec![0x00], "mem 10 ffffffff\ndis 8000 5556\nregs\n");
+        assert!(output.contains("FFF0: "));
+        assert!(!output.contains("error: "));
+        assert!(output.contains("PC:8000"));
+    }
+
+    #[test]
+    fn test_assemble_patch() {
+        // LDA #$01; BRK, patched to LDA #$2A before running
+        let output = session(
+            vec![0xA9, 0x01, 0x00],
+            "label 0200 result\nasm 8000 lda #42\nasm 8002 sta result\nasm 8005 brk\n\
+             c\nmem 0200 1\nasm 8000 lda (result),z\n",
+        );
+        assert!(output.contains("8000  A9 2A     LDA #$2A"));
+        assert!(output.contains("8002  8D 00 02  STA result"));
+        assert!(output.contains("0200: 2A"));
+        assert!(output.contains("error: "));
+    }
+
+    #[test]
+    fn test_assemble_65c02() {
+        let mut cpu = CPU::new();
+        cpu.variant = Variant::Wdc65C02;
+        cpu.load(vec![0x00]);
+        cpu.reset();
+        let mut output = Vec::new();
+        Monitor::new(cpu)
+            .run(
+                "asm 8000 bra *\nasm 8002 lda ($10)\n".as_bytes(),
+                &mut output,
+            )
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("8000  80 FE     BRA $8000"));
+        assert!(output.contains("8002  B2 10     LDA ($10)"));
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        // LDA #$07; STA $0200; BRK
+        let program = vec![0xA9, 0x07, 0x8D, 0x00, 0x02, 0x00];
+        let output = session(program, "watch w 0200-02ff\nc\n");
+        assert!(output.contains("Write of $0200\n8005"));
+    }
+
+    #[test]
+    fn test_labels() {
+        // JSR sub; BRK; sub: RTS
+        let program = vec![0x20, 0x04, 0x80, 0x00, 0x60];
+        let output = session(
+            program,
+            "label 8004 sub clears state\nbreak sub\nc\ndis 8000 3\nunlabel sub\nbreak sub\n",
+        );
+        assert!(output.contains("sub = $8004"));
+        assert!(output.contains("Breakpoint at $8004 <sub>\n8004  60        RTS"));
+        assert!(output.contains("RTS             ; clears state"));
+        assert!(output.contains("  8000  20 04 80  JSR sub\n"));
+        assert!(output.contains("sub:\n> 8004"));
+        assert!(output.contains("error: `sub` is not a hex number"));
+    }
+}
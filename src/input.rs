@@ -0,0 +1,269 @@
+use crate::input::joypad::Joypad;
+use crate::input::zapper::Zapper;
+use crate::savestate::{Savestate, SavestateError, StateReader, StateWriter};
+
+pub mod joypad;
+pub mod zapper;
+
+/// Which controllers are plugged in for a session
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputMode {
+    /// One standard controller per port, read through D0
+    Standard,
+    /// NES Four Score: players 3 and 4 follow players 1 and 2 on D0
+    FourScore,
+    /// Famicom Hori 4 Players Adapter on the expansion port, read through D1
+    HoriFourPlayer,
+}
+
+impl InputMode {
+    /// Bits shifted out of a port after the controller reports, in read order.
+    /// The multitaps identify themselves with a single set bit so games can
+    /// tell them apart from plain controllers.
+    fn signature(self, port: usize) -> u8 {
+        match self {
+            InputMode::Standard => 0,
+            InputMode::FourScore => [0b0000_1000, 0b0000_0100][port],
+            InputMode::HoriFourPlayer => [0b0010_0000, 0b0001_0000][port],
+        }
+    }
+
+    fn report_len(self) -> u8 {
+        match self {
+            InputMode::Standard => 8,
+            InputMode::FourScore | InputMode::HoriFourPlayer => 24,
+        }
+    }
+
+    fn data_line(self) -> usize {
+        match self {
+            InputMode::Standard | InputMode::FourScore => 0,
+            InputMode::HoriFourPlayer => 1,
+        }
+    }
+}
+
+/// The two controller ports at 0x4016 and 0x4017
+///
+/// Writing bit 0 of 0x4016 strobes every controller. While the strobe is
+/// high each read returns the state of the A button; once it goes low the
+/// latched state is shifted out one bit per read.
+///
+/// A Zapper, when present, replaces whatever the mode puts on port 2.
+pub struct InputPorts {
+    pub mode: InputMode,
+    pub joypads: [Joypad; 4],
+    pub zapper: Option<Zapper>,
+    strobe: bool,
+    reports: [u32; 2],
+    reads: [u8; 2],
+}
+
+impl InputPorts {
+    pub const PORT_1: u16 = 0x4016;
+    pub const PORT_2: u16 = 0x4017;
+
+    pub fn new(mode: InputMode) -> Self {
+        InputPorts {
+            mode,
+            joypads: [Joypad::new(); 4],
+            zapper: None,
+            strobe: false,
+            reports: [0; 2],
+            reads: [0; 2],
+        }
+    }
+
+    /// Ports as they come up: strobe low and nothing latched yet
+    pub fn power_on(&mut self) {
+        self.strobe = false;
+        self.reports = [0; 2];
+        self.reads = [0; 2];
+    }
+
+    /// The reset line clears the strobe output on the CPU; the shift
+    /// registers in the controllers keep whatever they hold
+    pub fn reset(&mut self) {
+        self.strobe = false;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let port = match addr {
+            InputPorts::PORT_1 => 0,
+            InputPorts::PORT_2 => 1,
+            _ => panic!("address {:04x} is not a controller port", addr),
+        };
+
+        if let (1, Some(zapper)) = (port, &self.zapper) {
+            return zapper.read();
+        }
+
+        if self.strobe {
+            self.latch();
+        }
+
+        let bit = if self.reads[port] < self.mode.report_len() {
+            (self.reports[port] >> self.reads[port]) as u8 & 1
+        } else {
+            1
+        };
+        if !self.strobe {
+            self.reads[port] = self.reads[port].saturating_add(1);
+        }
+
+        bit << self.mode.data_line()
+    }
+
+    fn latch(&mut self) {
+        for port in 0..2 {
+            let mut report = self.joypads[port].0 as u32;
+            if self.mode != InputMode::Standard {
+                report |= (self.joypads[port + 2].0 as u32) << 8;
+                report |= (self.mode.signature(port) as u32) << 16;
+            }
+            self.reports[port] = report;
+            self.reads[port] = 0;
+        }
+    }
+}
+
+impl Savestate for InputPorts {
+    fn save(&self, state: &mut StateWriter) {
+        let InputPorts {
+            mode,
+            joypads,
+            zapper,
+            strobe,
+            reports,
+            reads,
+        } = self;
+
+        state.write_u8(*mode as u8);
+        for joypad in joypads {
+            joypad.save(state);
+        }
+        state.write_bool(zapper.is_some());
+        if let Some(zapper) = zapper {
+            zapper.save(state);
+        }
+        state.write_bool(*strobe);
+        for port in 0..2 {
+            state.write_u32(reports[port]);
+            state.write_u8(reads[port]);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError> {
+        let InputPorts {
+            mode,
+            joypads,
+            zapper,
+            strobe,
+            reports,
+            reads,
+        } = self;
+
+        *mode = match state.read_u8()? {
+            0 => InputMode::Standard,
+            1 => InputMode::FourScore,
+            2 => InputMode::HoriFourPlayer,
+            _ => return Err(SavestateError::Corrupted),
+        };
+        for joypad in joypads {
+            joypad.load(state)?;
+        }
+        *zapper = match state.read_bool()? {
+            true => {
+                let mut restored = Zapper::new();
+                restored.load(state)?;
+                Some(restored)
+            }
+            false => None,
+        };
+        *strobe = state.read_bool()?;
+        for port in 0..2 {
+            reports[port] = state.read_u32()?;
+            reads[port] = state.read_u8()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::joypad::JoypadButton;
+
+    fn read_bits(ports: &mut InputPorts, addr: u16, count: usize) -> Vec<u8> {
+        ports.write(1);
+        ports.write(0);
+        (0..count).map(|_| ports.read(addr)).collect()
+    }
+
+    #[test]
+    fn test_standard_shifts_buttons_then_ones() {
+        let mut ports = InputPorts::new(InputMode::Standard);
+        ports.joypads[0].set_button_pressed(JoypadButton::A, true);
+        ports.joypads[0].set_button_pressed(JoypadButton::Right, true);
+        let bits = read_bits(&mut ports, InputPorts::PORT_1, 10);
+        assert_eq!(bits, vec![1, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_keeps_returning_a() {
+        let mut ports = InputPorts::new(InputMode::Standard);
+        ports.joypads[1].set_button_pressed(JoypadButton::A, true);
+        ports.write(1);
+        assert_eq!(ports.read(InputPorts::PORT_2), 1);
+        assert_eq!(ports.read(InputPorts::PORT_2), 1);
+        ports.joypads[1].set_button_pressed(JoypadButton::A, false);
+        assert_eq!(ports.read(InputPorts::PORT_2), 0);
+    }
+
+    #[test]
+    fn test_four_score_reports_players_and_signature() {
+        let mut ports = InputPorts::new(InputMode::FourScore);
+        ports.joypads[0].set_button_pressed(JoypadButton::B, true);
+        ports.joypads[2].set_button_pressed(JoypadButton::Start, true);
+        ports.joypads[3].set_button_pressed(JoypadButton::Up, true);
+
+        let port_1 = read_bits(&mut ports, InputPorts::PORT_1, 24);
+        assert_eq!(port_1[..8], [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(port_1[8..16], [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(port_1[16..], [0, 0, 0, 1, 0, 0, 0, 0]);
+
+        let port_2 = read_bits(&mut ports, InputPorts::PORT_2, 24);
+        assert_eq!(port_2[..8], [0; 8]);
+        assert_eq!(port_2[8..16], [0, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(port_2[16..], [0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_hori_reports_through_d1() {
+        let mut ports = InputPorts::new(InputMode::HoriFourPlayer);
+        ports.joypads[0].set_button_pressed(JoypadButton::A, true);
+        let port_1 = read_bits(&mut ports, InputPorts::PORT_1, 24);
+        assert_eq!(port_1[0], 0b10);
+        assert_eq!(port_1[16..], [0, 0, 0, 0, 0, 0b10, 0, 0]);
+
+        let port_2 = read_bits(&mut ports, InputPorts::PORT_2, 24);
+        assert_eq!(port_2[16..], [0, 0, 0, 0, 0b10, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_zapper_replaces_port_2() {
+        let mut ports = InputPorts::new(InputMode::Standard);
+        ports.joypads[1].set_button_pressed(JoypadButton::A, true);
+        ports.zapper = Some(Zapper::new());
+        ports.zapper.as_mut().unwrap().pull_trigger();
+        let port_2 = read_bits(&mut ports, InputPorts::PORT_2, 2);
+        assert_eq!(port_2, vec![0b0001_1000, 0b0001_1000]);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::savestate::{Savestate, SavestateError, StateReader, StateWriter};
+use crate::util::u8_ext::BitwiseU8;
+use std::fmt;
+
+/// Buttons in the order a standard controller shifts them out after a strobe
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum JoypadButton {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl JoypadButton {
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Button state of a standard controller, one bit per `JoypadButton`
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct Joypad(pub u8);
+
+impl fmt::Debug for Joypad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08b}", self.0)
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad(0)
+    }
+
+    pub fn set_button_pressed(&mut self, button: JoypadButton, pressed: bool) {
+        match pressed {
+            true => self.0.set_bit_at(button.index()),
+            false => self.0.unset_bit_at(button.index()),
+        }
+    }
+
+    pub fn is_pressed(&self, button: JoypadButton) -> bool {
+        self.0.bit_is_set_at(button.index())
+    }
+}
+
+impl Savestate for Joypad {
+    fn save(&self, state: &mut StateWriter) {
+        state.write_u8(self.0);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError> {
+        self.0 = state.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::savestate::{Savestate, SavestateError, StateReader, StateWriter};
+
+/// Zapper light gun plugged into the second controller port
+///
+/// The host aims the gun at a framebuffer position and pulls the trigger.
+/// The photodiode only reports light while the beam is drawing bright
+/// pixels near that position, so the light-sense bit has to be refreshed
+/// with `update_light` at the beam position of each read.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Zapper {
+    pub x: u16,
+    pub y: u16,
+    light_sensed: bool,
+    trigger_held: bool,
+    trigger_frames: u8,
+}
+
+impl Zapper {
+    pub const FRAME_WIDTH: usize = 256;
+    pub const FRAME_HEIGHT: usize = 240;
+
+    /// Pixels around the aim point that reach the photodiode
+    const SENSOR_RADIUS: u16 = 2;
+    /// The photodiode keeps reporting light for roughly this many scanlines
+    /// after the beam has moved past the aim point
+    const LIGHT_PERSIST_SCANLINES: u16 = 20;
+    /// Rec. 601 luma above which a pixel counts as lit
+    const LIGHT_THRESHOLD: u32 = 0x80;
+    /// The trigger switch only closes while half-pulled; after about 100ms
+    /// of travel it opens again even if the trigger stays held
+    const TRIGGER_HALF_PULL_FRAMES: u8 = 6;
+
+    pub fn new() -> Self {
+        Zapper {
+            x: 0,
+            y: 0,
+            light_sensed: false,
+            trigger_held: false,
+            trigger_frames: 0,
+        }
+    }
+
+    pub fn aim(&mut self, x: u16, y: u16) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn pull_trigger(&mut self) {
+        if !self.trigger_held {
+            self.trigger_held = true;
+            self.trigger_frames = Zapper::TRIGGER_HALF_PULL_FRAMES;
+        }
+    }
+
+    pub fn release_trigger(&mut self) {
+        self.trigger_held = false;
+        self.trigger_frames = 0;
+    }
+
+    /// Advance the trigger mechanism, called once per frame
+    pub fn end_frame(&mut self) {
+        self.trigger_frames = self.trigger_frames.saturating_sub(1);
+    }
+
+    /// Recompute the light sensor for a beam at `scanline`/`dot` drawing
+    /// into `frame`, a 256x240 RGB framebuffer with 3 bytes per pixel
+    pub fn update_light(&mut self, frame: &[u8], scanline: u16, dot: u16) {
+        self.light_sensed = self.senses_light(frame, scanline, dot);
+    }
+
+    fn senses_light(&self, frame: &[u8], scanline: u16, dot: u16) -> bool {
+        if self.x as usize >= Zapper::FRAME_WIDTH || self.y as usize >= Zapper::FRAME_HEIGHT {
+            return false;
+        }
+
+        let top = self.y.saturating_sub(Zapper::SENSOR_RADIUS);
+        let bottom = (self.y + Zapper::SENSOR_RADIUS).min(Zapper::FRAME_HEIGHT as u16 - 1);
+        let left = self.x.saturating_sub(Zapper::SENSOR_RADIUS);
+        let right = (self.x + Zapper::SENSOR_RADIUS).min(Zapper::FRAME_WIDTH as u16 - 1);
+
+        (top..=bottom).any(|y| {
+            if y > scanline || scanline - y > Zapper::LIGHT_PERSIST_SCANLINES {
+                return false;
+            }
+            (left..=right).any(|x| {
+                // Dot 0 is idle, so pixel x is output on dot x + 1
+                let drawn = y < scanline || x + 1 < dot;
+                drawn && Zapper::luma(frame, x as usize, y as usize) >= Zapper::LIGHT_THRESHOLD
+            })
+        })
+    }
+
+    fn luma(frame: &[u8], x: usize, y: usize) -> u32 {
+        let offset = (y * Zapper::FRAME_WIDTH + x) * 3;
+        match frame.get(offset..offset + 3) {
+            Some(&[r, g, b]) => (299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000,
+            _ => 0,
+        }
+    }
+
+    /// Value seen on 0x4017: D3 is clear while light is sensed and D4 is
+    /// set while the trigger is half-pulled
+    pub fn read(&self) -> u8 {
+        let mut data = 0;
+        if !self.light_sensed {
+            data |= 0b0000_1000;
+        }
+        if self.trigger_held && self.trigger_frames > 0 {
+            data |= 0b0001_0000;
+        }
+        data
+    }
+}
+
+impl Savestate for Zapper {
+    fn save(&self, state: &mut StateWriter) {
+        let Zapper {
+            x,
+            y,
+            light_sensed,
+            trigger_held,
+            trigger_frames,
+        } = self;
+
+        state.write_u16(*x);
+        state.write_u16(*y);
+        state.write_bool(*light_sensed);
+        state.write_bool(*trigger_held);
+        state.write_u8(*trigger_frames);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError> {
+        let Zapper {
+            x,
+            y,
+            light_sensed,
+            trigger_held,
+            trigger_frames,
+        } = self;
+
+        *x = state.read_u16()?;
+        *y = state.read_u16()?;
+        *light_sensed = state.read_bool()?;
+        *trigger_held = state.read_bool()?;
+        *trigger_frames = state.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame_with_white_box(x: usize, y: usize, size: usize) -> Vec<u8> {
+        let mut frame = vec![0; Zapper::FRAME_WIDTH * Zapper::FRAME_HEIGHT * 3];
+        for row in y..y + size {
+            for col in x..x + size {
+                let offset = (row * Zapper::FRAME_WIDTH + col) * 3;
+                frame[offset..offset + 3].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+            }
+        }
+        frame
+    }
+
+    #[test]
+    fn test_light_sensed_only_after_beam_passes_target() {
+        let frame = frame_with_white_box(100, 100, 16);
+        let mut zapper = Zapper::new();
+        zapper.aim(108, 108);
+
+        zapper.update_light(&frame, 50, 0);
+        assert_eq!(zapper.read() & 0b0000_1000, 0b0000_1000);
+
+        zapper.update_light(&frame, 108, 200);
+        assert_eq!(zapper.read() & 0b0000_1000, 0);
+
+        zapper.update_light(&frame, 140, 0);
+        assert_eq!(zapper.read() & 0b0000_1000, 0b0000_1000);
+    }
+
+    #[test]
+    fn test_dark_target_is_not_sensed() {
+        let frame = frame_with_white_box(0, 0, 16);
+        let mut zapper = Zapper::new();
+        zapper.aim(200, 200);
+        zapper.update_light(&frame, 205, 0);
+        assert_eq!(zapper.read() & 0b0000_1000, 0b0000_1000);
+    }
+
+    #[test]
+    fn test_trigger_half_pull_lasts_several_frames() {
+        let mut zapper = Zapper::new();
+        zapper.pull_trigger();
+        for _ in 0..Zapper::TRIGGER_HALF_PULL_FRAMES {
+            assert_eq!(zapper.read() & 0b0001_0000, 0b0001_0000);
+            zapper.end_frame();
+        }
+        assert_eq!(zapper.read() & 0b0001_0000, 0);
+
+        zapper.pull_trigger();
+        assert_eq!(zapper.read() & 0b0001_0000, 0);
+        zapper.release_trigger();
+        zapper.pull_trigger();
+        assert_eq!(zapper.read() & 0b0001_0000, 0b0001_0000);
+    }
+}
@@ -1,7 +1,17 @@
 #[macro_use]
 extern crate lazy_static;
 
-mod cpu;
+pub mod cartridge;
+pub mod cdl;
+pub mod cpu;
+pub mod debugger;
+pub mod input;
+pub mod movie;
+pub mod profiler;
+pub mod rewind;
+pub mod savestate;
+pub mod symbols;
+pub mod test_rom;
 mod util;
 
 pub fn add(left: usize, right: usize) -> usize {
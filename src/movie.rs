@@ -0,0 +1,547 @@
+use crate::cpu::CPU;
+use crate::input::joypad::Joypad;
+use crate::input::{InputMode, InputPorts};
+use crate::savestate::{self, SavestateError};
+use std::fmt;
+
+/// Button letters of an FM2 input field, from bit 7 down to bit 0
+const FM2_BUTTONS: &[u8; 8] = b"RLDUTSBA";
+/// FM2 port type for a standard controller
+const FM2_GAMEPAD: u8 = 1;
+
+#[derive(Debug, PartialEq)]
+pub enum MovieError {
+    MissingField(&'static str),
+    InvalidField(String),
+    InvalidInput(usize),
+    UnsupportedPort(String),
+    UnsupportedMode(InputMode),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::MissingField(key) => write!(f, "movie is missing `{}`", key),
+            MovieError::InvalidField(key) => write!(f, "movie has an invalid `{}`", key),
+            MovieError::InvalidInput(line) => write!(f, "invalid input on line {}", line),
+            MovieError::UnsupportedPort(port) => write!(f, "port device {} is not supported", port),
+            MovieError::UnsupportedMode(mode) => write!(f, "{:?} can't be stored in FM2", mode),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+/// Reported during playback when the machine no longer matches the recording
+#[derive(Debug, PartialEq)]
+pub struct Desync {
+    pub frame: usize,
+    pub expected: u32,
+    pub found: u32,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "desync at frame {}: RAM checksum {:08x}, recorded {:08x}",
+            self.frame, self.found, self.expected
+        )
+    }
+}
+
+impl std::error::Error for Desync {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    /// A state from `CPU::save_machine`
+    Savestate(Vec<u8>),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MovieFrame {
+    /// FM2 command bits, such as `SOFT_RESET` and `HARD_RESET`
+    pub commands: u8,
+    pub joypads: [Joypad; 4],
+    /// Checksum of internal RAM at the end of the frame
+    pub ram_checksum: Option<u32>,
+}
+
+impl MovieFrame {
+    /// The reset button was pressed before the frame
+    pub const SOFT_RESET: u8 = 0b01;
+    /// The console was power cycled before the frame
+    pub const HARD_RESET: u8 = 0b10;
+}
+
+/// Per-frame controller input, recorded from power-on or from a save state
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub mode: InputMode,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+    pub rerecord_count: u32,
+    pub rom_filename: String,
+    /// Kept as written by the emulator that made the movie
+    pub rom_checksum: String,
+    pub guid: String,
+}
+
+impl Movie {
+    pub fn new(mode: InputMode, start: MovieStart) -> Self {
+        Movie {
+            mode,
+            start,
+            frames: Vec::new(),
+            rerecord_count: 0,
+            rom_filename: String::new(),
+            rom_checksum: String::new(),
+            guid: String::from("00000000-0000-0000-0000-000000000000"),
+        }
+    }
+
+    /// Appends the input used for the frame that just finished, along with
+    /// a checksum of RAM so playback can detect desyncs. `commands` holds
+    /// the `MovieFrame::SOFT_RESET` or `HARD_RESET` done before the frame.
+    pub fn record_frame(&mut self, commands: u8, input: &InputPorts, cpu: &CPU) {
+        self.frames.push(MovieFrame {
+            commands,
+            joypads: input.joypads,
+            ram_checksum: Some(ram_checksum(cpu)),
+        });
+    }
+
+    pub fn from_fm2(text: &str) -> Result<Self, MovieError> {
+        let mut movie = Movie::new(InputMode::Standard, MovieStart::PowerOn);
+        let mut ports = [FM2_GAMEPAD, FM2_GAMEPAD];
+        let mut checksums = Vec::new();
+        let mut has_version = false;
+
+        for (index, line) in text.lines().enumerate() {
+            if line.starts_with('|') {
+                movie.frames.push(parse_fm2_frame(line, movie.mode, ports, index + 1)?);
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "version" => has_version = true,
+                "rerecordCount" => movie.rerecord_count = parse_number(key, value)?,
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => movie.rom_checksum = value.to_string(),
+                "guid" => movie.guid = value.to_string(),
+                "fourscore" if parse_number::<u8>(key, value)? != 0 => {
+                    movie.mode = InputMode::FourScore;
+                }
+                "port0" => ports[0] = parse_number(key, value)?,
+                "port1" => ports[1] = parse_number(key, value)?,
+                "savestate" => movie.start = MovieStart::Savestate(parse_savestate(value)?),
+                "ramChecksums" => checksums = parse_checksums(value)?,
+                _ => {}
+            }
+        }
+
+        if !has_version {
+            return Err(MovieError::MissingField("version"));
+        }
+        for (frame, checksum) in movie.frames.iter_mut().zip(checksums) {
+            frame.ram_checksum = checksum;
+        }
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> Result<String, MovieError> {
+        let four_score = match self.mode {
+            InputMode::Standard => false,
+            InputMode::FourScore => true,
+            InputMode::HoriFourPlayer => return Err(MovieError::UnsupportedMode(self.mode)),
+        };
+        let ports = if four_score { 0 } else { FM2_GAMEPAD };
+
+        let mut out = String::new();
+        out.push_str("version 3\n");
+        out.push_str("emuVersion 22020\n");
+        out.push_str(&format!("rerecordCount {}\n", self.rerecord_count));
+        out.push_str("palFlag 0\n");
+        out.push_str(&format!("romFilename {}\n", self.rom_filename));
+        out.push_str(&format!("romChecksum {}\n", self.rom_checksum));
+        out.push_str(&format!("guid {}\n", self.guid));
+        out.push_str(&format!("fourscore {}\n", four_score as u8));
+        out.push_str("microphone 0\n");
+        out.push_str(&format!("port0 {}\nport1 {}\nport2 0\n", ports, ports));
+        out.push_str("FDS 0\nNewPPU 0\n");
+        if let MovieStart::Savestate(state) = &self.start {
+            let hex: String = state.iter().map(|byte| format!("{:02x}", byte)).collect();
+            out.push_str(&format!("savestate 0x{}\n", hex));
+        }
+        // Not part of FM2; other emulators ignore unknown header keys
+        if self.frames.iter().any(|frame| frame.ram_checksum.is_some()) {
+            let checksums: Vec<String> = self
+                .frames
+                .iter()
+                .map(|frame| match frame.ram_checksum {
+                    Some(checksum) => format!("{:08x}", checksum),
+                    None => String::from("-"),
+                })
+                .collect();
+            out.push_str(&format!("ramChecksums {}\n", checksums.join(",")));
+        }
+
+        let players = if four_score { 4 } else { 2 };
+        for frame in &self.frames {
+            out.push_str(&format!("|{}|", frame.commands));
+            for joypad in &frame.joypads[..players] {
+                out.push_str(&format_fm2_joypad(*joypad));
+                out.push('|');
+            }
+            out.push_str("|\n");
+        }
+        Ok(out)
+    }
+}
+
+/// Replays a movie one frame at a time
+pub struct MoviePlayer<'a> {
+    movie: &'a Movie,
+    frame: usize,
+}
+
+impl<'a> MoviePlayer<'a> {
+    pub fn new(movie: &'a Movie) -> Self {
+        MoviePlayer { movie, frame: 0 }
+    }
+
+    /// Puts the machine in the state the recording started from
+    pub fn begin(&mut self, cpu: &mut CPU, input: &mut InputPorts) -> Result<(), SavestateError> {
+        self.frame = 0;
+        match &self.movie.start {
+            MovieStart::PowerOn => cpu.power_on_machine(input),
+            MovieStart::Savestate(state) => cpu.load_machine(input, state)?,
+        }
+        input.mode = self.movie.mode;
+        Ok(())
+    }
+
+    /// Runs the resets the next frame starts with and sets its
+    /// controllers, returning false at the end
+    pub fn apply_input(&self, cpu: &mut CPU, input: &mut InputPorts) -> bool {
+        match self.movie.frames.get(self.frame) {
+            Some(frame) => {
+                if frame.commands & MovieFrame::HARD_RESET != 0 {
+                    cpu.power_on_machine(input);
+                } else if frame.commands & MovieFrame::SOFT_RESET != 0 {
+                    cpu.reset_machine(input);
+                }
+                input.joypads = frame.joypads;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks the frame that just ran against the recording and moves on
+    pub fn end_frame(&mut self, cpu: &CPU) -> Result<(), Desync> {
+        let frame = self.frame;
+        self.frame += 1;
+
+        match self.movie.frames.get(frame).and_then(|f| f.ram_checksum) {
+            Some(expected) => {
+                let found = ram_checksum(cpu);
+                if found == expected {
+                    Ok(())
+                } else {
+                    Err(Desync {
+                        frame,
+                        expected,
+                        found,
+                    })
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+/// Checksum of the 2 KiB of internal RAM at 0x0000..0x0800
+pub fn ram_checksum(cpu: &CPU) -> u32 {
+    let ram: Vec<u8> = (0..0x0800).map(|addr| cpu.memory.read(addr)).collect();
+    savestate::hash(&ram) as u32
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, MovieError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| MovieError::InvalidField(key.to_string()))
+}
+
+/// FM2 stores binary fields either as `0x` followed by hex digits or, as
+/// FCEUX writes them, `base64:` followed by base64
+fn parse_savestate(value: &str) -> Result<Vec<u8>, MovieError> {
+    let invalid = || MovieError::InvalidField(String::from("savestate"));
+    let value = value.trim();
+    if let Some(encoded) = value.strip_prefix("base64:") {
+        return decode_base64(encoded).ok_or_else(invalid);
+    }
+    let digits = value.strip_prefix("0x").ok_or_else(invalid)?;
+    if digits.len() % 2 != 0 {
+        return Err(invalid());
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+fn parse_checksums(value: &str) -> Result<Vec<Option<u32>>, MovieError> {
+    value
+        .split(',')
+        .map(|checksum| match checksum {
+            "-" => Ok(None),
+            _ => u32::from_str_radix(checksum, 16)
+                .map(Some)
+                .map_err(|_| MovieError::InvalidField(String::from("ramChecksums"))),
+        })
+        .collect()
+}
+
+fn parse_fm2_frame(
+    line: &str,
+    mode: InputMode,
+    ports: [u8; 2],
+    line_number: usize,
+) -> Result<MovieFrame, MovieError> {
+    let fields: Vec<&str> = line.split('|').collect();
+    let invalid = || MovieError::InvalidInput(line_number);
+
+    let mut frame = MovieFrame {
+        commands: fields.get(1).ok_or_else(invalid)?.parse().map_err(|_| invalid())?,
+        ..MovieFrame::default()
+    };
+
+    let players = match mode {
+        InputMode::FourScore => 4,
+        _ => 2,
+    };
+    let fields = fields.get(2..2 + players).ok_or_else(invalid)?;
+    for (player, field) in fields.iter().enumerate() {
+        let port = ports.get(player).copied().unwrap_or(FM2_GAMEPAD);
+        if mode == InputMode::Standard && port != FM2_GAMEPAD {
+            if port != 0 {
+                return Err(MovieError::UnsupportedPort(format!("port{}", player)));
+            }
+            continue;
+        }
+        frame.joypads[player] = parse_fm2_joypad(field).ok_or_else(invalid)?;
+    }
+    Ok(frame)
+}
+
+fn parse_fm2_joypad(field: &str) -> Option<Joypad> {
+    if field.len() != FM2_BUTTONS.len() {
+        return None;
+    }
+    let mut joypad = Joypad::new();
+    for (index, button) in field.bytes().enumerate() {
+        if button != b'.' && button != b' ' {
+            joypad.0 |= 1 << (7 - index);
+        }
+    }
+    Some(joypad)
+}
+
+fn format_fm2_joypad(joypad: Joypad) -> String {
+    FM2_BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(index, button)| match joypad.0 & (1 << (7 - index)) {
+            0 => '.',
+            _ => *button as char,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::joypad::JoypadButton;
+
+    const FM2: &str = "version 3
+emuVersion 22020
+rerecordCount 12
+palFlag 0
+romFilename smb
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+microphone 0
+port0 1
+port1 1
+port2 0
+|0|........|........||
+|0|....T...|........||
+|0|R......A|.L......||
+";
+
+    #[test]
+    fn test_fm2_import() {
+        let movie = Movie::from_fm2(FM2).unwrap();
+        assert_eq!(movie.rerecord_count, 12);
+        assert_eq!(movie.rom_filename, "smb");
+        assert_eq!(movie.frames.len(), 3);
+        assert!(movie.frames[1].joypads[0].is_pressed(JoypadButton::Start));
+        assert!(movie.frames[2].joypads[0].is_pressed(JoypadButton::Right));
+        assert!(movie.frames[2].joypads[0].is_pressed(JoypadButton::A));
+        assert!(movie.frames[2].joypads[1].is_pressed(JoypadButton::Left));
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let mut movie = Movie::from_fm2(FM2).unwrap();
+        movie.frames[0].ram_checksum = Some(0xDEADBEEF);
+        movie.start = MovieStart::Savestate(vec![0x4E, 0x45, 0x00]);
+
+        let exported = movie.to_fm2().unwrap();
+        assert!(exported.contains("|0|R......A|.L......||\n"));
+        assert_eq!(Movie::from_fm2(&exported).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_fm2_base64_savestate() {
+        let fm2 = FM2.replace("port2 0\n", "port2 0\nsavestate base64:TkVTUwI=\n");
+        let movie = Movie::from_fm2(&fm2).unwrap();
+        assert_eq!(
+            movie.start,
+            MovieStart::Savestate(vec![0x4E, 0x45, 0x53, 0x53, 0x02])
+        );
+
+        let fm2 = FM2.replace("port2 0\n", "port2 0\nsavestate base64:TkV*\n");
+        assert!(Movie::from_fm2(&fm2).is_err());
+    }
+
+    #[test]
+    fn test_four_score_round_trip() {
+        let mut movie = Movie::new(InputMode::FourScore, MovieStart::PowerOn);
+        let mut frame = MovieFrame::default();
+        frame.joypads[3].set_button_pressed(JoypadButton::B, true);
+        movie.frames.push(frame);
+
+        let exported = movie.to_fm2().unwrap();
+        assert!(exported.contains("|0|........|........|........|......B.||\n"));
+        assert_eq!(Movie::from_fm2(&exported).unwrap(), movie);
+    }
+
+    #[test]
+    fn test_playback_applies_resets() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xA9, 0x42, 0x85, 0x10, 0x00]); // LDA #0x42, STA $10
+        cpu.power_on();
+        let mut input = InputPorts::new(InputMode::Standard);
+
+        let mut movie = Movie::new(InputMode::Standard, MovieStart::PowerOn);
+        for commands in [0, MovieFrame::SOFT_RESET, MovieFrame::HARD_RESET] {
+            movie.frames.push(MovieFrame {
+                commands,
+                ..MovieFrame::default()
+            });
+        }
+
+        let mut player = MoviePlayer::new(&movie);
+        player.begin(&mut cpu, &mut input).unwrap();
+        assert!(player.apply_input(&mut cpu, &mut input));
+        cpu.run();
+        player.end_frame(&cpu).unwrap();
+
+        // The soft reset keeps RAM and A, the hard reset clears both
+        assert!(player.apply_input(&mut cpu, &mut input));
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.register_s.0, 0xF7);
+        assert_eq!((cpu.register_a.0, cpu.memory.read(0x10)), (0x42, 0x42));
+        player.end_frame(&cpu).unwrap();
+
+        assert!(player.apply_input(&mut cpu, &mut input));
+        assert_eq!(cpu.register_s.0, 0xFD);
+        assert_eq!((cpu.register_a.0, cpu.memory.read(0x10)), (0, 0));
+    }
+
+    #[test]
+    fn test_recording_keeps_resets() {
+        let cpu = CPU::new();
+        let input = InputPorts::new(InputMode::Standard);
+        let mut movie = Movie::new(InputMode::Standard, MovieStart::PowerOn);
+        movie.record_frame(0, &input, &cpu);
+        movie.record_frame(MovieFrame::SOFT_RESET, &input, &cpu);
+
+        let exported = movie.to_fm2().unwrap();
+        let commands: Vec<u8> = Movie::from_fm2(&exported)
+            .unwrap()
+            .frames
+            .iter()
+            .map(|frame| frame.commands)
+            .collect();
+        assert_eq!(commands, vec![0, MovieFrame::SOFT_RESET]);
+    }
+
+    #[test]
+    fn test_playback_reports_desync() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xA5, 0x10, 0x69, 0x01, 0x85, 0x10, 0x00]); // LDA $10, ADC #0x01, STA $10
+        cpu.reset();
+        let mut input = InputPorts::new(InputMode::Standard);
+
+        let mut movie = Movie::new(InputMode::Standard, MovieStart::PowerOn);
+        for _ in 0..3 {
+            cpu.program_counter = 0x8000;
+            cpu.run();
+            movie.record_frame(0, &input, &cpu);
+        }
+
+        let mut player = MoviePlayer::new(&movie);
+        cpu.memory.write(0x10, 0);
+        player.begin(&mut cpu, &mut input).unwrap();
+        assert!(player.apply_input(&mut cpu, &mut input));
+        cpu.program_counter = 0x8000;
+        cpu.run();
+        assert_eq!(player.end_frame(&cpu), Ok(()));
+
+        cpu.memory.write(0x10, 0x7F);
+        assert!(player.apply_input(&mut cpu, &mut input));
+        cpu.program_counter = 0x8000;
+        cpu.run();
+        let desync = player.end_frame(&cpu).unwrap_err();
+        assert_eq!(desync.frame, 1);
+    }
+}
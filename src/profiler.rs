@@ -0,0 +1,429 @@
+use crate::cpu::CPU;
+use crate::symbols::SymbolTable;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+const BRK: u8 = 0x00;
+/// Length of the NMI and IRQ sequence
+const INTERRUPT_CYCLES: u64 = 7;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RoutineStats {
+    pub calls: u64,
+    /// Cycles spent in the routine and everything it called
+    pub inclusive: u64,
+    /// Cycles spent in the routine's own instructions
+    pub exclusive: u64,
+}
+
+/// Cycles attributed to routines and instruction addresses over some span,
+/// either one frame or the whole run
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub cycles: u64,
+    pub routines: BTreeMap<u16, RoutineStats>,
+    pub addresses: HashMap<u16, u64>,
+}
+
+impl Profile {
+    /// The `count` instruction addresses that took the most cycles
+    pub fn hottest(&self, count: usize) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self.addresses.iter().map(|(a, c)| (*a, *c)).collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses.truncate(count);
+        addresses
+    }
+
+    /// Routines by inclusive cycles, then the hottest addresses
+    pub fn report(&self, symbols: &SymbolTable, hottest: usize) -> String {
+        let percent = |cycles: u64| 100.0 * cycles as f64 / self.cycles.max(1) as f64;
+        let mut routines: Vec<(&u16, &RoutineStats)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0)));
+
+        let mut report = String::new();
+        writeln!(report, "{} cycles", self.cycles).unwrap();
+        writeln!(
+            report,
+            "{:<24} {:>8} {:>12} {:>6} {:>12} {:>6}",
+            "routine", "calls", "inclusive", "%", "exclusive", "%"
+        )
+        .unwrap();
+        for (addr, stats) in routines {
+            writeln!(
+                report,
+                "{:<24} {:>8} {:>12} {:>6.2} {:>12} {:>6.2}",
+                symbols.format_address(*addr, 4),
+                stats.calls,
+                stats.inclusive,
+                percent(stats.inclusive),
+                stats.exclusive,
+                percent(stats.exclusive)
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nhottest addresses").unwrap();
+        for (addr, cycles) in self.hottest(hottest) {
+            let location = match symbols.name(addr) {
+                Some(name) => format!("${:04X} {}", addr, name),
+                None => format!("${:04X}", addr),
+            };
+            writeln!(
+                report,
+                "{:<24} {:>12} {:>6.2}",
+                location,
+                cycles,
+                percent(cycles)
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    fn add_call(&mut self, routine: u16) {
+        self.routines.entry(routine).or_default().calls += 1;
+    }
+
+    fn add_cycles(&mut self, pc: u16, stack: &[Frame], cycles: u64) {
+        self.cycles += cycles;
+        *self.addresses.entry(pc).or_default() += cycles;
+
+        for (depth, frame) in stack.iter().enumerate() {
+            // Recursive routines only count once towards inclusive time
+            if stack[..depth]
+                .iter()
+                .any(|outer| outer.routine == frame.routine)
+            {
+                continue;
+            }
+            self.routines.entry(frame.routine).or_default().inclusive += cycles;
+        }
+        if let Some(top) = stack.last() {
+            self.routines.entry(top.routine).or_default().exclusive += cycles;
+        }
+    }
+}
+
+/// A routine on the call stack
+#[derive(Clone, Debug)]
+struct Frame {
+    routine: u16,
+    /// Index of this call path in `Profiler::nodes`
+    node: usize,
+    /// The stack pointer once the routine has returned; `None` for the
+    /// outermost frame, which never does
+    return_sp: Option<u8>,
+}
+
+/// One distinct call path, for folded stacks
+#[derive(Clone, Debug)]
+struct Node {
+    parent: Option<usize>,
+    routine: u16,
+    cycles: u64,
+}
+
+/// The instruction seen last, whose cycles are attributed once the next
+/// one starts
+#[derive(Clone, Debug)]
+struct Pending {
+    pc: u16,
+    cycles: u64,
+    /// Opcode whose effect on the call stack hasn't been applied yet
+    code: Option<u8>,
+}
+
+/// Attributes CPU cycles to subroutines by following JSR/RTS and interrupt
+/// entry/RTI
+///
+/// Call `before_instruction` before every instruction, e.g. from
+/// `CPU::run_with_callback`; an NMI or IRQ taken in between is picked up
+/// from `CPU::interrupt_taken`, and its cycles count towards the handler.
+/// Returns are matched by stack pointer, so code that drops return
+/// addresses with PLA or returns with a pushed address and RTS still
+/// unwinds correctly.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    stack: Vec<Frame>,
+    nodes: Vec<Node>,
+    children: HashMap<(Option<usize>, u16), usize>,
+    pending: Option<Pending>,
+    frame: Profile,
+    total: Profile,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    pub fn before_instruction(&mut self, cpu: &CPU) {
+        if cpu.interrupt_taken().is_some() {
+            self.enter_interrupt(cpu);
+        }
+        self.sync(cpu.cycles);
+        self.apply_pending(cpu.program_counter, cpu.register_s.0);
+
+        if self.stack.is_empty() {
+            self.push(cpu.program_counter, None);
+        }
+        self.pending = Some(Pending {
+            pc: cpu.program_counter,
+            cycles: cpu.cycles,
+            code: Some(cpu.memory.read(cpu.program_counter)),
+        });
+    }
+
+    /// Closes the current frame's profile and starts a new one
+    pub fn end_frame(&mut self, cpu: &CPU) -> Profile {
+        self.sync(cpu.cycles);
+        std::mem::take(&mut self.frame)
+    }
+
+    /// Everything profiled so far, including the current frame
+    pub fn total(&self) -> &Profile {
+        &self.total
+    }
+
+    /// Call stacks in the folded format flamegraph.pl and inferno read:
+    /// `outer;inner;innermost cycles` per line
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let mut lines = Vec::new();
+        for node in self.nodes.iter().filter(|node| node.cycles > 0) {
+            let mut path = vec![symbols.format_address(node.routine, 4)];
+            let mut parent = node.parent;
+            while let Some(index) = parent {
+                path.push(symbols.format_address(self.nodes[index].routine, 4));
+                parent = self.nodes[index].parent;
+            }
+            path.reverse();
+            lines.push(format!("{} {}", path.join(";"), node.cycles));
+        }
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    /// Finishes the instruction the interrupt came after, from the state
+    /// the CPU pushed, and enters the handler it's now at
+    fn enter_interrupt(&mut self, cpu: &CPU) {
+        let sp = cpu.register_s.0;
+        let return_sp = sp.wrapping_add(3);
+        let return_address = u16::from_le_bytes([
+            cpu.memory.read(0x0100 + sp.wrapping_add(2) as u16),
+            cpu.memory.read(0x0100 + return_sp as u16),
+        ]);
+        let started = cpu.cycles.saturating_sub(INTERRUPT_CYCLES);
+
+        self.sync(started);
+        self.apply_pending(return_address, return_sp);
+        if self.stack.is_empty() {
+            self.push(return_address, None);
+        }
+        self.push(cpu.program_counter, Some(return_sp));
+        self.pending = Some(Pending {
+            pc: cpu.program_counter,
+            cycles: started,
+            code: None,
+        });
+    }
+
+    /// Attributes cycles since the last instruction started to it, up to
+    /// the cycle count `now`
+    fn sync(&mut self, now: u64) {
+        let pending = match self.pending.as_mut() {
+            Some(pending) => pending,
+            None => return,
+        };
+        // The counter goes back when the machine is powered on again or a
+        // state is loaded; the cycles since then can't be told apart, so
+        // counting just starts over from the new value
+        let cycles = match now.checked_sub(pending.cycles) {
+            Some(cycles) => cycles,
+            None => {
+                pending.cycles = now;
+                return;
+            }
+        };
+        pending.cycles = now;
+        if cycles == 0 {
+            return;
+        }
+
+        self.frame.add_cycles(pending.pc, &self.stack, cycles);
+        self.total.add_cycles(pending.pc, &self.stack, cycles);
+        if let Some(top) = self.stack.last() {
+            self.nodes[top.node].cycles += cycles;
+        }
+    }
+
+    /// Applies the pending instruction's JSR, BRK or return, given the
+    /// program counter and stack pointer it left
+    fn apply_pending(&mut self, pc: u16, sp: u8) {
+        let code = match self
+            .pending
+            .as_mut()
+            .and_then(|pending| pending.code.take())
+        {
+            Some(code) => code,
+            None => return,
+        };
+
+        match code {
+            JSR => self.push(pc, Some(sp.wrapping_add(2))),
+            BRK => self.push(pc, Some(sp.wrapping_add(3))),
+            RTS | RTI => {
+                while self.stack.len() > 1 {
+                    match self.stack.last().and_then(|frame| frame.return_sp) {
+                        Some(return_sp) if return_sp <= sp => self.stack.pop(),
+                        _ => break,
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push(&mut self, routine: u16, return_sp: Option<u8>) {
+        let parent = self.stack.last().map(|frame| frame.node);
+        let nodes = &mut self.nodes;
+        let node = *self.children.entry((parent, routine)).or_insert_with(|| {
+            nodes.push(Node {
+                parent,
+                routine,
+                cycles: 0,
+            });
+            nodes.len() - 1
+        });
+
+        self.frame.add_call(routine);
+        self.total.add_call(routine);
+        self.stack.push(Frame {
+            routine,
+            node,
+            return_sp,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // main: JSR outer; JSR leaf; BRK
+    // outer: JSR leaf; RTS
+    // leaf: LDX #$03; loop: DEX; BNE loop; RTS
+    const PROGRAM: [u8; 17] = [
+        0x20, 0x07, 0x80, // 8000 JSR $8007
+        0x20, 0x0B, 0x80, // 8003 JSR $800B
+        0x00, // 8006 BRK
+        0x20, 0x0B, 0x80, // 8007 JSR $800B
+        0x60, // 800A RTS
+        0xA2, 0x03, // 800B LDX #$03
+        0xCA, // 800D DEX
+        0xD0, 0xFD, // 800E BNE $800D
+        0x60, // 8010 RTS
+    ];
+
+    fn profile() -> (Profiler, CPU) {
+        let mut cpu = CPU::new();
+        cpu.load(PROGRAM.to_vec());
+        cpu.reset();
+        let mut profiler = Profiler::new();
+        cpu.run_with_callback(|cpu| profiler.before_instruction(cpu));
+        profiler.sync(cpu.cycles);
+        (profiler, cpu)
+    }
+
+    #[test]
+    fn test_inclusive_and_exclusive_cycles() {
+        let (profiler, cpu) = profile();
+        let total = profiler.total();
+        assert_eq!(total.cycles, cpu.cycles - 7);
+
+        // LDX 2 + 3 * DEX 2 + BNE 3 + 3 + 2 + RTS 6 per call
+        let leaf = total.routines[&0x800B];
+        assert_eq!(leaf.calls, 2);
+        assert_eq!(leaf.exclusive, 2 * 22);
+        assert_eq!(leaf.inclusive, leaf.exclusive);
+
+        let outer = total.routines[&0x8007];
+        assert_eq!(outer.calls, 1);
+        assert_eq!(outer.exclusive, 6 + 6);
+        assert_eq!(outer.inclusive, 6 + 6 + 22);
+
+        let main = total.routines[&0x8000];
+        // BRK ends the run once it has entered the IRQ handler
+        assert_eq!(main.exclusive, 6 + 6 + 7);
+        assert_eq!(main.inclusive, total.cycles);
+        assert_eq!(total.hottest(1), vec![(0x800E, 16)]);
+    }
+
+    #[test]
+    fn test_folded_stacks_and_report() {
+        let (profiler, _) = profile();
+        let mut symbols = SymbolTable::new();
+        symbols.define(0x800B, "leaf", None);
+
+        assert_eq!(
+            profiler.folded(&symbols),
+            "$8000 19\n$8000;$8007 12\n$8000;$8007;leaf 22\n$8000;leaf 22\n"
+        );
+        let report = profiler.total().report(&symbols, 3);
+        assert!(report.starts_with("75 cycles\n"));
+        assert!(report.contains(
+            "\nleaf                            2           44  58.67           44  58.67\n"
+        ));
+        assert!(report.contains("hottest addresses\n$800E "));
+    }
+
+    #[test]
+    fn test_frames_and_interrupts() {
+        let mut cpu = CPU::new();
+        cpu.load(PROGRAM.to_vec());
+        cpu.memory.load_at(0x8020, &[0xE8, 0x40]); // nmi: INX; RTI
+        cpu.reset();
+        cpu.memory.write_u16(CPU::NMI_VECTOR, 0x8020);
+        let mut profiler = Profiler::new();
+
+        // An NMI arrives during the first JSR and is taken before outer's
+        // first instruction
+        cpu.trigger_nmi();
+        profiler.before_instruction(&cpu);
+        cpu.step(); // JSR $8007
+        let first = profiler.end_frame(&cpu);
+        assert_eq!(first.cycles, 6);
+
+        cpu.run_with_callback(|cpu| profiler.before_instruction(cpu));
+        let second = profiler.end_frame(&cpu);
+        let nmi = second.routines[&0x8020];
+        assert_eq!((nmi.calls, nmi.inclusive, nmi.exclusive), (1, 15, 15));
+        assert_eq!(second.routines[&0x8007].calls, 1);
+        assert_eq!(second.routines[&0x8007].inclusive, 15 + 34);
+        assert_eq!(second.routines[&0x800B].calls, 2);
+        assert_eq!(first.cycles + second.cycles, cpu.cycles - 7);
+        assert_eq!(profiler.total().cycles, cpu.cycles - 7);
+    }
+
+    #[test]
+    fn test_cycle_counter_going_back() {
+        let mut cpu = CPU::new();
+        cpu.load(PROGRAM.to_vec());
+        cpu.reset();
+        let mut profiler = Profiler::new();
+        for _ in 0..3 {
+            profiler.before_instruction(&cpu);
+            cpu.step();
+        }
+
+        cpu.power_on();
+        profiler.before_instruction(&cpu);
+        cpu.step(); // JSR $8007
+        profiler.before_instruction(&cpu);
+        // The LDX before the power cycle was still pending, so its cycles
+        // are dropped rather than underflowing
+        assert_eq!(profiler.total().cycles, 6 + 6 + 6);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::cpu::CPU;
+use crate::input::InputPorts;
+use crate::savestate::SavestateError;
+use std::collections::VecDeque;
+
+/// NTSC frames per second, used to turn held snapshots into seconds
+const FRAMES_PER_SECOND: f64 = 60.0988;
+
+/// Ring buffer of per-frame save states for running the game backwards
+///
+/// Every `keyframe_interval` snapshots a full keyframe is stored, and the
+/// snapshots in between are stored as the XOR against that keyframe. Both
+/// are run-length compressed, which works well since consecutive frames
+/// differ in only a handful of bytes. The oldest keyframe and its deltas
+/// are dropped once the compressed size exceeds the memory budget.
+pub struct RewindBuffer {
+    budget: usize,
+    keyframe_interval: usize,
+    groups: VecDeque<Group>,
+    size: usize,
+}
+
+struct Group {
+    keyframe: Vec<u8>,
+    keyframe_len: usize,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Group {
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+impl RewindBuffer {
+    pub const DEFAULT_KEYFRAME_INTERVAL: usize = 60;
+
+    /// Creates a buffer holding at most `budget` bytes of compressed history
+    pub fn new(budget: usize) -> Self {
+        RewindBuffer::with_keyframe_interval(budget, RewindBuffer::DEFAULT_KEYFRAME_INTERVAL)
+    }
+
+    pub fn with_keyframe_interval(budget: usize, keyframe_interval: usize) -> Self {
+        RewindBuffer {
+            budget,
+            keyframe_interval: keyframe_interval.max(1),
+            groups: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    /// Records a snapshot, meant to be called at the start of every frame
+    pub fn push(&mut self, snapshot: &[u8]) {
+        let start_group = match self.groups.back() {
+            Some(group) => {
+                group.deltas.len() + 1 >= self.keyframe_interval
+                    || group.keyframe_len != snapshot.len()
+            }
+            None => true,
+        };
+
+        if start_group {
+            let keyframe = compress(snapshot);
+            self.size += keyframe.len();
+            self.groups.push_back(Group {
+                keyframe,
+                keyframe_len: snapshot.len(),
+                deltas: Vec::new(),
+            });
+        } else {
+            let group = self.groups.back_mut().unwrap();
+            let keyframe = decompress(&group.keyframe);
+            let delta: Vec<u8> = keyframe.iter().zip(snapshot).map(|(a, b)| a ^ b).collect();
+            let delta = compress(&delta);
+            self.size += delta.len();
+            group.deltas.push(delta);
+        }
+
+        // Always keep the newest group, even if it alone is over budget
+        while self.size > self.budget && self.groups.len() > 1 {
+            let evicted = self.groups.pop_front().unwrap();
+            self.size -= evicted.size();
+        }
+    }
+
+    /// Removes and returns the most recent snapshot, one frame back in time
+    pub fn step_back(&mut self) -> Option<Vec<u8>> {
+        let group = self.groups.back_mut()?;
+        let keyframe = decompress(&group.keyframe);
+
+        match group.deltas.pop() {
+            Some(delta) => {
+                self.size -= delta.len();
+                let delta = decompress(&delta);
+                Some(keyframe.iter().zip(&delta).map(|(a, b)| a ^ b).collect())
+            }
+            None => {
+                let group = self.groups.pop_back().unwrap();
+                self.size -= group.keyframe.len();
+                Some(keyframe)
+            }
+        }
+    }
+
+    /// Steps the machine back one frame, returning false once history
+    /// runs out
+    pub fn rewind(
+        &mut self,
+        cpu: &mut CPU,
+        input: &mut InputPorts,
+    ) -> Result<bool, SavestateError> {
+        match self.step_back() {
+            Some(snapshot) => cpu.load_machine(input, &snapshot).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Records the whole machine, controller shift registers included
+    pub fn capture(&mut self, cpu: &CPU, input: &InputPorts) {
+        self.push(&cpu.save_machine(input));
+    }
+
+    pub fn frames(&self) -> usize {
+        self.groups.iter().map(|group| group.deltas.len() + 1).sum()
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.frames() as f64 / FRAMES_PER_SECOND
+    }
+
+    /// Compressed bytes currently held
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.groups.clear();
+        self.size = 0;
+    }
+}
+
+/// Run-length encodes zero runs: each chunk is a varint count of zeros,
+/// a varint count of literal bytes, then the literal bytes themselves
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let zeros = data[pos..].iter().take_while(|byte| **byte == 0).count();
+        pos += zeros;
+        let literals = data[pos..].iter().take_while(|byte| **byte != 0).count();
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals);
+        out.extend_from_slice(&data[pos..pos + literals]);
+        pos += literals;
+    }
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let zeros = read_varint(data, &mut pos);
+        let literals = read_varint(data, &mut pos);
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&data[pos..pos + literals]);
+        pos += literals;
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compression_round_trip() {
+        let mut data = vec![0; 1000];
+        data[3] = 7;
+        data[500..520].copy_from_slice(&[0xAB; 20]);
+        data[999] = 1;
+
+        let compressed = compress(&data);
+        assert!(compressed.len() < 40);
+        assert_eq!(decompress(&compressed), data);
+    }
+
+    #[test]
+    fn test_step_back_returns_snapshots_newest_first() {
+        let mut rewind = RewindBuffer::with_keyframe_interval(usize::MAX, 3);
+        let snapshots: Vec<Vec<u8>> = (0..7u8).map(|frame| vec![frame, 0, 0, frame * 2]).collect();
+        for snapshot in &snapshots {
+            rewind.push(snapshot);
+        }
+
+        assert_eq!(rewind.frames(), 7);
+        for snapshot in snapshots.iter().rev() {
+            assert_eq!(rewind.step_back().as_ref(), Some(snapshot));
+        }
+        assert_eq!(rewind.step_back(), None);
+        assert_eq!(rewind.size(), 0);
+    }
+
+    #[test]
+    fn test_budget_evicts_oldest_history() {
+        let mut rewind = RewindBuffer::with_keyframe_interval(200, 4);
+        for frame in 0..100u8 {
+            rewind.push(&[frame; 16]);
+        }
+
+        assert!(rewind.size() <= 200);
+        assert!(rewind.frames() < 100);
+        assert_eq!(rewind.step_back(), Some(vec![99; 16]));
+    }
+
+    #[test]
+    fn test_rewind_restores_cpu_and_input() {
+        use crate::input::joypad::JoypadButton;
+        use crate::input::InputMode;
+
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xA9, 0x01, 0x00]);
+        let mut input = InputPorts::new(InputMode::Standard);
+        input.joypads[0].set_button_pressed(JoypadButton::A, true);
+        input.write(1);
+        input.write(0);
+        let mut rewind = RewindBuffer::new(1 << 20);
+
+        rewind.capture(&cpu, &input);
+        cpu.register_a.0 = 0x02;
+        input.read(InputPorts::PORT_1);
+        rewind.capture(&cpu, &input);
+        cpu.register_a.0 = 0x03;
+        input.read(InputPorts::PORT_1);
+
+        assert!(rewind.seconds() > 0.0);
+        assert_eq!(rewind.rewind(&mut cpu, &mut input), Ok(true));
+        assert_eq!(cpu.register_a.0, 0x02);
+        assert_eq!(rewind.rewind(&mut cpu, &mut input), Ok(true));
+        assert_eq!(cpu.register_a.0, 0x01);
+        // The shift register is back before the A button was read
+        assert_eq!(input.read(InputPorts::PORT_1), 1);
+        assert_eq!(rewind.rewind(&mut cpu, &mut input), Ok(false));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::cpu::variant::Variant;
+use std::fmt;
+
+/// Identifies a save state blob
+const MAGIC: [u8; 4] = *b"NESS";
+/// Bumped whenever the serialized layout of any component changes
+pub const VERSION: u16 = 6;
+/// Magic, version, ROM hash, body length and body checksum
+const HEADER_LEN: usize = 4 + 2 + 8 + 4 + 8;
+
+#[derive(Debug, PartialEq)]
+pub enum SavestateError {
+    InvalidHeader,
+    UnsupportedVersion(u16),
+    RomMismatch { expected: u64, found: u64 },
+    VariantMismatch { expected: Variant, found: Variant },
+    Corrupted,
+    UnexpectedEnd,
+    TrailingData,
+}
+
+impl fmt::Display for SavestateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SavestateError::InvalidHeader => write!(f, "not a save state"),
+            SavestateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} is not supported (expected {})",
+                version, VERSION
+            ),
+            SavestateError::RomMismatch { expected, found } => write!(
+                f,
+                "save state belongs to ROM {:016x}, but ROM {:016x} is loaded",
+                found, expected
+            ),
+            SavestateError::VariantMismatch { expected, found } => write!(
+                f,
+                "save state was made on a {:?}, but this CPU is a {:?}",
+                found, expected
+            ),
+            SavestateError::Corrupted => write!(f, "save state checksum does not match"),
+            SavestateError::UnexpectedEnd => write!(f, "save state is truncated"),
+            SavestateError::TrailingData => {
+                write!(f, "save state has data left over after loading")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SavestateError {}
+
+/// Implemented by every piece of the machine that holds state
+///
+/// `save` and `load` must visit the same fields in the same order.
+pub trait Savestate {
+    fn save(&self, state: &mut StateWriter);
+    fn load(&mut self, state: &mut StateReader) -> Result<(), SavestateError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SavestateError> {
+        if self.data.len() < len {
+            return Err(SavestateError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, SavestateError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, SavestateError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, SavestateError> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, SavestateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, SavestateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Checks that loading used up the whole body
+    pub fn finish(&self) -> Result<(), SavestateError> {
+        match self.is_empty() {
+            true => Ok(()),
+            false => Err(SavestateError::TrailingData),
+        }
+    }
+}
+
+/// 64-bit FNV-1a, used to tie save states to a ROM and to catch corruption
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Serialize `machine` behind a header naming the format version and ROM
+pub fn save_state<S: Savestate>(machine: &S, rom_hash: u64) -> Vec<u8> {
+    let mut body = StateWriter::new();
+    machine.save(&mut body);
+    seal(body, rom_hash)
+}
+
+/// Puts the header in front of a body written by one or more components
+pub fn seal(body: StateWriter, rom_hash: u64) -> Vec<u8> {
+    let body = body.into_bytes();
+
+    let mut state = StateWriter::new();
+    state.write_bytes(&MAGIC);
+    state.write_u16(VERSION);
+    state.write_u64(rom_hash);
+    state.write_u32(body.len() as u32);
+    state.write_u64(hash(&body));
+    state.write_bytes(&body);
+    state.into_bytes()
+}
+
+/// Restore `machine` from a blob produced by `save_state`
+///
+/// The header and checksum are validated before anything is loaded, so a
+/// state rejected for those leaves the machine untouched. A body longer
+/// than the machine reads is only noticed once it has loaded.
+pub fn load_state<S: Savestate>(
+    machine: &mut S,
+    rom_hash: u64,
+    data: &[u8],
+) -> Result<(), SavestateError> {
+    let mut body = open(rom_hash, data)?;
+    machine.load(&mut body)?;
+    body.finish()
+}
+
+/// Validates the header and checksum of a blob produced by `seal`,
+/// returning a reader over its body
+pub fn open(rom_hash: u64, data: &[u8]) -> Result<StateReader<'_>, SavestateError> {
+    if data.len() < HEADER_LEN {
+        return Err(SavestateError::InvalidHeader);
+    }
+
+    let mut header = StateReader::new(&data[..HEADER_LEN]);
+    if header.read_bytes(MAGIC.len())? != MAGIC {
+        return Err(SavestateError::InvalidHeader);
+    }
+    let version = header.read_u16()?;
+    if version != VERSION {
+        return Err(SavestateError::UnsupportedVersion(version));
+    }
+    let found = header.read_u64()?;
+    if found != rom_hash {
+        return Err(SavestateError::RomMismatch {
+            expected: rom_hash,
+            found,
+        });
+    }
+    let body_len = header.read_u32()? as usize;
+    let checksum = header.read_u64()?;
+
+    let body = &data[HEADER_LEN..];
+    if body.len() != body_len {
+        return Err(SavestateError::UnexpectedEnd);
+    }
+    if hash(body) != checksum {
+        return Err(SavestateError::Corrupted);
+    }
+
+    Ok(StateReader::new(body))
+}
@@ -0,0 +1,253 @@
+use crate::cartridge::PrgMap;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+pub mod ca65;
+pub mod fceux;
+pub mod mesen;
+
+/// Size of the iNES header in front of PRG ROM in a `.nes` file
+pub const INES_HEADER_LEN: usize = 16;
+/// PRG bank size FCEUX uses to number its `.nl` files
+pub const FCEUX_BANK_SIZE: usize = 0x4000;
+
+#[derive(Debug)]
+pub enum SymbolError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    UnknownFormat(String),
+}
+
+impl SymbolError {
+    fn parse(line: usize, message: impl Into<String>) -> Self {
+        SymbolError::Parse {
+            line: line + 1,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::Io(error) => write!(f, "{}", error),
+            SymbolError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            SymbolError::UnknownFormat(path) => {
+                write!(f, "{}: expected a .dbg, .nl or .mlb file", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+impl From<io::Error> for SymbolError {
+    fn from(error: io::Error) -> Self {
+        SymbolError::Io(error)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub comment: Option<String>,
+}
+
+/// Labels and comments for addresses, from symbol files or defined at runtime
+///
+/// Symbols in PRG ROM are keyed by ROM offset, so the same CPU address
+/// resolves to different labels as banks are switched, following
+/// `prg_map`. Everything else (RAM, registers) is keyed by CPU address.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    cpu: BTreeMap<u16, Symbol>,
+    prg: BTreeMap<usize, Symbol>,
+    pub prg_map: PrgMap,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpu.is_empty() && self.prg.is_empty()
+    }
+
+    /// Maps `range` to PRG ROM starting at `offset`; see `PrgMap::map`
+    pub fn map_prg(&mut self, range: RangeInclusive<u16>, offset: usize) {
+        self.prg_map.map(range, offset);
+    }
+
+    pub fn map_nrom(&mut self, prg_len: usize) {
+        self.prg_map = PrgMap::nrom(prg_len);
+    }
+
+    /// ROM offset that `addr` currently reaches, if it is mapped to PRG
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        self.prg_map.offset(addr)
+    }
+
+    /// Labels `addr` in whatever it is currently mapped to
+    pub fn define(&mut self, addr: u16, name: &str, comment: Option<&str>) {
+        let symbol = Symbol {
+            name: String::from(name),
+            comment: comment.map(String::from),
+        };
+        match self.prg_offset(addr) {
+            Some(offset) => self.prg.insert(offset, symbol),
+            None => self.cpu.insert(addr, symbol),
+        };
+    }
+
+    pub fn define_cpu(&mut self, addr: u16, symbol: Symbol) {
+        self.cpu.insert(addr, symbol);
+    }
+
+    pub fn define_prg(&mut self, offset: usize, symbol: Symbol) {
+        self.prg.insert(offset, symbol);
+    }
+
+    /// Removes the label at `addr`, returning whether there was one
+    pub fn remove(&mut self, addr: u16) -> bool {
+        let removed = match self.prg_offset(addr) {
+            Some(offset) => self.prg.remove(&offset),
+            None => None,
+        };
+        removed.or_else(|| self.cpu.remove(&addr)).is_some()
+    }
+
+    pub fn lookup(&self, addr: u16) -> Option<&Symbol> {
+        self.prg_offset(addr)
+            .and_then(|offset| self.prg.get(&offset))
+            .or_else(|| self.cpu.get(&addr))
+    }
+
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.lookup(addr)
+            .map(|symbol| symbol.name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    pub fn comment(&self, addr: u16) -> Option<&str> {
+        self.lookup(addr)?.comment.as_deref()
+    }
+
+    /// CPU address of the label `name`, for PRG labels only if their bank
+    /// is currently mapped
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        if let Some((addr, _)) = self.cpu.iter().find(|(_, symbol)| symbol.name == name) {
+            return Some(*addr);
+        }
+        let (offset, _) = self.prg.iter().find(|(_, symbol)| symbol.name == name)?;
+        self.prg_map.address_of(*offset)
+    }
+
+    /// Every label with the CPU address it currently resolves to
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Symbol)> {
+        let prg = self
+            .prg_map
+            .iter()
+            .filter_map(|(addr, offset)| Some((addr, self.prg.get(&offset)?)));
+        self.cpu
+            .iter()
+            .map(|(addr, symbol)| (*addr, symbol))
+            .chain(prg)
+    }
+
+    /// `addr` as its label, or as hex `digits` wide when it has none
+    pub fn format_address(&self, addr: u16, digits: usize) -> String {
+        match self.name(addr) {
+            Some(name) => String::from(name),
+            None => format!("${:0width$X}", addr, width = digits),
+        }
+    }
+
+    /// Loads a symbol file, picking the format from its name:
+    /// ca65 `.dbg`, Mesen `.mlb`, or FCEUX `.nl` where `game.nes.ram.nl`
+    /// holds RAM labels and `game.nes.N.nl` those of 16 KiB bank N
+    pub fn load_file(&mut self, path: &Path) -> Result<(), SymbolError> {
+        let text = fs::read_to_string(path)?;
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let unknown = || SymbolError::UnknownFormat(path.display().to_string());
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("dbg") => ca65::parse(&text, self),
+            Some("mlb") => mesen::parse(&text, self),
+            Some("nl") => {
+                let bank = file_name.trim_end_matches(".nl").rsplit('.').next();
+                match bank {
+                    Some("ram") => fceux::parse(&text, None, self),
+                    Some(bank) => match usize::from_str_radix(bank, 16) {
+                        Ok(bank) => fceux::parse(&text, Some(bank), self),
+                        Err(_) => Err(unknown()),
+                    },
+                    None => Err(unknown()),
+                }
+            }
+            _ => Err(unknown()),
+        }
+    }
+}
+
+/// Parses `$C000`, `0xC000` or `C000` as hex
+pub(crate) fn parse_hex(text: &str) -> Option<usize> {
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn label(name: &str) -> Symbol {
+        Symbol {
+            name: String::from(name),
+            comment: None,
+        }
+    }
+
+    #[test]
+    fn test_prg_labels_follow_bank_mapping() {
+        let mut symbols = SymbolTable::new();
+        symbols.define_prg(0x0000, label("bank0_entry"));
+        symbols.define_prg(0x4000, label("bank1_entry"));
+        symbols.define_cpu(0x0300, label("buffer"));
+        symbols.map_prg(0x8000..=0xBFFF, 0x0000);
+
+        assert_eq!(symbols.name(0x8000), Some("bank0_entry"));
+        assert_eq!(symbols.name(0x0300), Some("buffer"));
+        assert_eq!(symbols.address_of("bank1_entry"), None);
+
+        symbols.map_prg(0x8000..=0xBFFF, 0x4000);
+        assert_eq!(symbols.name(0x8000), Some("bank1_entry"));
+        assert_eq!(symbols.address_of("bank1_entry"), Some(0x8000));
+        assert_eq!(symbols.format_address(0x8001, 4), "$8001");
+    }
+
+    #[test]
+    fn test_runtime_labels() {
+        let mut symbols = SymbolTable::new();
+        symbols.map_nrom(0x4000);
+        symbols.define(0xC010, "nmi", Some("vblank handler"));
+        symbols.define(0x0010, "temp", None);
+
+        // The 16 KiB bank is mirrored, so both halves see the label
+        assert_eq!(symbols.name(0x8010), Some("nmi"));
+        assert_eq!(symbols.comment(0xC010), Some("vblank handler"));
+        assert_eq!(symbols.address_of("temp"), Some(0x0010));
+
+        assert!(symbols.remove(0x8010));
+        assert_eq!(symbols.name(0xC010), None);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::symbols::{Symbol, SymbolError, SymbolTable, INES_HEADER_LEN};
+use std::collections::HashMap;
+
+/// A `seg` record: where a segment was linked and where it landed in the
+/// output file, if it was written to one
+struct Segment {
+    start: usize,
+    output_offset: Option<usize>,
+}
+
+/// Reads the labels and constants in an ld65 `--dbgfile`
+///
+/// Symbols in segments written to the ROM image become PRG symbols at their
+/// ROM offset (the output offset less the iNES header); symbols in RAM
+/// segments and equates become CPU symbols. Cheap local labels (`@name`)
+/// are skipped.
+pub fn parse(text: &str, symbols: &mut SymbolTable) -> Result<(), SymbolError> {
+    let mut segments = HashMap::new();
+    let mut records = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let (kind, fields) = match line.split_once(char::is_whitespace) {
+            Some((kind, fields)) => (kind, parse_fields(fields)),
+            None => continue,
+        };
+        match kind {
+            "seg" => {
+                let id = required(&fields, "id", number)?;
+                let start = number_field(&fields, "start", number)?;
+                let output_offset = match fields.get("ooffs") {
+                    Some(_) => Some(number_field(&fields, "ooffs", number)?),
+                    None => None,
+                };
+                segments.insert(
+                    String::from(id),
+                    Segment {
+                        start,
+                        output_offset,
+                    },
+                );
+            }
+            "sym" => records.push((number, fields)),
+            _ => {}
+        }
+    }
+
+    // Symbols may be listed before the segments they refer to
+    for (number, fields) in records {
+        let name = required(&fields, "name", number)?;
+        if name.starts_with('@') || !fields.contains_key("val") {
+            continue;
+        }
+        let value = number_field(&fields, "val", number)?;
+        let symbol = Symbol {
+            name: String::from(name),
+            comment: None,
+        };
+
+        let segment = fields.get("seg").and_then(|id| segments.get(*id));
+        let rom_offset = segment.and_then(|segment| {
+            let file_offset = segment.output_offset? + value.checked_sub(segment.start)?;
+            file_offset.checked_sub(INES_HEADER_LEN)
+        });
+        match rom_offset {
+            Some(offset) if fields.get("type") == Some(&"lab") => {
+                symbols.define_prg(offset, symbol)
+            }
+            _ => match u16::try_from(value) {
+                Ok(addr) => symbols.define_cpu(addr, symbol),
+                Err(_) => return Err(SymbolError::parse(number, "value out of range")),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Splits `id=0,name="reset",val=0x8000` into its fields
+fn parse_fields(text: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    let mut rest = text.trim();
+
+    while let Some((key, tail)) = rest.split_once('=') {
+        let (value, tail) = match tail.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, tail)) => (value, tail),
+                None => (quoted, ""),
+            },
+            None => tail
+                .split_once(',')
+                .map_or((tail, ""), |(value, tail)| (value, tail)),
+        };
+        fields.insert(key.trim(), value);
+        rest = tail.trim_start_matches(',');
+    }
+    fields
+}
+
+fn required<'a>(
+    fields: &HashMap<&str, &'a str>,
+    key: &str,
+    line: usize,
+) -> Result<&'a str, SymbolError> {
+    fields
+        .get(key)
+        .copied()
+        .ok_or_else(|| SymbolError::parse(line, format!("missing `{}`", key)))
+}
+
+fn number_field(
+    fields: &HashMap<&str, &str>,
+    key: &str,
+    line: usize,
+) -> Result<usize, SymbolError> {
+    let text = required(fields, key, line)?;
+    let value = match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    value.map_err(|_| SymbolError::parse(line, format!("bad number `{}`", text)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DBG: &str = r#"version	major=2,minor=0
+file	id=0,name="game.s",size=512,mtime=0x5F000000,mod=0
+seg	id=0,name="ZEROPAGE",start=0x000000,size=0x0010,addrsize=zeropage,type=rw
+seg	id=1,name="CODE",start=0x008000,size=0x0100,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+seg	id=2,name="BANK1",start=0x008000,size=0x0100,addrsize=absolute,type=ro,oname="game.nes",ooffs=16400
+sym	id=0,name="frame",addrsize=zeropage,size=1,scope=0,def=1,ref=4,val=0x2,seg=0,type=lab
+sym	id=1,name="reset",addrsize=absolute,scope=0,def=2,val=0x8000,seg=1,type=lab
+sym	id=2,name="@loop",addrsize=absolute,scope=0,def=3,val=0x8004,seg=1,type=lab
+sym	id=3,name="PPUCTRL",addrsize=absolute,scope=0,def=5,val=0x2000,type=equ
+sym	id=4,name="bank1_init",addrsize=absolute,scope=0,def=6,val=0x8010,seg=2,type=lab
+"#;
+
+    #[test]
+    fn test_parse_debug_file() {
+        let mut symbols = SymbolTable::new();
+        parse(DBG, &mut symbols).unwrap();
+        symbols.map_prg(0x8000..=0xBFFF, 0);
+
+        assert_eq!(symbols.name(0x0002), Some("frame"));
+        assert_eq!(symbols.name(0x2000), Some("PPUCTRL"));
+        assert_eq!(symbols.name(0x8000), Some("reset"));
+        assert_eq!(symbols.name(0x8004), None);
+        assert_eq!(symbols.address_of("bank1_init"), None);
+
+        symbols.map_prg(0x8000..=0xBFFF, 0x4000);
+        assert_eq!(symbols.name(0x8010), Some("bank1_init"));
+    }
+}
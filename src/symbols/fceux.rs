@@ -0,0 +1,84 @@
+use crate::symbols::{parse_hex, Symbol, SymbolError, SymbolTable, FCEUX_BANK_SIZE};
+
+/// Reads an FCEUX name list: one `$ADDR#Label#Comment` per line, where an
+/// address of `$ADDR/SIZE` labels an array
+///
+/// `bank` is the 16 KiB PRG bank the file describes, or `None` for the RAM
+/// file. Array elements after the first are labelled `name+N`.
+pub fn parse(
+    text: &str,
+    bank: Option<usize>,
+    symbols: &mut SymbolTable,
+) -> Result<(), SymbolError> {
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if !line.starts_with('$') {
+            continue;
+        }
+
+        let mut parts = line.splitn(3, '#');
+        let location = parts.next().unwrap_or("");
+        let name = parts.next().unwrap_or("");
+        let comment = parts.next().filter(|comment| !comment.is_empty());
+
+        let (addr, size) = match location.split_once('/') {
+            Some((addr, size)) => (addr, parse_hex(size)),
+            None => (location, Some(1)),
+        };
+        let (addr, size) = match (parse_hex(addr), size) {
+            (Some(addr), Some(size)) if addr <= 0xFFFF => (addr as u16, size.max(1)),
+            _ => {
+                return Err(SymbolError::parse(
+                    number,
+                    format!("bad address `{}`", location),
+                ))
+            }
+        };
+
+        for index in 0..size {
+            let symbol = Symbol {
+                name: match index {
+                    0 => String::from(name),
+                    _ => format!("{}+{}", name, index),
+                },
+                comment: comment.filter(|_| index == 0).map(String::from),
+            };
+            let addr = addr.wrapping_add(index as u16);
+            match bank {
+                Some(bank) => {
+                    let offset = bank * FCEUX_BANK_SIZE + (addr as usize % FCEUX_BANK_SIZE);
+                    symbols.define_prg(offset, symbol)
+                }
+                None => symbols.define_cpu(addr, symbol),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_name_lists() {
+        let mut symbols = SymbolTable::new();
+        parse(
+            "$0000#temp#\n$0300/3#oam_buffer#sprite copy\n",
+            None,
+            &mut symbols,
+        )
+        .unwrap();
+        parse("$C000#Reset#power on entry\n", Some(1), &mut symbols).unwrap();
+        symbols.map_prg(0xC000..=0xFFFF, 0x4000);
+
+        assert_eq!(symbols.name(0x0000), Some("temp"));
+        assert_eq!(symbols.comment(0x0000), None);
+        assert_eq!(symbols.name(0x0302), Some("oam_buffer+2"));
+        assert_eq!(symbols.comment(0x0300), Some("sprite copy"));
+        assert_eq!(symbols.name(0xC000), Some("Reset"));
+        assert_eq!(symbols.comment(0xC000), Some("power on entry"));
+
+        assert!(parse("$XYZ#bad#\n", None, &mut symbols).is_err());
+    }
+}
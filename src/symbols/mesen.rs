@@ -0,0 +1,98 @@
+use crate::symbols::{parse_hex, Symbol, SymbolError, SymbolTable};
+
+/// Where cartridge work/save RAM appears in the CPU address space
+const WORK_RAM_START: usize = 0x6000;
+
+/// Reads a Mesen label file: `TYPE:ADDR[-END]:label[:comment]` per line
+///
+/// Both the single-letter memory types of Mesen (`P`, `R`, `S`, `W`, `G`)
+/// and the names Mesen 2 writes (`NesPrgRom`, `NesInternalRam`, ...) are
+/// understood. Labels for CHR and other PPU memory are skipped.
+pub fn parse(text: &str, symbols: &mut SymbolTable) -> Result<(), SymbolError> {
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut parts = line.splitn(4, ':');
+        let (kind, location, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(kind), Some(location), Some(name)) => (kind, location, name),
+            _ => return Err(SymbolError::parse(number, "expected TYPE:ADDR:LABEL")),
+        };
+        let comment = parts
+            .next()
+            .filter(|comment| !comment.is_empty())
+            .map(|comment| comment.replace("\\n", "\n"));
+
+        let (start, end) = match location.split_once('-') {
+            Some((start, end)) => (parse_hex(start), parse_hex(end)),
+            None => (parse_hex(location), parse_hex(location)),
+        };
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if start <= end => (start, end),
+            _ => {
+                return Err(SymbolError::parse(
+                    number,
+                    format!("bad address `{}`", location),
+                ))
+            }
+        };
+
+        for offset in start..=end {
+            let symbol = Symbol {
+                name: match offset - start {
+                    0 => String::from(name),
+                    index => format!("{}+{}", name, index),
+                },
+                comment: comment.clone().filter(|_| offset == start),
+            };
+            let cpu_addr = match kind {
+                "P" | "NesPrgRom" => {
+                    symbols.define_prg(offset, symbol);
+                    continue;
+                }
+                "R" | "NesInternalRam" => offset & 0x07FF,
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => WORK_RAM_START + offset,
+                "G" | "NesMemory" => offset,
+                _ => continue,
+            };
+            match u16::try_from(cpu_addr) {
+                Ok(addr) => symbols.define_cpu(addr, symbol),
+                Err(_) => return Err(SymbolError::parse(number, "address out of range")),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_label_file() {
+        let text = "\
+P:0000:reset:entry point\\nruns on power on
+R:0010-0011:pointer
+G:2000:PPUCTRL
+NesWorkRam:0000:save_slot:
+C:0000:tiles
+";
+        let mut symbols = SymbolTable::new();
+        parse(text, &mut symbols).unwrap();
+        symbols.map_nrom(0x8000);
+
+        assert_eq!(symbols.name(0x8000), Some("reset"));
+        assert_eq!(
+            symbols.comment(0x8000),
+            Some("entry point\nruns on power on")
+        );
+        assert_eq!(symbols.name(0x0011), Some("pointer+1"));
+        assert_eq!(symbols.name(0x2000), Some("PPUCTRL"));
+        assert_eq!(symbols.name(0x6000), Some("save_slot"));
+        assert_eq!(symbols.name(0x0000), None);
+
+        assert!(parse("P:0000\n", &mut symbols).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use crate::cartridge::Rom;
+use crate::cpu::{Step, Stop, CPU};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Status byte written by the test ROM
+const STATUS: u16 = 0x6000;
+/// $DE $B0 $61 at $6001-$6003 marks the status and text as valid
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+/// Zero-terminated message text
+const TEXT: u16 = 0x6004;
+
+const STATUS_RUNNING: u8 = 0x80;
+/// The ROM wants the reset button pressed, no sooner than 100 ms from now
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+/// NTSC CPU clock
+pub const CPU_HZ: u64 = 1_789_773;
+pub const RESET_DELAY: u64 = CPU_HZ / 10;
+/// Enough for the slowest of the blargg ROMs, which take about 20 seconds
+pub const DEFAULT_CYCLE_BUDGET: u64 = 60 * CPU_HZ;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    /// The ROM finished with a non-zero result code
+    Failed(u8),
+    /// The cycle budget ran out before the ROM reported a result
+    TimedOut,
+    /// Execution hit BRK, or stopped on WAI or a rejected illegal opcode
+    Halted {
+        pc: u16,
+    },
+    /// KIL locked up the CPU
+    Jammed {
+        pc: u16,
+    },
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Failed(code) => write!(f, "failed (code {})", code),
+            Outcome::TimedOut => write!(f, "timed out"),
+            Outcome::Halted { pc } => write!(f, "halted at ${:04X}", pc),
+            Outcome::Jammed { pc } => write!(f, "jammed at ${:04X}", pc),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestResult {
+    pub outcome: Outcome,
+    /// Text the ROM left at $6004, whatever the outcome
+    pub message: String,
+    pub cycles: u64,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+}
+
+/// Runs test ROMs that report through the blargg $6000 protocol
+///
+/// The ROM writes $80 to $6000 while it runs and its result code when it
+/// is done (0 for a pass), with the signature $DE $B0 $61 at $6001 and a
+/// message from $6004 on. $81 asks for a reset, which is done after
+/// `RESET_DELAY` cycles.
+///
+/// Only the CPU and NROM (mapper 0) cartridges are emulated. ROMs on other
+/// mappers, such as the MMC1 builds of instr_test-v5, fail to load, and
+/// suites that check the PPU or APU (ppu_vbl_nmi, apu_test) can't pass, so
+/// this is for CPU tests on NROM rather than tracking those suites.
+#[derive(Clone, Debug)]
+pub struct TestRomHarness {
+    pub cycle_budget: u64,
+}
+
+impl Default for TestRomHarness {
+    fn default() -> Self {
+        TestRomHarness {
+            cycle_budget: DEFAULT_CYCLE_BUDGET,
+        }
+    }
+}
+
+impl TestRomHarness {
+    pub fn new() -> Self {
+        TestRomHarness::default()
+    }
+
+    pub fn run_file(&self, path: &Path) -> Result<TestResult, String> {
+        let raw = fs::read(path).map_err(|error| error.to_string())?;
+        self.run_rom(&Rom::new(&raw)?)
+    }
+
+    pub fn run_rom(&self, rom: &Rom) -> Result<TestResult, String> {
+        let mut cpu = CPU::new();
+        cpu.load_rom(rom)?;
+        cpu.power_on();
+        Ok(self.run(&mut cpu))
+    }
+
+    /// Runs an already loaded and powered on CPU until the ROM reports a result
+    /// or the cycle budget runs out
+    pub fn run(&self, cpu: &mut CPU) -> TestResult {
+        let deadline = cpu.cycles.saturating_add(self.cycle_budget);
+        let mut running = false;
+        let mut reset_at = None;
+
+        let outcome = loop {
+            if has_signature(cpu) {
+                match cpu.memory.read(STATUS) {
+                    STATUS_RUNNING => running = true,
+                    STATUS_NEEDS_RESET if running => {
+                        running = false;
+                        reset_at = Some(cpu.cycles + RESET_DELAY);
+                    }
+                    STATUS_NEEDS_RESET => {}
+                    0 if running => break Outcome::Passed,
+                    code if running => break Outcome::Failed(code),
+                    _ => {}
+                }
+            }
+            if let Some(cycle) = reset_at {
+                if cpu.cycles >= cycle {
+                    reset_at = None;
+                    cpu.reset();
+                }
+            }
+            if cpu.cycles >= deadline {
+                break Outcome::TimedOut;
+            }
+
+            let pc = cpu.program_counter;
+            match cpu.step() {
+                Step::Instruction | Step::Interrupt(_) => {}
+                Step::Stopped(Stop::Jammed) => break Outcome::Jammed { pc },
+                Step::Stopped(_) => break Outcome::Halted { pc },
+            }
+        };
+
+        TestResult {
+            outcome,
+            message: read_message(cpu),
+            cycles: cpu.cycles,
+        }
+    }
+
+    /// Runs every `.nes` file under `dir`, recursively, in path order
+    pub fn run_directory(
+        &self,
+        dir: &Path,
+    ) -> io::Result<Vec<(PathBuf, Result<TestResult, String>)>> {
+        let mut paths = Vec::new();
+        find_roms(dir, &mut paths)?;
+        paths.sort();
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let result = self.run_file(&path);
+                (path, result)
+            })
+            .collect())
+    }
+}
+
+fn find_roms(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, paths)?;
+        } else if path.extension().is_some_and(|extension| extension == "nes") {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn has_signature(cpu: &CPU) -> bool {
+    (0..3).all(|i| cpu.memory.read(SIGNATURE_ADDR + i) == SIGNATURE[i as usize])
+}
+
+fn read_message(cpu: &CPU) -> String {
+    if !has_signature(cpu) {
+        return String::new();
+    }
+    let bytes: Vec<u8> = (TEXT..=0xFFFF)
+        .map(|addr| cpu.memory.read(addr))
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+/// One line per ROM with its outcome, cycle count and the last line of
+/// its message, then a total. Paths are shown relative to `root`.
+pub fn report(root: &Path, results: &[(PathBuf, Result<TestResult, String>)]) -> String {
+    let mut lines = Vec::new();
+    let mut passed = 0;
+    for (path, result) in results {
+        let name = path.strip_prefix(root).unwrap_or(path).display();
+        lines.push(match result {
+            Ok(result) => {
+                if result.passed() {
+                    passed += 1;
+                }
+                let summary = result.message.lines().last().unwrap_or("");
+                format!(
+                    "{}\t{}\t{}\t{}",
+                    name, result.outcome, result.cycles, summary
+                )
+            }
+            Err(error) => format!("{}\terror\t0\t{}", name, error),
+        });
+    }
+    lines.push(format!("{}/{} passed", passed, results.len()));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::cpu::assembler::assemble;
+
+    /// An NROM image built from `source`, with the reset vector at `reset`
+    fn rom(source: &str) -> Rom {
+        let source = format!(
+            "{}
+            report: LDA #$DE
+                    STA $6001
+                    LDA #$B0
+                    STA $6002
+                    LDA #$61
+                    STA $6003
+                    RTS
+            .org $FFFC
+                    .word reset",
+            source
+        );
+        let mut prg_rom = assemble(&source).unwrap().bytes();
+        prg_rom.resize(0x8000, 0);
+        Rom {
+            prg_rom,
+            chr_rom: vec![0; 0x2000],
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+        }
+    }
+
+    #[test]
+    fn test_pass_and_fail() {
+        let source = "
+            reset:  LDA #$80
+                    STA $6000
+                    JSR report
+                    LDA #'o'
+                    STA $6004
+                    LDA #'k'
+                    STA $6005
+                    LDA #RESULT
+                    STA $6000
+            done:   JMP done";
+
+        let result = TestRomHarness::new()
+            .run_rom(&rom(&format!("RESULT = 0\n{}", source)))
+            .unwrap();
+        assert_eq!(result.outcome, Outcome::Passed);
+        assert_eq!(result.message, "ok");
+
+        let result = TestRomHarness::new()
+            .run_rom(&rom(&format!("RESULT = 3\n{}", source)))
+            .unwrap();
+        assert_eq!(result.outcome, Outcome::Failed(3));
+    }
+
+    #[test]
+    fn test_reset_request_and_timeout() {
+        // Asks for a reset on the first run and never finishes on the second
+        let source = "
+            reset:  JSR report
+                    LDA $10
+                    BNE again
+                    INC $10
+                    LDA #$80
+                    STA $6000
+                    LDA #$81
+                    STA $6000
+            wait:   JMP wait
+            again:  LDA #$80
+                    STA $6000
+            spin:   JMP spin";
+
+        let run = |cycle_budget| {
+            let mut cpu = CPU::new();
+            cpu.load_rom(&rom(source)).unwrap();
+            cpu.power_on();
+            let result = TestRomHarness { cycle_budget }.run(&mut cpu);
+            (result, cpu.memory.read(STATUS))
+        };
+
+        let (result, status) = run(RESET_DELAY / 2);
+        assert_eq!(result.outcome, Outcome::TimedOut);
+        assert_eq!(status, STATUS_NEEDS_RESET);
+
+        let (result, status) = run(RESET_DELAY * 2);
+        assert_eq!(result.outcome, Outcome::TimedOut);
+        assert_eq!(status, STATUS_RUNNING);
+        assert_eq!(result.message, "");
+    }
+
+    #[test]
+    fn test_only_nrom_loads() {
+        let mut mmc1 = rom("reset: JMP reset");
+        mmc1.mapper = 1;
+        assert_eq!(
+            TestRomHarness::new().run_rom(&mmc1),
+            Err(String::from("Mapper 1 is not supported"))
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let results = vec![
+            (
+                PathBuf::from("roms/cpu/01-basics.nes"),
+                Ok(TestResult {
+                    outcome: Outcome::Passed,
+                    message: String::from("01-basics\n\nPassed"),
+                    cycles: 1234,
+                }),
+            ),
+            (
+                PathBuf::from("roms/ppu.nes"),
+                Err(String::from("Mapper 4 is not supported")),
+            ),
+        ];
+        assert_eq!(
+            report(Path::new("roms"), &results),
+            "cpu/01-basics.nes\tpassed\t1234\tPassed\n\
+             ppu.nes\terror\t0\tMapper 4 is not supported\n\
+             1/2 passed"
+        );
+    }
+}
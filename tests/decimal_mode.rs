@@ -0,0 +1,260 @@
+//! Bruce Clark's decimal mode test, from "Decimal Mode" on 6502.org
+//!
+//! The program tries ADC and SBC in decimal mode on every pair of operands
+//! with both carry values, predicts the results using binary arithmetic
+//! and compares accumulator, N, V, Z and C. ERROR ends up 0 when
+//! everything matched, otherwise N1, N2 and Y hold the failing case.
+//!
+//! APREDICT and SPREDICT name the routines that predict the flags of the
+//! CPU under test, NMOS or 65C02.
+
+use nes::cpu::assembler::assemble;
+use nes::cpu::standalone::{run_until_trap, Halt};
+use nes::cpu::variant::Variant;
+use nes::cpu::CPU;
+
+const DECIMAL_TEST: &str = "
+ERROR   = $00
+N1      = $01
+N2      = $02
+HA      = $03       ; binary accumulator result
+HNVZC   = $04       ; binary flags result
+N1L     = $05
+N1H     = $06
+N2L     = $07
+N2H     = $08       ; two bytes
+DA      = $0A       ; decimal accumulator result
+DNVZC   = $0B       ; decimal flags result
+AR      = $0C       ; predicted accumulator result
+NF      = $0D       ; predicted flags
+VF      = $0E
+ZF      = $0F
+CF      = $10
+
+        .org $0200
+start:  JSR TEST
+done:   JMP done
+
+TEST:   LDY #1          ; loops through both carry values
+        STY ERROR       ; 1 until the test passes
+        LDA #0
+        STA N1
+        STA N2
+LOOP1:  LDA N2
+        AND #$0F
+        STA N2L
+        LDA N2
+        AND #$F0
+        STA N2H
+        ORA #$0F
+        STA N2H+1
+LOOP2:  LDA N1
+        AND #$0F
+        STA N1L
+        LDA N1
+        AND #$F0
+        STA N1H
+        JSR ADD
+        JSR APREDICT
+        JSR COMPARE
+        BNE DONE
+        JSR SUB
+        JSR SPREDICT
+        JSR COMPARE
+        BNE DONE
+        INC N1
+        BNE LOOP2
+        INC N2
+        BNE LOOP1
+        DEY
+        BPL LOOP1
+        LDA #0
+        STA ERROR
+DONE:   RTS
+
+; Actual and predicted results of N1 + N2
+ADD:    SED
+        CPY #1          ; carry set if Y = 1
+        LDA N1
+        ADC N2
+        STA DA
+        PHP
+        PLA
+        STA DNVZC
+        CLD
+        CPY #1
+        LDA N1
+        ADC N2
+        STA HA
+        PHP
+        PLA
+        STA HNVZC
+        CPY #1
+        LDA N1L
+        ADC N2L
+        CMP #$0A
+        LDX #0
+        BCC A1
+        INX
+        ADC #5          ; add 6, carry is set
+        AND #$0F
+        SEC
+A1:     ORA N1H
+        ADC N2H,X       ; N2 & $F0, or that + $10 on a low digit carry
+        PHP
+        BCS A2
+        CMP #$A0
+        BCC A3
+A2:     ADC #$5F        ; add $60, carry is set
+        SEC
+A3:     STA AR
+        PHP
+        PLA
+        STA CF
+        PLA
+        STA VF          ; all of P from before the correction
+        RTS
+
+; Actual results of N1 - N2
+SUB:    SED
+        CPY #1
+        LDA N1
+        SBC N2
+        STA DA
+        PHP
+        PLA
+        STA DNVZC
+        CLD
+        CPY #1
+        LDA N1
+        SBC N2
+        STA HA
+        PHP
+        PLA
+        STA HNVZC
+        RTS
+
+; Predicted accumulator of N1 - N2
+SUB1:   CPY #1
+        LDA N1L
+        SBC N2L
+        LDX #0
+        BCS S11
+        INX
+        SBC #5          ; subtract 6, carry is clear
+        AND #$0F
+        CLC
+S11:    ORA N1H
+        SBC N2H,X
+        BCS S12
+        SBC #$5F        ; subtract $60, carry is clear
+S12:    STA AR
+        RTS
+
+COMPARE: LDA DA
+        CMP AR
+        BNE C1
+        LDA DNVZC
+        EOR NF
+        AND #$80
+        BNE C1
+        LDA DNVZC
+        EOR VF
+        AND #$40
+        BNE C1
+        LDA DNVZC
+        EOR ZF
+        AND #2
+        BNE C1
+        LDA DNVZC
+        EOR CF
+        AND #1
+C1:     RTS
+
+; NMOS 6502 flag predictions
+A6502:  LDA VF
+        STA NF
+        LDA HNVZC
+        STA ZF
+        RTS
+
+S6502:  JSR SUB1
+        LDA HNVZC
+        STA NF
+        STA VF
+        STA ZF
+        STA CF
+        RTS
+
+; Predicted 65C02 accumulator of N1 - N2
+SUB2:   CPY #1
+        LDA N1L
+        SBC N2L
+        LDX #0
+        BCS S21
+        INX
+        AND #$0F
+        CLC
+S21:    ORA N1H
+        SBC N2H,X
+        BCS S22
+        SBC #$5F
+S22:    CPX #0
+        BEQ S23
+        SBC #6
+S23:    STA AR
+        RTS
+
+; 65C02 flag predictions: N and Z follow the accumulator
+A65C02: LDA AR
+        PHP
+        PLA
+        STA NF
+        STA ZF
+        RTS
+
+S65C02: JSR SUB2
+        LDA AR
+        PHP
+        PLA
+        STA NF
+        STA ZF
+        LDA HNVZC
+        STA VF
+        STA CF
+        RTS
+";
+
+fn run_decimal_test(variant: Variant, add_predictions: &str, sub_predictions: &str) {
+    let source = format!(
+        "{}\nAPREDICT = {}\nSPREDICT = {}",
+        DECIMAL_TEST, add_predictions, sub_predictions
+    );
+    let assembly = assemble(&source).unwrap();
+    let mut cpu = CPU::new();
+    cpu.variant = variant;
+    cpu.load_flat(&assembly.bytes(), 0x0200, assembly.labels["start"]);
+
+    let halt = run_until_trap(&mut cpu, 100_000_000);
+    assert_eq!(halt, Halt::Trap(assembly.labels["done"]));
+    assert_eq!(
+        cpu.memory.read(0x00),
+        0,
+        "failed on N1={:02X} N2={:02X} carry={}: got {:02X}, expected {:02X}",
+        cpu.memory.read(0x01),
+        cpu.memory.read(0x02),
+        cpu.register_y.0,
+        cpu.memory.read(0x0A),
+        cpu.memory.read(0x0C),
+    );
+}
+
+#[test]
+fn test_bruce_clark_decimal_mode() {
+    run_decimal_test(Variant::Nmos6502, "A6502", "S6502");
+}
+
+#[test]
+fn test_bruce_clark_decimal_mode_65c02() {
+    run_decimal_test(Variant::Wdc65C02, "A65C02", "S65C02");
+}
@@ -0,0 +1,57 @@
+//! Klaus Dormann's 6502 functional test, run on the bare CPU
+//!
+//! The binary isn't distributed with this crate. Build or download
+//! `6502_functional_test.bin` from https://github.com/Klaus2m5/6502_65C02_functional_tests
+//! and put it in `tests/roms`, or point `$KLAUS_FUNCTIONAL_TEST` at it, then
+//! run with `cargo test -- --ignored`. The image covers all 64 KiB and
+//! starts at $0400. `$KLAUS_SUCCESS_ADDR` overrides the address of the
+//! success trap, which is $3469 in the prebuilt binary and can be found in
+//! the listing of a custom build.
+
+use nes::cpu::standalone::{run_until_trap, Halt};
+use nes::cpu::CPU;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const ORIGIN: u16 = 0x0000;
+const START: u16 = 0x0400;
+const SUCCESS: u16 = 0x3469;
+/// The full test takes about 30 million instructions
+const INSTRUCTION_LIMIT: u64 = 100_000_000;
+
+#[test]
+#[ignore = "needs 6502_functional_test.bin in tests/roms or $KLAUS_FUNCTIONAL_TEST"]
+fn test_klaus_functional() {
+    let path = match env::var_os("KLAUS_FUNCTIONAL_TEST") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms/6502_functional_test.bin")
+        }
+    };
+    let image = match fs::read(&path) {
+        Ok(image) => image,
+        Err(err) => panic!("can't read {}: {}", path.display(), err),
+    };
+    let success = match env::var("KLAUS_SUCCESS_ADDR") {
+        Ok(addr) => u16::from_str_radix(addr.trim_start_matches('$'), 16).unwrap(),
+        Err(_) => SUCCESS,
+    };
+
+    let mut cpu = CPU::new();
+    cpu.load_flat(&image, ORIGIN, START);
+    match run_until_trap(&mut cpu, INSTRUCTION_LIMIT) {
+        Halt::Trap(addr) if addr == success => {}
+        halt => panic!(
+            "{:?} after {} cycles; test number ${:02X} at $0200, A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            halt,
+            cpu.cycles,
+            cpu.memory.read(0x0200),
+            cpu.register_a.0,
+            cpu.register_x.0,
+            cpu.register_y.0,
+            cpu.status.0,
+            cpu.register_s.0,
+        ),
+    }
+}
@@ -0,0 +1,43 @@
+//! Runs a directory of blargg-style test ROMs
+//!
+//! ROMs are looked up in `$NES_TEST_ROMS`, or `tests/roms` by default. They
+//! aren't distributed with this crate, so the test is ignored unless asked
+//! for with `cargo test -- --ignored`, and fails if the directory is missing.
+//! The report is printed (use `--nocapture` to see it) and, if
+//! `$NES_TEST_ROMS_REPORT` names a file, written there too. ROMs that don't
+//! pass only fail the test when `$NES_TEST_ROMS_STRICT` is set.
+//!
+//! Only NROM cartridges load and there is no PPU or APU, so this covers CPU
+//! tests on mapper 0. instr_test-v5 (MMC1), ppu_vbl_nmi and apu_test show
+//! up as errors or failures.
+
+use nes::test_rom::{report, TestRomHarness};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+#[ignore = "needs test ROMs in tests/roms or $NES_TEST_ROMS"]
+fn test_roms() {
+    let dir = match env::var_os("NES_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"),
+    };
+    assert!(dir.is_dir(), "test ROMs not found: {}", dir.display());
+
+    let results = TestRomHarness::new().run_directory(&dir).unwrap();
+    let report = report(&dir, &results);
+    println!("{}", report);
+    if let Some(path) = env::var_os("NES_TEST_ROMS_REPORT") {
+        fs::write(&path, format!("{}\n", report)).unwrap();
+    }
+
+    if env::var_os("NES_TEST_ROMS_STRICT").is_some() {
+        let failures: Vec<_> = results
+            .iter()
+            .filter(|(_, result)| !result.as_ref().is_ok_and(|result| result.passed()))
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        assert!(failures.is_empty(), "failing ROMs: {:?}", failures);
+    }
+}